@@ -0,0 +1,30 @@
+macro_rules! here {
+    () => {
+        (file!(), line!(), column!())
+    };
+}
+
+mod a {
+    pub const PATH: &str = module_path!();
+    pub const NAME: &str = concat!("a", 1, '-', -2.5, true, stringify!(b::c));
+}
+
+fn f() {
+    let here = here!();
+    let unix = cfg!(unix);
+    let both = cfg!(all(unix, feature = "std"),);
+    let name = concat!(file!(), ":", line!());
+}
+
+const BYTES: &str = concat!("a", b"b");
+const ARGS: u32 = line!(1);
+const EMPTY: bool = cfg!();
+const CALL: &str = concat!(f());
+
+#[cfg(any())]
+#[doc = concat!("a", "b")]
+pub struct X;
+
+#[cfg(any())]
+#[doc = MISSING]
+pub struct Y;
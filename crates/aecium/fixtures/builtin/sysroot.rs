@@ -0,0 +1,3 @@
+const LINE: u32 = line!();
+const NAME: &str = concat!(core::concat!("a", "b"), line!());
+const UNIX: bool = cfg!(unix);
@@ -0,0 +1,3 @@
+#![cfg_attr(unix, cfg(windows))]
+
+mod missing;
@@ -0,0 +1,29 @@
+#[cfg(windows)]
+mod win;
+#[cfg(not(windows))]
+mod other;
+#[cfg(all(unix, feature = "a"))]
+mod a;
+#[cfg(all(unix, feature = "b"))]
+mod b;
+
+#[cfg(windows)]
+windows_only!();
+
+fn f() {
+    #[cfg(windows)]
+    mod nested;
+
+    #[cfg(windows)]
+    let _ = g!();
+
+    #[cfg(windows)]
+    let _ = t.0.1;
+}
+
+mod inline {
+    #![cfg(windows)]
+    mod missing;
+}
+
+mod inner;
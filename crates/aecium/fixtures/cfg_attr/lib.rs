@@ -0,0 +1,12 @@
+#[cfg_attr(unix, path = "sys/unix.rs")]
+#[cfg_attr(windows, path = "sys/windows.rs")]
+mod sys;
+
+#[cfg_attr(all(), cfg_attr(not(unix), cfg(any())), cfg_attr(unix, cfg(all())))]
+mod kept;
+
+#[cfg_attr(unix, allow(dead_code), cfg(windows & unix))]
+fn f() {}
+
+#[cfg_attr(unix, cfg(windows))]
+mod stripped;
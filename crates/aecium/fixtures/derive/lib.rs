@@ -0,0 +1,59 @@
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Unit;
+
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+struct Pair<'a, T: ?Sized, const N: usize = 4>(&'a T, [u8; N])
+where
+    T: fmt::Display;
+
+#[derive(Clone, Default, Hash)]
+pub struct Point<T = i32> {
+    pub x: T,
+    #[cfg(any())]
+    hidden: T,
+    pub(crate) y: T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+enum Shape {
+    #[default]
+    Empty,
+    Circle(u32),
+    Rect { w: u32, h: u32 },
+}
+
+#[derive(Debug, PartialEq)]
+enum Never {}
+
+#[derive(Clone, Copy)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+#[cfg_attr(all(), derive(core::fmt::Debug))]
+struct Wrapper(Shape);
+
+fn f() {
+    #[derive(Clone)]
+    struct Local(u8);
+    let _ = Local(1).clone();
+}
+
+#[derive(Debug)]
+union NotDebug {
+    int: u32,
+}
+
+#[derive(Default)]
+enum NoDefault {
+    A,
+}
+
+#[derive(Missing, "string")]
+struct Errors;
+
+#[derive]
+struct Malformed;
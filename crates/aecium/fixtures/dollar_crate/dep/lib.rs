@@ -0,0 +1,47 @@
+pub mod helpers {
+    pub fn helper() {}
+
+    pub struct Helper;
+}
+
+fn private() {}
+
+#[macro_export]
+macro_rules! import_helper {
+    () => {
+        use $crate::helpers::Helper;
+    };
+}
+
+#[macro_export]
+macro_rules! forward {
+    () => {
+        $crate::import_helper!();
+    };
+}
+
+#[macro_export]
+macro_rules! define_import {
+    () => {
+        macro_rules! import_fn {
+            () => {
+                use $crate::helpers::helper;
+            };
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! import_private {
+    () => {
+        use $crate::private as _;
+    };
+}
+
+mod own {
+    import_helper!();
+
+    fn f() {
+        let _ = Helper;
+    }
+}
@@ -0,0 +1,29 @@
+// A module of the same name, which `$crate` doesn't refer to.
+mod helpers {}
+
+mod a {
+    dep::import_helper!();
+
+    fn f() {
+        let _ = Helper;
+    }
+}
+
+mod b {
+    dep::forward!();
+
+    fn f() {
+        let _ = Helper;
+    }
+}
+
+mod c {
+    dep::define_import!();
+    import_fn!();
+
+    fn f() {
+        let _ = helper;
+    }
+}
+
+dep::import_private!();
@@ -0,0 +1,12 @@
+include!(concat!(env!("OUT_DIR"), "/gen.rs"));
+
+const NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
+const MISSING: Option<&str> = option_env!("MISSING");
+
+fn f() -> usize {
+    let home: &str = env!("HOME", "set `HOME` first");
+    generated()
+}
+
+const NOT_SET: &str = env!("MISSING");
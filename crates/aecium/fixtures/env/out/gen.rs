@@ -0,0 +1,3 @@
+fn generated() -> usize {
+    NAME.len()
+}
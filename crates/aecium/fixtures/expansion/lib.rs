@@ -0,0 +1,129 @@
+macro_rules! make_mod {
+    ($name:ident) => {
+        pub mod $name {
+            pub fn f() {}
+        }
+    };
+}
+
+macro_rules! make_fn {
+    ($name:ident, $value:expr) => {
+        pub fn $name() -> u32 {
+            $value
+        }
+    };
+}
+
+macro_rules! nested {
+    ($($name:ident),*) => {
+        $(make_fn!($name, 0);)*
+    };
+}
+
+macro_rules! out_of_line {
+    () => {
+        mod inner;
+    };
+}
+
+macro_rules! define_later {
+    () => {
+        macro_rules! later {
+            () => {
+                pub struct Later;
+            };
+        }
+    };
+}
+
+macro_rules! square {
+    ($e:expr) => {
+        $e * $e
+    };
+}
+
+macro_rules! unit {
+    () => {
+        ()
+    };
+}
+
+macro_rules! wild {
+    () => {
+        _
+    };
+}
+
+make_mod!(a);
+make_mod! { b }
+make_fn!(one, 1);
+nested!(two, three);
+out_of_line!();
+define_later!();
+later!();
+
+use a::f;
+use inner::g;
+
+fn h(x: u32) -> unit!() {
+    let wild!() = square!(x);
+    let y = square!(x);
+    f();
+    g();
+    b::f();
+    let _ = (one(), two(), three(), Later, y);
+}
+
+macro_rules! method {
+    () => {
+        fn method(&self) -> &'de u32 {
+            &0
+        }
+    };
+}
+
+macro_rules! methods {
+    () => {
+        method!();
+    };
+}
+
+pub struct S<'de>(&'de u32);
+
+impl<'de> S<'de> {
+    method!();
+}
+
+trait T<'de> {
+    methods!();
+}
+
+fn method() {}
+
+macro_rules! documented {
+    ($($name:ident #[doc = $doc:literal])*) => {
+        $(
+            /// Generated.
+            pub struct $name;
+        )*
+    };
+}
+
+macro_rules! inner_doc {
+    (#![doc = $doc:literal]) => {};
+}
+
+documented! {
+    A /// a
+    B /** b */
+}
+
+inner_doc! {
+    //! inner
+    //// not a doc comment
+    /***/
+}
+
+fn docs() -> (A, B) {
+    (A, B)
+}
@@ -0,0 +1,6 @@
+extern crate dep;
+extern crate renamed as other;
+extern crate self as main;
+extern crate missing;
+
+mod inner;
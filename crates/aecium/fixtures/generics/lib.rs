@@ -0,0 +1,52 @@
+use std::marker::PhantomData;
+
+struct Wrapper<'a, T, U>(&'a T, PhantomData<U>);
+
+struct Unused<'a, T, const N: usize>;
+
+enum Either<L, R> {
+    Left(L),
+    Right { right: R },
+}
+
+struct Bounded<T>
+where
+    T: Clone;
+
+trait Convert<T> {
+    fn convert<'b>(&'b self, other: &'b T) -> Option<T>;
+}
+
+impl<'a, T: Into<U>, U> Convert<U> for Wrapper<'a, T, U>
+where
+    T: Clone + 'a,
+{
+    fn convert<'b>(&'b self, other: &'b U) -> Option<U> {
+        let x: &'a T = self.0;
+        fn nested(t: T) -> &'a u8 {
+            t
+        }
+        None
+    }
+}
+
+impl Missing for Either<u8, u8> {}
+
+fn higher<F>(f: F) -> Nowhere
+where
+    for<'c> F: Fn(&'c u8) -> &'c u8,
+{
+    let g = for<'d> |x: &'d u8| -> &'d u8 { x };
+    let h: &'c u8 = f(g(&0));
+    loop {}
+}
+
+fn undeclared(x: &'e u8) -> impl Display + 'static {}
+
+fn prefix<T: Iterator>(t: T::Item) -> unknown::Kind {}
+
+fn forward<'a: 'b, 'b, T: Into<U>, U>(x: &'a T) -> &'b u8 {}
+
+fn args<const N: usize>() -> [u8; N] {
+    Wrapper::<N>;
+}
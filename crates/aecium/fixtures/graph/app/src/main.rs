@@ -0,0 +1,6 @@
+extern crate util;
+
+#[cfg(feature = "extra")]
+extern crate helper_lib;
+
+fn main() {}
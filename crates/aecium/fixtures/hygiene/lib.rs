@@ -0,0 +1,62 @@
+macro_rules! shadow {
+    ($e:expr) => {{
+        let x = 2;
+        $e + x
+    }};
+}
+
+macro_rules! bind {
+    ($name:ident) => {
+        let $name = 0;
+        let hidden = 0;
+    };
+}
+
+macro_rules! outer_x {
+    () => {
+        x
+    };
+}
+
+macro_rules! break_outer {
+    () => {
+        break 'outer
+    };
+}
+
+macro_rules! param {
+    () => {
+        T
+    };
+}
+
+fn f<T>(x: u32) -> u32 {
+    let a = shadow!(x);
+    bind!(b);
+    let _ = (b, hidden);
+    let _ = outer_x!();
+    'outer: loop {
+        break_outer!();
+    }
+    let _: param!();
+    a
+}
+
+fn g() {
+    let x = 1;
+    macro_rules! local_x {
+        () => {
+            x
+        };
+    }
+    let x = 2;
+    let _ = (x, local_x!());
+    'a: loop {
+        macro_rules! break_a {
+            () => {
+                break 'a
+            };
+        }
+        break_a!();
+    }
+}
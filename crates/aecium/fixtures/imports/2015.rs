@@ -0,0 +1,18 @@
+mod a {
+    use b::S;
+    use ::b::S as T;
+    use self::S as U;
+}
+
+mod b {
+    pub struct S;
+}
+
+use a::b;
+
+mod c {
+    use std::cmp::max;
+    use ::std::mem;
+    use crate::std::fmt;
+    use core::cmp::min;
+}
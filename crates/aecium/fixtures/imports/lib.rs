@@ -0,0 +1,53 @@
+mod a {
+    pub mod b {
+        pub struct S;
+        pub fn f() {}
+        pub enum E {
+            V,
+            W { x: u8 },
+        }
+    }
+    pub use self::b::*;
+    pub use super::c::G as H;
+}
+
+mod c {
+    pub use super::a::b::E::{self, *};
+    pub use crate::a::*;
+    pub struct G;
+}
+
+mod d {
+    pub struct S;
+}
+
+mod e {
+    pub use crate::a::*;
+    pub use crate::d::*;
+}
+
+use a::{
+    b::{self, f as g},
+    H,
+};
+use c::{f, S, V, W};
+use self::a::b::S as _;
+use T as U;
+use c::G as T;
+
+use a::b::Missing;
+use missing::x;
+use a::b::S::x;
+use super::z;
+use e::S as Ambiguous;
+
+fn g() {}
+use d::S as Twice;
+use c::G as Twice;
+
+fn block() {
+    use self::S as Outer;
+    {
+        use Outer as Inner;
+    }
+}
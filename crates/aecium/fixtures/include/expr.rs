@@ -0,0 +1 @@
+text.len() + bytes.len()
@@ -0,0 +1,5 @@
+pub fn g() -> u32 {
+    line!()
+}
+
+include!("more.rs");
@@ -0,0 +1,15 @@
+mod a {
+    include!("inc/items.rs");
+}
+
+use a::{g, S};
+
+fn f() -> usize {
+    let text: &str = include_str!("data/text.txt");
+    let bytes: &[u8] = include_bytes!(concat!("data/", "bytes.bin"));
+    include!("expr.rs")
+}
+
+const MISSING: &str = include_str!("data/missing.txt");
+include!("missing.rs");
+const NOT_STRING: &str = include_str!(1);
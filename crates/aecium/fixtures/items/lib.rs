@@ -0,0 +1,34 @@
+struct Unit;
+struct Record {}
+struct Tuple(u8);
+fn f() {}
+mod f {}
+
+extern "C" {
+    static ext: u8;
+}
+
+trait Trait {
+    fn method();
+}
+
+mod inner {}
+
+#[macro_export]
+macro_rules! m {
+    () => {};
+}
+#[macro_export]
+macro_rules! m {
+    () => {};
+}
+
+extern crate self as me;
+
+enum Unit {}
+mod inner {}
+
+fn outer() {
+    fn local() {}
+    const local: u8 = 0;
+}
@@ -0,0 +1,60 @@
+pub fn loops(n: u32) -> u32 {
+    let mut i = 0;
+    'outer: loop {
+        while i < n {
+            i += 1;
+            if i % 2 == 0 {
+                continue;
+            }
+            for _ in 0..i {
+                break 'outer;
+            }
+        }
+        break;
+    }
+    'block: {
+        if i > 3 {
+            break 'block;
+        }
+        i = 0;
+    }
+    i
+}
+
+pub fn errors() {
+    break;
+    'a: {
+        continue 'a;
+    }
+    loop {
+        break 'missing;
+    }
+    'b: loop {
+        let _ = || {
+            break 'b;
+        };
+        let _ = || {
+            continue;
+        };
+        'c: {
+            break;
+        }
+    }
+    'd: loop {
+        'd: loop {
+            break 'd;
+        }
+    }
+    continue;
+}
+
+pub fn items() {
+    loop {
+        fn inner() {
+            break;
+        }
+        const X: () = loop {
+            break;
+        };
+    }
+}
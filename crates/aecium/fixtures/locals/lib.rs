@@ -0,0 +1,34 @@
+const C: u8 = 0;
+
+fn f(a: u8, (b, c): (u8, u8)) -> u8 {
+    let a = a;
+    let x = y;
+    let C = 1;
+    let d = |e: u8| e + a + C;
+    match a {
+        g if g > b => g,
+        _ => x,
+    };
+    if let Some(h) = None { h } else { h };
+    while let Some(i) = None { i }
+    i;
+    for j in j {}
+    let k = 1;
+    fn inner() -> u8 {
+        k
+    }
+    {
+        let l = 1;
+    }
+    l;
+    let Some(m) = None else { m };
+    m + c + d(0) + f(0, (0, 0))
+}
+
+struct Arr<const M: usize>;
+
+impl<const M: usize> Arr<M> {
+    fn get<const N: usize>() -> usize {
+        M + N
+    }
+}
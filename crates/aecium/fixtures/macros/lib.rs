@@ -0,0 +1,73 @@
+fn early() {
+    let _ = before!();
+}
+
+macro_rules! before {
+    () => { () };
+}
+
+macro_rules! shadowed {
+    () => { () };
+}
+
+macro_rules! shadowed {
+    () => { () };
+}
+
+#[macro_use]
+mod with_use;
+mod without_use;
+
+mod inline {
+    #[macro_use]
+    extern crate std;
+
+    fn f() {
+        let _ = before!();
+    }
+
+    macro_rules! inner {
+        () => { () };
+    }
+}
+
+#[macro_use]
+mod inline_use {
+    macro_rules! kept {
+        () => { () };
+    }
+}
+
+#[macro_export]
+macro_rules! exported {
+    () => { () };
+}
+
+mod paths {
+    pub(crate) use before;
+
+    fn f() {
+        let _ = crate::exported!();
+        let _ = crate::missing!();
+        let _ = self::before!();
+    }
+}
+
+fn late() {
+    macro_rules! local {
+        () => { () };
+    }
+    let _ = before!();
+    let _ = shadowed!();
+    let _ = from_child!();
+    let _ = hidden!();
+    let _ = inner!();
+    let _ = kept!();
+    let _ = local!();
+    let _ = println!();
+    let _ = paths::before!();
+}
+
+fn after() {
+    let _ = local!();
+}
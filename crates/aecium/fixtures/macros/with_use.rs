@@ -0,0 +1,7 @@
+macro_rules! from_child {
+    () => { () };
+}
+
+fn f() {
+    let _ = before!();
+}
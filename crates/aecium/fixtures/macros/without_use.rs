@@ -0,0 +1,7 @@
+macro_rules! hidden {
+    () => { () };
+}
+
+fn f() {
+    let _ = shadowed!();
+}
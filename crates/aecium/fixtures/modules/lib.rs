@@ -0,0 +1,6 @@
+mod a;
+mod c;
+
+mod inline {
+    mod e;
+}
@@ -0,0 +1,2 @@
+#[cfg(feature = "std")]
+mod std_only;
@@ -0,0 +1 @@
+fn std_only() {}
@@ -0,0 +1,7 @@
+#[path = "x.rs"]
+mod x;
+
+mod inline {
+    #[path = "y.rs"]
+    mod y;
+}
@@ -0,0 +1,8 @@
+#[path = "other/renamed.rs"]
+mod a;
+mod c;
+
+#[path = "dir"]
+mod d {
+    mod e;
+}
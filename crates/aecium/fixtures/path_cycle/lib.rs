@@ -0,0 +1,4 @@
+mod a;
+#[path = "b.rs"]
+mod b2;
+mod b;
@@ -0,0 +1,10 @@
+extern crate alloc;
+
+use Option as O;
+use Some as S;
+use TryFrom as T;
+use Vec as V;
+
+mod m {
+    use alloc as a;
+}
@@ -0,0 +1,4 @@
+#![no_std]
+
+use Option as O;
+use Vec as V;
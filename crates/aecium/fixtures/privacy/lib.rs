@@ -0,0 +1,67 @@
+mod c {}
+
+mod a {
+    pub mod b {
+        pub(crate) struct Crate;
+        pub(super) struct Super;
+        pub(in crate::a) struct InA;
+        pub(self) struct SelfVis;
+        struct Private;
+        pub struct Tuple(u8);
+        pub struct PubTuple(pub u8);
+        pub struct Record {
+            pub x: u8,
+            y: u8,
+            x: u8,
+        }
+        pub(in crate::c) struct NotAncestor;
+    }
+
+    use self::b::{InA, Super};
+
+    mod private {
+        pub struct Hidden;
+    }
+
+    pub use self::b::*;
+    pub use self::private::Hidden;
+}
+
+use a::b::Crate;
+use a::b::Super;
+use a::b::Private;
+use a::private::Hidden as H;
+use a::Hidden;
+use a::b::Tuple;
+use a::b::PubTuple;
+use a::SelfVis;
+use a::Crate as Crate2;
+
+fn fields(record: a::b::Record) {
+    let a::b::Record { x, y: _, .. } = record;
+    let _ = a::b::Record { x, y: 0 };
+    let _ = a::b::PubTuple { 0: x };
+}
+
+mod vis {
+    pub(in crate::vis::super) struct NotStart;
+    pub(in Self) struct SelfType;
+}
+
+mod paths {
+    mod a {
+        fn private() {}
+        mod inner {
+            pub fn f() {}
+        }
+        pub mod open {
+            pub struct S;
+        }
+    }
+
+    fn g(_: a::inner::S, _: a::open::S) {
+        a::private();
+        a::inner::f();
+        a::open::S;
+    }
+}
@@ -0,0 +1,43 @@
+mod inner {
+    pub fn f<T>(x: T) -> T {
+        let g = |y: u8| {
+            'outer: loop {
+                let z = y;
+                break 'outer;
+            }
+        };
+        x
+    }
+}
+
+trait Tr {
+    fn m(&self) {}
+}
+
+impl Tr for () {
+    fn m(&self) {
+        'block: {
+            let w = 0;
+        }
+    }
+}
+
+fn h<F>(f: F)
+where
+    for<'a> F: Fn(&'a u8),
+{
+}
+
+macro_rules! m {
+    () => {
+        fn e() {}
+    };
+}
+
+m!();
+
+fn g() {
+    let before = 1;
+    m!();
+    let b = 2;
+}
@@ -0,0 +1,9 @@
+fn fields(x: ((u8, u8), (u8, u8))) -> u8 {
+    let a = x.0.1;
+    let b = x.1 .0;
+    #[cfg(any())]
+    let c = x.0.0;
+    a + b + x.1.0.min(1)
+}
+
+fn broken( {}
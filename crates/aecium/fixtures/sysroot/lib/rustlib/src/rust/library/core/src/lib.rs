@@ -0,0 +1,55 @@
+#![no_core]
+
+pub mod option {
+    pub enum Option<T> {
+        None,
+        Some(T),
+    }
+}
+
+pub mod macros {
+    #[rustc_builtin_macro]
+    #[macro_export]
+    macro_rules! concat {
+        ($($e:expr),* $(,)?) => {{ /* compiler built-in */ }};
+    }
+
+    #[rustc_builtin_macro]
+    #[macro_export]
+    macro_rules! line {
+        () => {
+            /* compiler built-in */
+        };
+    }
+
+    pub mod builtin {
+        #[rustc_builtin_macro]
+        pub macro cfg($($cfg:tt)*) {
+            /* compiler built-in */
+        }
+    }
+}
+
+pub mod prelude {
+    pub mod v1 {
+        pub use crate::macros::builtin::cfg;
+        pub use crate::option::Option::{self, None, Some};
+        pub use crate::{concat, line};
+    }
+
+    pub mod rust_2015 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2018 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2021 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2024 {
+        pub use super::v1::*;
+    }
+}
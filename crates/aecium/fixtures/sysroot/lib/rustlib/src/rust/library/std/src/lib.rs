@@ -0,0 +1,31 @@
+#![no_std]
+
+extern crate alloc as alloc_crate;
+extern crate libc;
+
+pub mod vec {
+    pub struct Vec;
+}
+
+pub mod prelude {
+    pub mod v1 {
+        pub use crate::vec::Vec;
+        pub use core::prelude::v1::*;
+    }
+
+    pub mod rust_2015 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2018 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2021 {
+        pub use super::v1::*;
+    }
+
+    pub mod rust_2024 {
+        pub use super::v1::*;
+    }
+}
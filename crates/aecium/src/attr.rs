@@ -0,0 +1,160 @@
+use ra_ap_parser::SyntaxKind;
+#[cfg(test)]
+use ra_ap_parser::{Edition, LexedStr};
+#[cfg(test)]
+use ra_ap_rustc_index::Idx;
+
+use crate::{
+    hygiene::SyntaxContext,
+    name::{Name, Names},
+    source::SourceLoc,
+};
+
+/// A token recorded verbatim, for attributes whose contents are interpreted later.
+#[derive(Clone, Copy, Debug)]
+pub struct Token {
+    pub kind: SyntaxKind,
+    pub text: Name,
+    pub loc: SourceLoc,
+    /// The macro expansions the token came out of, which only matters for identifiers.
+    pub ctx: SyntaxContext,
+}
+
+impl Token {
+    /// Lex text at `base` in the source into tokens, leaving out trivia.
+    #[cfg(test)]
+    pub fn lex(names: &mut Names, edition: Edition, base: SourceLoc, text: &str) -> Vec<Token> {
+        let lexed = LexedStr::new(edition, text);
+        (0..lexed.len())
+            .filter(|&i| !lexed.kind(i).is_trivia() && lexed.kind(i) != SyntaxKind::EOF)
+            .map(|i| Token {
+                kind: lexed.kind(i),
+                text: names.make(lexed.text(i)),
+                loc: base.plus(lexed.text_start(i)),
+                ctx: SyntaxContext::ROOT,
+            })
+            .collect()
+    }
+}
+
+/// An attribute, with the tokens between its brackets.
+pub struct Attr {
+    pub inner: bool,
+    pub tokens: Vec<Token>,
+    /// The location of the `cfg_attr` this attribute was expanded from, if any.
+    pub cfg_attr: Option<SourceLoc>,
+}
+
+impl Attr {
+    pub fn loc(&self) -> SourceLoc {
+        self.tokens[0].loc
+    }
+
+    /// The name of the attribute, if its path is a single identifier.
+    pub fn name(&self) -> Option<Name> {
+        match self.tokens.as_slice() {
+            [first, rest @ ..]
+                if first.kind == SyntaxKind::IDENT
+                    && rest.first().is_none_or(|t| t.kind != SyntaxKind::COLON2) =>
+            {
+                Some(first.text)
+            }
+            _ => None,
+        }
+    }
+
+    /// The string value of a `#[name = "value"]` attribute, and the location of the literal.
+    pub fn value(&self, names: &Names) -> Option<(String, SourceLoc)> {
+        match self.tokens.as_slice() {
+            [_, eq, lit] if eq.kind == SyntaxKind::EQ && lit.kind == SyntaxKind::STRING => {
+                Some((unquote(names.get(lit.text))?, lit.loc))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Split the arguments of a `name(...)` attribute at their top-level commas, allowing one trailing
+/// comma. Returns `None` if the attribute doesn't have that shape or has an empty argument.
+pub fn split_args(tokens: &[Token]) -> Option<Vec<&[Token]>> {
+    let [_, open, inside @ .., close] = tokens else {
+        return None;
+    };
+    if open.kind != SyntaxKind::L_PAREN || close.kind != SyntaxKind::R_PAREN {
+        return None;
+    }
+    split_commas(inside)
+}
+
+/// Split tokens at their top-level commas, allowing one trailing comma. Returns `None` if there is
+/// an empty argument or the delimiters don't match up.
+pub fn split_commas(inside: &[Token]) -> Option<Vec<&[Token]>> {
+    let mut args = Vec::new();
+    let mut depth: usize = 0;
+    let mut start = 0;
+    for (i, token) in inside.iter().enumerate() {
+        match token.kind {
+            SyntaxKind::L_PAREN | SyntaxKind::L_BRACK | SyntaxKind::L_CURLY => depth += 1,
+            SyntaxKind::R_PAREN | SyntaxKind::R_BRACK | SyntaxKind::R_CURLY => {
+                depth = depth.checked_sub(1)?
+            }
+            SyntaxKind::COMMA if depth == 0 => {
+                args.push(&inside[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < inside.len() {
+        args.push(&inside[start..]);
+    }
+    if args.iter().any(|arg| arg.is_empty()) {
+        return None;
+    }
+    Some(args)
+}
+
+/// Decode the text of a string literal, returning `None` if it is malformed.
+pub fn unquote(text: &str) -> Option<String> {
+    if let Some(raw) = text.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let body = raw[hashes..].strip_prefix('"')?;
+        let body = body.strip_suffix(&raw[..hashes])?.strip_suffix('"')?;
+        return Some(body.to_owned());
+    }
+    let body = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            '\\' => out.push('\\'),
+            '\'' => out.push('\''),
+            '"' => out.push('"'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(char::from(u8::from_str_radix(&hex, 16).ok()?));
+            }
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let end = rest.find('}')?;
+                let code = u32::from_str_radix(&rest[..end].replace('_', ""), 16).ok()?;
+                out.push(char::from_u32(code)?);
+                chars = rest[end + 1..].chars();
+            }
+            '\n' => {
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
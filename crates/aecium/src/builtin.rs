@@ -0,0 +1,652 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{self, PathBuf},
+};
+
+use ra_ap_parser::SyntaxKind;
+use ra_ap_rustc_index::Idx;
+
+use crate::{
+    attr::{split_commas, unquote, Token},
+    cfg::{CfgExpr, CfgSet},
+    derive::{self, BuiltinDerive},
+    diagnostic::Diagnostic,
+    hygiene::SyntaxContext,
+    name::{Name, Names},
+    path::{Path, Paths},
+    source::{Source, SourceFile, SourceLoc},
+};
+
+/// A macro that the compiler implements itself rather than with rules, like `concat!` or the
+/// derive of `Clone`. The standard library declares these with `#[rustc_builtin_macro]`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuiltinMacro {
+    Cfg,
+    Column,
+    Concat,
+    Derive(BuiltinDerive),
+    Env,
+    File,
+    Include,
+    IncludeBytes,
+    IncludeStr,
+    Line,
+    ModulePath,
+    OptionEnv,
+    Stringify,
+}
+
+impl BuiltinMacro {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "cfg" => Self::Cfg,
+            "column" => Self::Column,
+            "concat" => Self::Concat,
+            "env" => Self::Env,
+            "file" => Self::File,
+            "include" => Self::Include,
+            "include_bytes" => Self::IncludeBytes,
+            "include_str" => Self::IncludeStr,
+            "line" => Self::Line,
+            "module_path" => Self::ModulePath,
+            "option_env" => Self::OptionEnv,
+            "stringify" => Self::Stringify,
+            _ => return BuiltinDerive::from_name(name).map(Self::Derive),
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Cfg => "cfg",
+            Self::Column => "column",
+            Self::Concat => "concat",
+            Self::Derive(derive) => derive.name(),
+            Self::Env => "env",
+            Self::File => "file",
+            Self::Include => "include",
+            Self::IncludeBytes => "include_bytes",
+            Self::IncludeStr => "include_str",
+            Self::Line => "line",
+            Self::ModulePath => "module_path",
+            Self::OptionEnv => "option_env",
+            Self::Stringify => "stringify",
+        }
+    }
+}
+
+/// The expansion of a call to a built-in macro.
+pub enum BuiltinExpansion {
+    Tokens(Vec<Token>),
+    /// The source file loaded by `include!`, to be parsed in place of the call.
+    File(SourceFile),
+}
+
+/// What built-in macros can see of the call being expanded.
+pub struct BuiltinCx<'a> {
+    /// The source files, which `include!` adds to.
+    pub src: &'a mut Source,
+    pub names: &'a mut Names,
+    pub paths: &'a Paths,
+    pub cfg: &'a CfgSet,
+    /// The environment variables of the crate containing the call, which `env!` reads.
+    pub env: &'a HashMap<String, String>,
+    /// The name of the crate containing the call.
+    pub krate: Name,
+    /// The module containing the call.
+    pub module: Path,
+    /// The location of the outermost macro call the call came out of, which is where `file!`,
+    /// `line!` and `column!` point, and which the paths of files to include are relative to.
+    pub call_site: SourceLoc,
+    /// The syntax context of the tokens that the expansion makes up, which has the mark of the
+    /// expansion applied.
+    pub ctx: SyntaxContext,
+}
+
+impl BuiltinCx<'_> {
+    /// Expand a call to a built-in macro whose path starts at `loc`, with the tokens between its
+    /// delimiters as `input`, or the tokens of the item a built-in derive is applied to.
+    pub fn expand(
+        &mut self,
+        builtin: BuiltinMacro,
+        input: &[Token],
+        loc: SourceLoc,
+    ) -> Result<BuiltinExpansion, Diagnostic> {
+        match builtin {
+            BuiltinMacro::Derive(derive) => {
+                derive::expand(self.names, derive, input, loc, self.ctx)
+                    .map(BuiltinExpansion::Tokens)
+            }
+            BuiltinMacro::Include => {
+                let (file, arg) = self.include_path(builtin, input, loc)?;
+                let text = fs::read_to_string(&file).map_err(|e| read_error(&file, arg, e))?;
+                let name = file.to_string_lossy();
+                let source_file = self.src.read(&name, text.as_bytes()).unwrap();
+                Ok(BuiltinExpansion::File(source_file))
+            }
+            BuiltinMacro::OptionEnv => {
+                let arg = match split_commas(input).as_deref() {
+                    Some(&[arg]) => arg,
+                    _ => return Err(arg_count(builtin, "1 argument", loc)),
+                };
+                let var = self.string_arg(arg)?;
+                Ok(BuiltinExpansion::Tokens(self.option_env(&var, loc)))
+            }
+            _ => {
+                let token = self.literal(builtin, input, loc)?;
+                Ok(BuiltinExpansion::Tokens(vec![token]))
+            }
+        }
+    }
+
+    /// The tokens of `::core::option::Option::Some("value")` for a variable that is set, or of
+    /// `::core::option::Option::None::<&'static str>` for one that isn't.
+    fn option_env(&mut self, var: &str, loc: SourceLoc) -> Vec<Token> {
+        let (mut tokens, ctx) = (Vec::new(), self.ctx);
+        let mut push = |names: &mut Names, kind, text: &str| {
+            tokens.push(Token {
+                kind,
+                text: names.make(text),
+                loc,
+                ctx,
+            })
+        };
+        for segment in ["core", "option", "Option"] {
+            push(self.names, SyntaxKind::COLON2, "::");
+            push(self.names, SyntaxKind::IDENT, segment);
+        }
+        push(self.names, SyntaxKind::COLON2, "::");
+        match self.env.get(var) {
+            Some(value) => {
+                push(self.names, SyntaxKind::IDENT, "Some");
+                push(self.names, SyntaxKind::L_PAREN, "(");
+                push(self.names, SyntaxKind::STRING, &format!("{value:?}"));
+                push(self.names, SyntaxKind::R_PAREN, ")");
+            }
+            None => {
+                push(self.names, SyntaxKind::IDENT, "None");
+                push(self.names, SyntaxKind::COLON2, "::");
+                push(self.names, SyntaxKind::L_ANGLE, "<");
+                push(self.names, SyntaxKind::AMP, "&");
+                push(self.names, SyntaxKind::LIFETIME_IDENT, "'static");
+                push(self.names, SyntaxKind::IDENT, "str");
+                push(self.names, SyntaxKind::R_ANGLE, ">");
+            }
+        }
+        tokens
+    }
+
+    /// Expand a call to a built-in macro that expands to a single literal token.
+    fn literal(
+        &mut self,
+        builtin: BuiltinMacro,
+        input: &[Token],
+        loc: SourceLoc,
+    ) -> Result<Token, Diagnostic> {
+        let no_args = || match input.first() {
+            Some(first) => Err(Diagnostic::error(
+                first.loc,
+                format!("`{}!` takes no arguments", builtin.name()),
+            )),
+            None => Ok(()),
+        };
+        let (kind, text) = match builtin {
+            BuiltinMacro::Cfg => {
+                let args = match input.last() {
+                    Some(last) if last.kind == SyntaxKind::COMMA => &input[..input.len() - 1],
+                    _ => input,
+                };
+                if args.is_empty() {
+                    return Err(Diagnostic::error(
+                        loc,
+                        "macro requires a cfg-pattern as an argument",
+                    ));
+                }
+                let Some(expr) = CfgExpr::parse(self.names, args) else {
+                    return Err(Diagnostic::error(
+                        args[0].loc,
+                        "malformed `cfg!` macro input",
+                    ));
+                };
+                match self.cfg.eval(&expr) {
+                    true => (SyntaxKind::TRUE_KW, "true".to_owned()),
+                    false => (SyntaxKind::FALSE_KW, "false".to_owned()),
+                }
+            }
+            BuiltinMacro::Column => {
+                no_args()?;
+                let (_, col) = self.src.line_col(self.call_site);
+                (SyntaxKind::INT_NUMBER, format!("{col}u32"))
+            }
+            BuiltinMacro::Concat => (SyntaxKind::STRING, format!("{:?}", self.concat(input)?)),
+            BuiltinMacro::Env => {
+                let (var, message) = match split_commas(input).as_deref() {
+                    Some(&[var]) => (self.string_arg(var)?, None),
+                    Some(&[var, message]) => {
+                        (self.string_arg(var)?, Some(self.string_arg(message)?))
+                    }
+                    _ => return Err(arg_count(builtin, "1 or 2 arguments", loc)),
+                };
+                let Some(value) = self.env.get(&var) else {
+                    let message = message.unwrap_or_else(|| {
+                        format!("environment variable `{var}` not defined at compile time")
+                    });
+                    return Err(Diagnostic::error(loc, message).help(format!(
+                        "use `std::env::var({var:?})` to read the variable at run time"
+                    )));
+                };
+                (SyntaxKind::STRING, format!("{value:?}"))
+            }
+            BuiltinMacro::File => {
+                no_args()?;
+                let name = self.src.name(self.src.file(self.call_site));
+                (SyntaxKind::STRING, format!("{name:?}"))
+            }
+            BuiltinMacro::Derive(_) | BuiltinMacro::Include | BuiltinMacro::OptionEnv => {
+                return Err(not_literal(loc))
+            }
+            BuiltinMacro::IncludeBytes => {
+                let (file, arg) = self.include_path(builtin, input, loc)?;
+                let bytes = fs::read(&file).map_err(|e| read_error(&file, arg, e))?;
+                (SyntaxKind::BYTE_STRING, byte_string(&bytes))
+            }
+            BuiltinMacro::IncludeStr => {
+                let (file, arg) = self.include_path(builtin, input, loc)?;
+                let text = fs::read_to_string(&file).map_err(|e| read_error(&file, arg, e))?;
+                (SyntaxKind::STRING, format!("{text:?}"))
+            }
+            BuiltinMacro::Line => {
+                no_args()?;
+                let (line, _) = self.src.line_col(self.call_site);
+                (SyntaxKind::INT_NUMBER, format!("{line}u32"))
+            }
+            BuiltinMacro::ModulePath => {
+                no_args()?;
+                let krate = self.names.get(self.krate);
+                let path = self.paths.display_from(self.names, krate, self.module);
+                (SyntaxKind::STRING, format!("{path:?}"))
+            }
+            BuiltinMacro::Stringify => (
+                SyntaxKind::STRING,
+                format!("{:?}", stringify(self.names, input)),
+            ),
+        };
+        Ok(Token {
+            kind,
+            text: self.names.make(&text),
+            loc,
+            ctx: self.ctx,
+        })
+    }
+
+    /// The literal that an argument is, if it is one or a call to another built-in macro that
+    /// expands to one. Like rustc, such calls are expanded first, naming the macros directly.
+    fn literal_arg(&mut self, arg: &[Token]) -> Result<Option<Token>, Diagnostic> {
+        if let Some((name, inner)) = nested_call(arg) {
+            return match BuiltinMacro::from_name(self.names.get(name.text)) {
+                Some(builtin) => self.literal(builtin, inner, name.loc).map(Some),
+                None => Ok(None),
+            };
+        }
+        Ok(match *arg {
+            [lit] => Some(lit),
+            _ => None,
+        })
+    }
+
+    /// The value of an argument that must be a string literal.
+    fn string_arg(&mut self, arg: &[Token]) -> Result<String, Diagnostic> {
+        let value = match self.literal_arg(arg)? {
+            Some(lit) if lit.kind == SyntaxKind::STRING => unquote(self.names.get(lit.text)),
+            _ => None,
+        };
+        value.ok_or_else(|| Diagnostic::error(arg[0].loc, "argument must be a string literal"))
+    }
+
+    /// The file named by the single string argument of `include!` and the like, relative to the
+    /// directory of the file containing the call site, along with the location of the argument.
+    fn include_path(
+        &mut self,
+        builtin: BuiltinMacro,
+        input: &[Token],
+        loc: SourceLoc,
+    ) -> Result<(PathBuf, SourceLoc), Diagnostic> {
+        let arg = match split_commas(input).as_deref() {
+            Some(&[arg]) => arg,
+            _ => return Err(arg_count(builtin, "1 argument", loc)),
+        };
+        let value = self.string_arg(arg)?;
+        let including = self.src.name(self.src.file(self.call_site));
+        let dir = path::Path::new(including)
+            .parent()
+            .unwrap_or(path::Path::new(""));
+        Ok((dir.join(value), arg[0].loc))
+    }
+
+    /// Concatenate the values of literals separated by commas.
+    fn concat(&mut self, input: &[Token]) -> Result<String, Diagnostic> {
+        // Only an empty argument or unbalanced delimiters fail to split, which takes some tokens.
+        let Some(args) = split_commas(input) else {
+            return Err(not_literal(input[0].loc));
+        };
+        let mut out = String::new();
+        for arg in args {
+            let lit = match (self.literal_arg(arg)?, arg) {
+                (Some(lit), _) => lit,
+                (None, &[minus, lit])
+                    if minus.kind == SyntaxKind::MINUS
+                        && matches!(
+                            lit.kind,
+                            SyntaxKind::INT_NUMBER | SyntaxKind::FLOAT_NUMBER
+                        ) =>
+                {
+                    out.push('-');
+                    lit
+                }
+                _ => return Err(not_literal(arg[0].loc)),
+            };
+            let text = self.names.get(lit.text);
+            match lit.kind {
+                SyntaxKind::STRING => {
+                    out.push_str(&unquote(text).ok_or_else(|| not_literal(lit.loc))?)
+                }
+                SyntaxKind::CHAR => {
+                    let body = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\''));
+                    let value = body.and_then(|body| unquote(&format!("\"{body}\"")));
+                    out.push_str(&value.ok_or_else(|| not_literal(lit.loc))?);
+                }
+                SyntaxKind::INT_NUMBER => out.push_str(
+                    &int_value(text)
+                        .ok_or_else(|| not_literal(lit.loc))?
+                        .to_string(),
+                ),
+                SyntaxKind::FLOAT_NUMBER => {
+                    let text = ["f16", "f32", "f64", "f128"]
+                        .iter()
+                        .find_map(|suffix| text.strip_suffix(suffix))
+                        .unwrap_or(text);
+                    out.push_str(&text.replace('_', ""));
+                }
+                SyntaxKind::TRUE_KW | SyntaxKind::FALSE_KW => out.push_str(text),
+                SyntaxKind::BYTE | SyntaxKind::BYTE_STRING => {
+                    return Err(Diagnostic::error(
+                        lit.loc,
+                        "cannot concatenate a byte string literal",
+                    ))
+                }
+                SyntaxKind::C_STRING => {
+                    return Err(Diagnostic::error(
+                        lit.loc,
+                        "cannot concatenate a C string literal",
+                    ))
+                }
+                _ => return Err(not_literal(lit.loc)),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Split a macro call like `name!(...)` or `core::name!(...)` into the last segment of its path
+/// and the tokens between its delimiters.
+fn nested_call(tokens: &[Token]) -> Option<(Token, &[Token])> {
+    let bang = tokens
+        .iter()
+        .position(|token| token.kind == SyntaxKind::BANG)?;
+    let (path, [_, open, inner @ .., close]) = tokens.split_at(bang) else {
+        return None;
+    };
+    let delimited = matches!(
+        (open.kind, close.kind),
+        (SyntaxKind::L_PAREN, SyntaxKind::R_PAREN)
+            | (SyntaxKind::L_BRACK, SyntaxKind::R_BRACK)
+            | (SyntaxKind::L_CURLY, SyntaxKind::R_CURLY)
+    );
+    // Inside a token tree, each `::` is two `:` tokens.
+    let mut parts: Vec<_> = path
+        .split(|token| token.kind == SyntaxKind::COLON)
+        .collect();
+    if parts.len() > 2 && parts[0].is_empty() {
+        parts.drain(..2);
+    }
+    let segments = parts
+        .iter()
+        .enumerate()
+        .all(|(i, part)| match (i % 2, part) {
+            (0, [token]) => token.kind == SyntaxKind::IDENT,
+            (1, []) => true,
+            _ => false,
+        });
+    let name = *parts.last()?.first()?;
+    (delimited && segments).then_some((name, inner))
+}
+
+fn arg_count(builtin: BuiltinMacro, count: &str, loc: SourceLoc) -> Diagnostic {
+    Diagnostic::error(loc, format!("`{}!` takes {count}", builtin.name()))
+}
+
+fn read_error(file: &path::Path, loc: SourceLoc, e: io::Error) -> Diagnostic {
+    Diagnostic::error(loc, format!("couldn't read {file:?}: {e}"))
+}
+
+fn not_literal(loc: SourceLoc) -> Diagnostic {
+    Diagnostic::error(loc, "expected a literal")
+        .note("only literals (like `\"foo\"`, `-42` and `3.14`) can be passed to `concat!()`")
+}
+
+/// A byte string literal with the given value.
+fn byte_string(bytes: &[u8]) -> String {
+    let mut out = String::from("b\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(char::from(byte));
+            }
+            b' '..=b'~' => out.push(char::from(byte)),
+            _ => out.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The value of an integer literal, ignoring its suffix.
+fn int_value(text: &str) -> Option<u128> {
+    let text = text.replace('_', "");
+    let (radix, digits) = match text.get(..2) {
+        Some("0x") => (16, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        _ => (10, &text[..]),
+    };
+    let end = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    u128::from_str_radix(&digits[..end], radix).ok()
+}
+
+/// The text of tokens as `stringify!` renders it, with a space between tokens unless they were
+/// adjacent in the source.
+fn stringify(names: &Names, tokens: &[Token]) -> String {
+    let mut out = String::new();
+    let mut prev: Option<(Token, usize)> = None;
+    for &token in tokens {
+        let text = names.get(token.text);
+        if prev.is_some_and(|(prev, len)| prev.loc.plus(len) != token.loc) {
+            out.push(' ');
+        }
+        out.push_str(text);
+        prev = Some((token, text.len()));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ra_ap_parser::Edition;
+
+    use crate::{
+        attr::Token,
+        builtin::{byte_string, BuiltinCx, BuiltinExpansion, BuiltinMacro},
+        cfg::{CfgFlag, CfgSet},
+        hygiene::SyntaxContext,
+        name::Names,
+        path::Paths,
+        source::Source,
+    };
+
+    /// Expand a call in module `a` of crate `krate`, on the second line of `src/lib.rs`, returning
+    /// the text of the literal or the first line of a rendered diagnostic.
+    fn expand(call: &str) -> Result<String, String> {
+        let mut src = Source::new();
+        let mut names = Names::new();
+        let text = format!("mod a {{\n    {call}\n}}\n");
+        let file = src.read("src/lib.rs", text.as_bytes()).unwrap();
+        let base = src.range(file).start;
+        let tokens = Token::lex(&mut names, Edition::Edition2021, base, &text);
+        // Skip `mod a {` and the call's path and `!(`, and leave off `)` and `}`.
+        let (name, input) = (tokens[3], &tokens[6..tokens.len() - 2]);
+        let builtin = BuiltinMacro::from_name(names.get(name.text)).unwrap();
+        let mut paths = Paths::new();
+        let module = paths.root();
+        let module = paths.child(module, names.make("a"));
+        let flags: Vec<CfgFlag> = vec!["unix".parse().unwrap()];
+        let cfg = CfgSet::new(&mut names, &flags);
+        let env = HashMap::from([("OUT_DIR".to_owned(), "target/out".to_owned())]);
+        let krate = names.make("krate");
+        let mut cx = BuiltinCx {
+            src: &mut src,
+            names: &mut names,
+            paths: &paths,
+            cfg: &cfg,
+            env: &env,
+            krate,
+            module,
+            call_site: name.loc,
+            ctx: SyntaxContext::ROOT,
+        };
+        let output = cx.expand(builtin, input, name.loc);
+        match output {
+            Ok(BuiltinExpansion::Tokens(tokens)) => Ok(tokens
+                .iter()
+                .map(|token| names.get(token.text))
+                .collect::<Vec<_>>()
+                .join(" ")),
+            Ok(BuiltinExpansion::File(file)) => Ok(src.code(src.range(file)).to_owned()),
+            Err(diagnostic) => Err(diagnostic.render(&src).lines().next().unwrap().to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_location() {
+        assert_eq!(expand("line!()").unwrap(), "2u32");
+        assert_eq!(expand("column!()").unwrap(), "5u32");
+        assert_eq!(expand("file!()").unwrap(), r#""src/lib.rs""#);
+        assert_eq!(expand("module_path!()").unwrap(), r#""krate::a""#);
+        assert_eq!(
+            expand("line!(1)").unwrap_err(),
+            "error: `line!` takes no arguments"
+        );
+    }
+
+    #[test]
+    fn test_cfg() {
+        assert_eq!(expand("cfg!(unix)").unwrap(), "true");
+        assert_eq!(expand("cfg!(not(unix),)").unwrap(), "false");
+        assert_eq!(
+            expand("cfg!()").unwrap_err(),
+            "error: macro requires a cfg-pattern as an argument"
+        );
+        assert_eq!(
+            expand("cfg!(a b)").unwrap_err(),
+            "error: malformed `cfg!` macro input"
+        );
+    }
+
+    #[test]
+    fn test_concat() {
+        assert_eq!(
+            expand(r#"concat!("a", 'b', 1_0, 0x10u8, -2.5f64, true,)"#).unwrap(),
+            r#""ab1016-2.5true""#
+        );
+        assert_eq!(expand(r#"concat!("\"", r"\")"#).unwrap(), r#""\"\\""#);
+        assert_eq!(
+            expand("concat!(line!(), core::stringify!(x::y))").unwrap(),
+            r#""2x::y""#
+        );
+        assert_eq!(expand("concat!()").unwrap(), r#""""#);
+        assert_eq!(
+            expand("concat!(x)").unwrap_err(),
+            "error: expected a literal"
+        );
+        assert_eq!(
+            expand("concat!(m!())").unwrap_err(),
+            "error: expected a literal"
+        );
+        assert_eq!(
+            expand(r#"concat!(b"x")"#).unwrap_err(),
+            "error: cannot concatenate a byte string literal"
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        assert_eq!(expand(r#"env!("OUT_DIR")"#).unwrap(), r#""target/out""#);
+        assert_eq!(
+            expand(r#"concat!(env!("OUT_DIR",), "/gen.rs")"#).unwrap(),
+            r#""target/out/gen.rs""#
+        );
+        assert_eq!(
+            expand(r#"env!("HOME")"#).unwrap_err(),
+            "error: environment variable `HOME` not defined at compile time"
+        );
+        assert_eq!(
+            expand(r#"env!("HOME", "where do you live?")"#).unwrap_err(),
+            "error: where do you live?"
+        );
+        assert_eq!(
+            expand("env!()").unwrap_err(),
+            "error: `env!` takes 1 or 2 arguments"
+        );
+        assert_eq!(
+            expand(r#"option_env!("OUT_DIR")"#).unwrap(),
+            r#":: core :: option :: Option :: Some ( "target/out" )"#
+        );
+        assert_eq!(
+            expand(r#"option_env!("HOME")"#).unwrap(),
+            ":: core :: option :: Option :: None :: < & 'static str >"
+        );
+        assert_eq!(
+            expand(r#"concat!(option_env!("OUT_DIR"))"#).unwrap_err(),
+            "error: expected a literal"
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(
+            expand("include_str!()").unwrap_err(),
+            "error: `include_str!` takes 1 argument"
+        );
+        assert_eq!(
+            expand("include!(x)").unwrap_err(),
+            "error: argument must be a string literal"
+        );
+        assert!(expand(r#"include_bytes!("missing")"#)
+            .unwrap_err()
+            .starts_with("error: couldn't read \"src/missing\""));
+        assert_eq!(byte_string(b"a\"\\\xff\n"), r#"b"a\"\\\xff\x0a""#);
+    }
+
+    #[test]
+    fn test_stringify() {
+        assert_eq!(
+            expand("stringify!(a + b, f(x), 'c')").unwrap(),
+            r#""a + b, f(x), 'c'""#
+        );
+        assert_eq!(expand("stringify!()").unwrap(), r#""""#);
+    }
+}
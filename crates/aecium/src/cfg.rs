@@ -0,0 +1,225 @@
+use std::{collections::HashSet, str::FromStr};
+
+use ra_ap_parser::SyntaxKind;
+
+use crate::{
+    attr::{split_args, unquote, Token},
+    name::{Name, Names},
+};
+
+/// A single rustc-style `--cfg` option, like `unix` or `feature="std"`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CfgFlag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for CfgFlag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => {
+                let value = unquote(value.trim())
+                    .ok_or_else(|| format!("`--cfg` value must be a string literal: {value}"))?;
+                (key.trim(), Some(value))
+            }
+            None => (s.trim(), None),
+        };
+        let valid = key
+            .chars()
+            .next()
+            .is_some_and(|c| c == '_' || c.is_alphabetic())
+            && key.chars().all(|c| c == '_' || c.is_alphanumeric());
+        if !valid {
+            return Err(format!("invalid `--cfg` name: {key:?}"));
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            value,
+        })
+    }
+}
+
+/// A configuration predicate, as found in `#[cfg(...)]`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum CfgExpr {
+    Bool(bool),
+    Option(Name, Option<Name>),
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    /// Parse the predicate of a `cfg(...)` attribute from its tokens, including the `cfg` itself.
+    pub fn parse_attr(names: &mut Names, tokens: &[Token]) -> Option<Self> {
+        match split_args(tokens)?.as_slice() {
+            [predicate] => Self::parse(names, predicate),
+            _ => None,
+        }
+    }
+
+    /// Parse a predicate that spans exactly the given tokens.
+    pub fn parse(names: &mut Names, tokens: &[Token]) -> Option<Self> {
+        let mut parser = CfgParser { names, tokens };
+        let expr = parser.expr()?;
+        parser.tokens.is_empty().then_some(expr)
+    }
+}
+
+struct CfgParser<'a, 'b> {
+    names: &'a mut Names,
+    tokens: &'b [Token],
+}
+
+impl CfgParser<'_, '_> {
+    fn next(&mut self) -> Option<Token> {
+        let (&first, rest) = self.tokens.split_first()?;
+        self.tokens = rest;
+        Some(first)
+    }
+
+    fn eat(&mut self, kind: SyntaxKind) -> bool {
+        match self.tokens.first() {
+            Some(token) if token.kind == kind => {
+                self.tokens = &self.tokens[1..];
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self) -> Option<CfgExpr> {
+        let token = self.next()?;
+        match token.kind {
+            SyntaxKind::TRUE_KW => return Some(CfgExpr::Bool(true)),
+            SyntaxKind::FALSE_KW => return Some(CfgExpr::Bool(false)),
+            SyntaxKind::IDENT => {}
+            _ => return None,
+        }
+        if self.eat(SyntaxKind::EQ) {
+            let value = self.next()?;
+            if value.kind != SyntaxKind::STRING {
+                return None;
+            }
+            let value = unquote(self.names.get(value.text))?;
+            let value = self.names.make(&value);
+            return Some(CfgExpr::Option(token.text, Some(value)));
+        }
+        if !self.eat(SyntaxKind::L_PAREN) {
+            return Some(CfgExpr::Option(token.text, None));
+        }
+        let mut args = Vec::new();
+        while !self.eat(SyntaxKind::R_PAREN) {
+            args.push(self.expr()?);
+            if !self.eat(SyntaxKind::COMMA) {
+                if !self.eat(SyntaxKind::R_PAREN) {
+                    return None;
+                }
+                break;
+            }
+        }
+        match self.names.get(token.text) {
+            "all" => Some(CfgExpr::All(args)),
+            "any" => Some(CfgExpr::Any(args)),
+            "not" if args.len() == 1 => Some(CfgExpr::Not(Box::new(args.pop().unwrap()))),
+            _ => None,
+        }
+    }
+}
+
+/// The set of enabled configuration options for a crate.
+pub struct CfgSet {
+    options: HashSet<(Name, Option<Name>)>,
+}
+
+impl CfgSet {
+    pub fn new(names: &mut Names, flags: &[CfgFlag]) -> Self {
+        let mut cfg = Self {
+            options: HashSet::new(),
+        };
+        for flag in flags {
+            let key = names.make(&flag.key);
+            let value = flag.value.as_deref().map(|value| names.make(value));
+            cfg.options.insert((key, value));
+        }
+        cfg
+    }
+
+    pub fn eval(&self, expr: &CfgExpr) -> bool {
+        match expr {
+            &CfgExpr::Bool(b) => b,
+            &CfgExpr::Option(key, value) => self.options.contains(&(key, value)),
+            CfgExpr::All(exprs) => exprs.iter().all(|expr| self.eval(expr)),
+            CfgExpr::Any(exprs) => exprs.iter().any(|expr| self.eval(expr)),
+            CfgExpr::Not(expr) => !self.eval(expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ra_ap_parser::{Edition, LexedStr, SyntaxKind};
+    use ra_ap_rustc_index::Idx;
+
+    use crate::{
+        attr::Token,
+        cfg::{CfgExpr, CfgFlag, CfgSet},
+        hygiene::SyntaxContext,
+        name::Names,
+        source::SourceLoc,
+    };
+
+    fn tokens(names: &mut Names, text: &str) -> Vec<Token> {
+        let lexed = LexedStr::new(Edition::Edition2021, text);
+        (0..lexed.len())
+            .filter(|&i| !lexed.kind(i).is_trivia() && lexed.kind(i) != SyntaxKind::EOF)
+            .map(|i| Token {
+                kind: lexed.kind(i),
+                text: names.make(lexed.text(i)),
+                loc: SourceLoc::new(lexed.text_start(i)),
+                ctx: SyntaxContext::ROOT,
+            })
+            .collect()
+    }
+
+    fn eval(flags: &[&str], attr: &str) -> Option<bool> {
+        let mut names = Names::new();
+        let flags: Vec<CfgFlag> = flags.iter().map(|flag| flag.parse().unwrap()).collect();
+        let cfg = CfgSet::new(&mut names, &flags);
+        let tokens = tokens(&mut names, attr);
+        let expr = CfgExpr::parse_attr(&mut names, &tokens)?;
+        Some(cfg.eval(&expr))
+    }
+
+    #[test]
+    fn test_flag() {
+        let flag: CfgFlag = r#"feature="std""#.parse().unwrap();
+        assert_eq!(flag.key, "feature");
+        assert_eq!(flag.value.as_deref(), Some("std"));
+        assert!("feature=std".parse::<CfgFlag>().is_err());
+        assert!("1x".parse::<CfgFlag>().is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let flags = ["unix", r#"feature="std""#];
+        assert_eq!(eval(&flags, "cfg(unix)"), Some(true));
+        assert_eq!(eval(&flags, "cfg(windows)"), Some(false));
+        assert_eq!(eval(&flags, r#"cfg(feature = "std")"#), Some(true));
+        assert_eq!(eval(&flags, r#"cfg(feature = "alloc")"#), Some(false));
+        assert_eq!(eval(&flags, "cfg(all(unix, not(windows)))"), Some(true));
+        assert_eq!(eval(&flags, "cfg(any(windows, false))"), Some(false));
+        assert_eq!(eval(&flags, "cfg(all())"), Some(true));
+        assert_eq!(eval(&flags, "cfg(any())"), Some(false));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(eval(&[], "cfg(not(a, b))"), None);
+        assert_eq!(eval(&[], "cfg(feature = std)"), None);
+        assert_eq!(eval(&[], "cfg(a b)"), None);
+        assert_eq!(eval(&[], "cfg"), None);
+    }
+}
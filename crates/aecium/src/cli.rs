@@ -1,29 +1,248 @@
-use std::process::ExitCode;
+use std::{path::Path, process::ExitCode};
 
 use clap::Parser;
+use ra_ap_parser::Edition;
 
-use crate::syntax::Tree;
+use crate::{
+    cfg::CfgFlag,
+    krate::{Crate, Extern},
+    manifest::{self, Package, TargetKind},
+    syntax::Tree,
+    sysroot::Sysroot,
+    target::Target,
+};
 
 #[derive(Debug, Parser)]
 struct Cli {
     /// Path to the crate root.
-    root: String,
+    #[clap(required_unless_present = "manifest_path")]
+    root: Option<String>,
 
+    #[clap(long, required_unless_present = "manifest_path")]
+    edition: Option<Edition>,
+
+    /// Path to a `Cargo.toml`, to check all of its targets instead of a single crate root.
+    #[clap(long, conflicts_with_all = ["root", "edition"])]
+    manifest_path: Option<String>,
+
+    /// Comma-separated list of package features to enable.
+    #[clap(long, value_delimiter = ',', requires = "manifest_path")]
+    features: Vec<String>,
+
+    /// Enable all package features.
+    #[clap(long, requires = "manifest_path")]
+    all_features: bool,
+
+    /// Don't enable the package's `default` feature.
+    #[clap(long, requires = "manifest_path")]
+    no_default_features: bool,
+
+    /// Enable a configuration option, like `unix` or `feature="std"`.
+    #[clap(long)]
+    cfg: Vec<CfgFlag>,
+
+    /// Target triple or path to a JSON target spec, whose builtin `cfg` options are enabled.
+    #[clap(long)]
+    target: Option<String>,
+
+    /// Check as in Cargo's `release` profile, without the `debug_assertions` option that the
+    /// default `dev` profile enables.
+    #[clap(long)]
+    release: bool,
+
+    /// Add a dependency, like `name=path/to/lib.rs` or `name=path/to/Cargo.toml`. A crate root
+    /// is checked with the same `--edition` and `--target` as the main crate, but not its
+    /// `--cfg` options.
+    #[clap(long = "extern", value_parser = parse_extern, conflicts_with = "manifest_path")]
+    externs: Vec<(String, String)>,
+
+    /// Set an environment variable for `env!` and `option_env!`, like `OUT_DIR=path/to/dir`.
+    #[clap(long = "env", value_parser = parse_env)]
+    env: Vec<(String, String)>,
+
+    /// Let `env!` and `option_env!` read the variables of the environment this runs in too.
+    #[clap(long)]
+    inherit_env: bool,
+
+    /// Sysroot whose `rust-src` component provides `core`, `alloc` and `std`. Defaults to that of
+    /// the `rustc` on the `PATH`, if it has `rust-src`.
     #[clap(long)]
-    edition: ra_ap_parser::Edition,
+    sysroot: Option<String>,
+}
+
+fn parse_extern(s: &str) -> Result<(String, String), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name=path`: {s:?}"))?;
+    Ok((name.to_owned(), path.to_owned()))
+}
+
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `KEY=VALUE`: {s:?}"))?;
+    Ok((key.to_owned(), value.to_owned()))
+}
+
+fn fail(message: String) -> ExitCode {
+    eprintln!("{message}");
+    ExitCode::FAILURE
+}
+
+fn add_crate(
+    tree: &mut Tree,
+    name: &str,
+    edition: Edition,
+    cfg: &[CfgFlag],
+    env: &[(String, String)],
+    root: &Path,
+    deps: &[(String, Extern)],
+) -> Result<Crate, ExitCode> {
+    let path = root
+        .to_str()
+        .ok_or_else(|| fail(format!("crate root {root:?} is not valid UTF-8")))?;
+    tree.add_crate(name, edition, cfg, env, path, deps)
+        .map_err(|e| fail(format!("failed to read crate root {root:?}: {e}")))
+}
+
+/// Add the libraries of a resolved package graph to the tree, along with the binaries of the root
+/// package if `bins` is set. Returns the root package's library, if it has one.
+///
+/// Each crate gets the environment variables Cargo sets for its package on top of `env`.
+fn add_packages(
+    tree: &mut Tree,
+    packages: &[Package],
+    cfg: &[CfgFlag],
+    env: &[(String, String)],
+    bins: bool,
+) -> Result<Option<Crate>, ExitCode> {
+    // Dependencies come before their dependents, so the root package is last.
+    let root = packages.len() - 1;
+    let mut libs: Vec<Option<Crate>> = Vec::new();
+    for (i, package) in packages.iter().enumerate() {
+        let mut cfg = cfg.to_vec();
+        cfg.extend(package.features.iter().cloned());
+        let mut env = env.to_vec();
+        env.extend(package.manifest.env.iter().cloned());
+        let mut deps: Vec<(String, Extern)> = package
+            .deps
+            .iter()
+            .filter_map(|(name, i)| match i {
+                Some(i) => Some((name.clone(), Extern::Crate(libs[*i]?))),
+                None => Some((name.clone(), Extern::Opaque)),
+            })
+            .collect();
+        let lib = match package.manifest.lib() {
+            Some(lib) => {
+                let mut deps = deps.clone();
+                if lib.proc_macro {
+                    deps.push(("proc_macro".to_owned(), Extern::Opaque));
+                }
+                let krate = add_crate(tree, &lib.name, lib.edition, &cfg, &env, &lib.root, &deps)?;
+                Some(krate)
+            }
+            None => None,
+        };
+        libs.push(lib);
+        if !bins || i != root {
+            continue;
+        }
+        if let (Some(lib), Some(krate)) = (package.manifest.lib(), lib) {
+            deps.push((lib.name.clone(), Extern::Crate(krate)));
+        }
+        for target in &package.manifest.targets {
+            if target.kind == TargetKind::Bin {
+                add_crate(
+                    tree,
+                    &target.name.replace('-', "_"),
+                    target.edition,
+                    &cfg,
+                    &env,
+                    &target.root,
+                    &deps,
+                )?;
+            }
+        }
+    }
+    Ok(libs[root])
 }
 
 fn cli_result() -> Result<(), ExitCode> {
     let args = Cli::parse();
-    let mut tree = Tree::new(args.edition, &args.root).map_err(|e| {
-        eprintln!("failed to read crate root: {e}");
-        ExitCode::FAILURE
-    })?;
-    tree.expand().map_err(|e| {
-        eprintln!("failed to expand crate: {e}");
-        ExitCode::FAILURE
-    })?;
+    let target_cfg = match &args.target {
+        Some(target) => Target::load(target).map_err(fail)?.cfg(),
+        None => Vec::new(),
+    };
+    // The standard library is built without `debug_assertions`, whatever the profile.
+    let mut crate_cfg = target_cfg.clone();
+    if !args.release {
+        crate_cfg.push(CfgFlag {
+            key: "debug_assertions".to_owned(),
+            value: None,
+        });
+    }
+    let mut cfg = crate_cfg.clone();
+    cfg.extend(args.cfg);
+    let sysroot = match &args.sysroot {
+        Some(sysroot) => Some(Sysroot::new(Path::new(sysroot)).map_err(fail)?),
+        None => Sysroot::detect(),
+    };
+    let mut env = Vec::new();
+    if args.inherit_env {
+        let vars = std::env::vars_os();
+        env.extend(
+            vars.filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            }),
+        );
+    }
+    env.extend(args.env);
+    let mut tree = Tree::new();
+    if let Some(sysroot) = &sysroot {
+        tree.add_sysroot(sysroot, &target_cfg)
+            .map_err(|e| fail(format!("failed to read the standard library: {e}")))?;
+    }
+    match &args.manifest_path {
+        Some(manifest_path) => {
+            let packages = manifest::resolve(
+                Path::new(manifest_path),
+                &args.features,
+                args.all_features,
+                args.no_default_features,
+            )
+            .map_err(fail)?;
+            add_packages(&mut tree, &packages, &cfg, &env, true)?;
+        }
+        None => {
+            let edition = args.edition.unwrap();
+            let mut deps = Vec::new();
+            for (name, path) in &args.externs {
+                let path = Path::new(path);
+                let krate = if path.extension().is_some_and(|ext| ext == "toml") {
+                    let packages = manifest::resolve(path, &[], false, false).map_err(fail)?;
+                    add_packages(&mut tree, &packages, &crate_cfg, &env, false)?
+                        .ok_or_else(|| fail(format!("no library target in {path:?}")))?
+                } else {
+                    // Without a manifest to say otherwise, it shares the main crate's edition.
+                    add_crate(&mut tree, name, edition, &crate_cfg, &env, path, &[])?
+                };
+                deps.push((name.clone(), Extern::Crate(krate)));
+            }
+            let root = Path::new(args.root.as_deref().unwrap());
+            // Like rustc, name the crate after its root file.
+            let stem = root
+                .file_stem()
+                .ok_or_else(|| fail(format!("crate root {root:?} is not a file")))?;
+            let name = stem.to_string_lossy().replace('-', "_");
+            add_crate(&mut tree, &name, edition, &cfg, &env, root, &deps)?;
+        }
+    }
+    tree.expand()
+        .map_err(|e| fail(format!("failed to expand crate: {e}")))?;
     tree.print();
+    if tree.report() {
+        return Err(ExitCode::FAILURE);
+    }
     Ok(())
 }
 
@@ -0,0 +1,803 @@
+use ra_ap_parser::{Edition, LexedStr, SyntaxKind};
+
+use crate::{
+    attr::Token,
+    diagnostic::Diagnostic,
+    hygiene::SyntaxContext,
+    name::{sym, Names},
+    source::SourceLoc,
+};
+
+/// A trait of the standard library whose derive the compiler implements itself, like `Clone`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuiltinDerive {
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Eq,
+    Hash,
+    Ord,
+    PartialEq,
+    PartialOrd,
+}
+
+impl BuiltinDerive {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "Clone" => Self::Clone,
+            "Copy" => Self::Copy,
+            "Debug" => Self::Debug,
+            "Default" => Self::Default,
+            "Eq" => Self::Eq,
+            "Hash" => Self::Hash,
+            "Ord" => Self::Ord,
+            "PartialEq" => Self::PartialEq,
+            "PartialOrd" => Self::PartialOrd,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clone => "Clone",
+            Self::Copy => "Copy",
+            Self::Debug => "Debug",
+            Self::Default => "Default",
+            Self::Eq => "Eq",
+            Self::Hash => "Hash",
+            Self::Ord => "Ord",
+            Self::PartialEq => "PartialEq",
+            Self::PartialOrd => "PartialOrd",
+        }
+    }
+
+    /// The path of the trait, which is what the generated code names it by.
+    fn path(self) -> &'static str {
+        match self {
+            Self::Clone => ":: core :: clone :: Clone",
+            Self::Copy => ":: core :: marker :: Copy",
+            Self::Debug => ":: core :: fmt :: Debug",
+            Self::Default => ":: core :: default :: Default",
+            Self::Eq => ":: core :: cmp :: Eq",
+            Self::Hash => ":: core :: hash :: Hash",
+            Self::Ord => ":: core :: cmp :: Ord",
+            Self::PartialEq => ":: core :: cmp :: PartialEq",
+            Self::PartialOrd => ":: core :: cmp :: PartialOrd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AdtKind {
+    Struct,
+    Enum,
+    Union,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ParamKind {
+    Lifetime,
+    Type,
+    Const,
+}
+
+/// A generic parameter of the item a derive is applied to.
+struct Param<'a> {
+    kind: ParamKind,
+    /// The tokens of the parameter, without any default.
+    tokens: &'a [Token],
+    name: Token,
+}
+
+/// The fields of a struct, union or variant.
+enum Fields {
+    Unit,
+    Tuple(usize),
+    /// The names of the fields.
+    Record(Vec<Token>),
+}
+
+/// A variant of an enum, or the only one of a struct or union, which has no name.
+struct Variant {
+    name: Option<Token>,
+    fields: Fields,
+    /// Whether the variant has a `#[default]` attribute.
+    default: bool,
+}
+
+/// The parts of a struct, enum or union that its derives need.
+struct Adt<'a> {
+    kind: AdtKind,
+    name: Token,
+    params: Vec<Param<'a>>,
+    /// The `where` clause, if any, including the keyword.
+    where_clause: &'a [Token],
+    variants: Vec<Variant>,
+}
+
+/// Expand a built-in derive whose path in the derive attribute is at `loc`, with the tokens of the
+/// struct, enum or union it is applied to after its outer attributes as `item`, to an `impl` of
+/// the trait for it whose own tokens are in the syntax context `ctx`.
+pub fn expand(
+    names: &mut Names,
+    derive: BuiltinDerive,
+    item: &[Token],
+    loc: SourceLoc,
+    ctx: SyntaxContext,
+) -> Result<Vec<Token>, Diagnostic> {
+    // The parser has already reported any syntax errors in an item that can't be made sense of.
+    let Some(adt) = parse(item) else {
+        return Ok(Vec::new());
+    };
+    if adt.kind == AdtKind::Union && !matches!(derive, BuiltinDerive::Clone | BuiltinDerive::Copy) {
+        return Err(Diagnostic::error(
+            loc,
+            "this trait cannot be derived for unions",
+        ));
+    }
+    let mut b = Builder {
+        names,
+        loc,
+        ctx,
+        tokens: Vec::new(),
+    };
+    b.header(&adt, derive);
+    match derive {
+        BuiltinDerive::Clone => {
+            b.push("fn clone ( & self ) -> Self {");
+            if adt.kind == AdtKind::Union {
+                // Only `Copy` unions can be cloned, which the `impl` of `Copy` requires.
+                b.push("* self");
+            } else {
+                b.each_variant(&adt, |b, variant| {
+                    b.construct(variant, |b, i| {
+                        b.push(&format!(
+                            ":: core :: clone :: Clone :: clone ( __self_{i} )"
+                        ));
+                    });
+                });
+            }
+            b.push("}");
+        }
+        BuiltinDerive::Copy | BuiltinDerive::Eq => {}
+        BuiltinDerive::Debug => {
+            b.push("fn fmt ( & self , f : & mut :: core :: fmt :: Formatter < '_ > )");
+            b.push("-> :: core :: fmt :: Result {");
+            b.each_variant(&adt, |b, variant| {
+                let name = match variant.name {
+                    Some(name) => b.names.get(name.text),
+                    None => b.names.get(adt.name.text),
+                };
+                let name = format!("{:?}", name.strip_prefix("r#").unwrap_or(name));
+                match &variant.fields {
+                    Fields::Unit => b.push(&format!(
+                        ":: core :: fmt :: Formatter :: write_str ( f , {name} )"
+                    )),
+                    Fields::Tuple(n) => {
+                        b.push(&format!(
+                            ":: core :: fmt :: Formatter :: debug_tuple ( f , {name} )"
+                        ));
+                        for i in 0..*n {
+                            b.push(&format!(". field ( __self_{i} )"));
+                        }
+                        b.push(". finish ( )");
+                    }
+                    Fields::Record(fields) => {
+                        b.push(&format!(
+                            ":: core :: fmt :: Formatter :: debug_struct ( f , {name} )"
+                        ));
+                        for (i, field) in fields.iter().enumerate() {
+                            let field = b.names.get(field.text);
+                            let field = format!("{:?}", field.strip_prefix("r#").unwrap_or(field));
+                            b.push(&format!(". field ( {field} , __self_{i} )"));
+                        }
+                        b.push(". finish ( )");
+                    }
+                }
+            });
+            b.push("}");
+        }
+        BuiltinDerive::Default => {
+            let variant = match adt.kind {
+                AdtKind::Enum => default_variant(&adt, loc)?,
+                _ => &adt.variants[0],
+            };
+            b.push("fn default ( ) -> Self {");
+            b.construct(variant, |b, _| {
+                b.push(":: core :: default :: Default :: default ( )");
+            });
+            b.push("}");
+        }
+        BuiltinDerive::Hash => {
+            b.push("fn hash < __H : :: core :: hash :: Hasher > ( & self , state : & mut __H ) {");
+            if adt.variants.len() > 1 {
+                b.discriminant(&adt, "self", "self");
+                b.push(":: core :: hash :: Hash :: hash ( & __self_discr , state ) ;");
+            }
+            b.each_variant(&adt, |b, variant| {
+                b.push("{");
+                for i in 0..variant.fields.len() {
+                    b.push(&format!(
+                        ":: core :: hash :: Hash :: hash ( __self_{i} , state ) ;"
+                    ));
+                }
+                b.push("}");
+            });
+            b.push("}");
+        }
+        BuiltinDerive::PartialEq => {
+            b.push("fn eq ( & self , other : & Self ) -> bool {");
+            b.each_pair(&adt, "false", |b, variant| {
+                b.push("true");
+                for i in 0..variant.fields.len() {
+                    b.push(&format!("&& __self_{i} == __arg1_{i}"));
+                }
+            });
+            b.push("}");
+        }
+        BuiltinDerive::PartialOrd | BuiltinDerive::Ord => {
+            let ord = derive == BuiltinDerive::Ord;
+            let (cmp, equal) = match ord {
+                true => (
+                    ":: core :: cmp :: Ord :: cmp",
+                    ":: core :: cmp :: Ordering :: Equal",
+                ),
+                false => (
+                    ":: core :: cmp :: PartialOrd :: partial_cmp",
+                    ":: core :: option :: Option :: Some ( :: core :: cmp :: Ordering :: Equal )",
+                ),
+            };
+            match ord {
+                true => {
+                    b.push("fn cmp ( & self , other : & Self ) -> :: core :: cmp :: Ordering {")
+                }
+                false => b.push(
+                    "fn partial_cmp ( & self , other : & Self ) \
+                     -> :: core :: option :: Option < :: core :: cmp :: Ordering > {",
+                ),
+            }
+            // Different variants compare like the order they are declared in.
+            if adt.variants.len() > 1 {
+                b.discriminant(&adt, "self", "self");
+                b.discriminant(&adt, "other", "arg1");
+            }
+            let different = format!("{cmp} ( & __self_discr , & __arg1_discr )");
+            b.each_pair(&adt, &different, |b, variant| {
+                // Compare the fields in order until one of them isn't equal.
+                let n = variant.fields.len();
+                if n == 0 {
+                    b.push(equal);
+                }
+                for i in 0..n {
+                    if i + 1 < n {
+                        b.push("match");
+                    }
+                    b.push(&format!("{cmp} ( __self_{i} , __arg1_{i} )"));
+                    if i + 1 < n {
+                        b.push(&format!("{{ {equal} =>"));
+                    }
+                }
+                for _ in 1..n {
+                    b.push(", cmp => cmp , }");
+                }
+            });
+            b.push("}");
+        }
+    }
+    b.push("}");
+    Ok(b.tokens)
+}
+
+/// The variant of an enum with the `#[default]` attribute, which must be a unit variant.
+fn default_variant<'a>(adt: &'a Adt, loc: SourceLoc) -> Result<&'a Variant, Diagnostic> {
+    let mut defaults = adt.variants.iter().filter(|variant| variant.default);
+    let Some(variant) = defaults.next() else {
+        return Err(Diagnostic::error(loc, "no default declared")
+            .help("make a unit variant default by placing `#[default]` above it"));
+    };
+    let variant_loc = |variant: &Variant| variant.name.unwrap().loc;
+    let others: Vec<_> = defaults.collect();
+    if !others.is_empty() {
+        let diagnostic = Diagnostic::error(loc, "multiple declared defaults")
+            .label(variant_loc(variant), "first default");
+        return Err(others.into_iter().fold(diagnostic, |diagnostic, other| {
+            diagnostic.label(variant_loc(other), "additional default")
+        }));
+    }
+    if !matches!(variant.fields, Fields::Unit) {
+        return Err(Diagnostic::error(
+            variant_loc(variant),
+            "the `#[default]` attribute may only be used on unit enum variants",
+        )
+        .help("consider a manual implementation of `Default`"));
+    }
+    Ok(variant)
+}
+
+impl Fields {
+    fn len(&self) -> usize {
+        match self {
+            Self::Unit => 0,
+            Self::Tuple(n) => *n,
+            Self::Record(fields) => fields.len(),
+        }
+    }
+}
+
+/// The tokens of an expansion being generated, which are at the location of the derive unless
+/// they are copied from the item.
+struct Builder<'a> {
+    names: &'a mut Names,
+    loc: SourceLoc,
+    /// The syntax context of the tokens the derive writes out, as opposed to those it copies from
+    /// the item, which keeps the names it binds apart from those in the item.
+    ctx: SyntaxContext,
+    tokens: Vec<Token>,
+}
+
+impl Builder<'_> {
+    /// Add tokens written out with spaces between them.
+    fn push(&mut self, text: &str) {
+        for word in text.split_whitespace() {
+            let kind = match word {
+                "::" => SyntaxKind::COLON2,
+                "->" => SyntaxKind::THIN_ARROW,
+                "=>" => SyntaxKind::FAT_ARROW,
+                "==" => SyntaxKind::EQ2,
+                "&&" => SyntaxKind::AMP2,
+                ".." => SyntaxKind::DOT2,
+                _ => LexedStr::new(Edition::Edition2021, word).kind(0),
+            };
+            self.tokens.push(Token {
+                kind,
+                text: self.names.make(word),
+                loc: self.loc,
+                ctx: self.ctx,
+            });
+        }
+    }
+
+    fn copy(&mut self, tokens: &[Token]) {
+        self.tokens.extend_from_slice(tokens);
+    }
+
+    /// Start an `impl` of the trait for the item, with each type parameter bounded by the trait.
+    fn header(&mut self, adt: &Adt, derive: BuiltinDerive) {
+        let path = derive.path();
+        self.push("impl");
+        if !adt.params.is_empty() {
+            self.push("<");
+            for param in &adt.params {
+                self.copy(param.tokens);
+                if param.kind == ParamKind::Type {
+                    match param.tokens {
+                        [_] => self.push(":"),
+                        [.., last] if last.kind == SyntaxKind::COLON => {}
+                        _ => self.push("+"),
+                    }
+                    self.push(path);
+                }
+                self.push(",");
+            }
+            self.push(">");
+        }
+        self.push(path);
+        self.push("for");
+        self.copy(&[adt.name]);
+        if !adt.params.is_empty() {
+            self.push("<");
+            for param in &adt.params {
+                self.copy(&[param.name]);
+                self.push(",");
+            }
+            self.push(">");
+        }
+        self.copy(adt.where_clause);
+        self.push("{");
+    }
+
+    /// The path of a variant, or `Self` for a struct or union.
+    fn variant_path(&mut self, variant: &Variant) {
+        self.push("Self");
+        if let Some(name) = variant.name {
+            self.push("::");
+            self.copy(&[name]);
+        }
+    }
+
+    /// A pattern for a variant that binds its fields to `__{prefix}_0`, `__{prefix}_1` and so on.
+    fn pattern(&mut self, variant: &Variant, prefix: &str) {
+        self.variant_path(variant);
+        match &variant.fields {
+            Fields::Unit => {}
+            Fields::Tuple(n) => {
+                self.push("(");
+                for i in 0..*n {
+                    self.push(&format!("__{prefix}_{i} ,"));
+                }
+                self.push(")");
+            }
+            Fields::Record(fields) => {
+                self.push("{");
+                for (i, &field) in fields.iter().enumerate() {
+                    self.copy(&[field]);
+                    self.push(&format!(": __{prefix}_{i} ,"));
+                }
+                self.push("}");
+            }
+        }
+    }
+
+    /// An expression constructing a variant, with the value of each field from `value`.
+    fn construct(&mut self, variant: &Variant, mut value: impl FnMut(&mut Self, usize)) {
+        self.variant_path(variant);
+        let (open, close) = match &variant.fields {
+            Fields::Unit => return,
+            Fields::Tuple(_) => ("(", ")"),
+            Fields::Record(_) => ("{", "}"),
+        };
+        self.push(open);
+        for i in 0..variant.fields.len() {
+            if let Fields::Record(fields) = &variant.fields {
+                self.copy(&[fields[i]]);
+                self.push(":");
+            }
+            value(self, i);
+            self.push(",");
+        }
+        self.push(close);
+    }
+
+    /// A `match` on `self` with an arm for each variant, whose fields are bound to `__self_0` and
+    /// so on in the expression from `arm`.
+    fn each_variant(&mut self, adt: &Adt, mut arm: impl FnMut(&mut Self, &Variant)) {
+        if adt.variants.is_empty() {
+            self.push("match * self { }");
+            return;
+        }
+        self.push("match self {");
+        for variant in &adt.variants {
+            self.pattern(variant, "self");
+            self.push("=>");
+            arm(self, variant);
+            self.push(",");
+        }
+        self.push("}");
+    }
+
+    /// A `match` on `self` and `other` with an arm for each variant that both are, whose fields
+    /// are bound to `__self_0` and `__arg1_0` and so on in the expression from `arm`, and an arm
+    /// with the `different` expression for when they are different variants.
+    fn each_pair(&mut self, adt: &Adt, different: &str, mut arm: impl FnMut(&mut Self, &Variant)) {
+        if adt.variants.is_empty() {
+            self.push("match * self { }");
+            return;
+        }
+        self.push("match ( self , other ) {");
+        for variant in &adt.variants {
+            self.push("(");
+            self.pattern(variant, "self");
+            self.push(",");
+            self.pattern(variant, "arg1");
+            self.push(") =>");
+            arm(self, variant);
+            self.push(",");
+        }
+        if adt.variants.len() > 1 {
+            self.push(&format!("_ => {different} ,"));
+        }
+        self.push("}");
+    }
+
+    /// Bind the index of the variant that `receiver` is to `__{prefix}_discr`.
+    fn discriminant(&mut self, adt: &Adt, receiver: &str, prefix: &str) {
+        self.push(&format!("let __{prefix}_discr = match {receiver} {{"));
+        for (i, variant) in adt.variants.iter().enumerate() {
+            self.variant_path(variant);
+            match variant.fields {
+                Fields::Unit => {}
+                Fields::Tuple(_) => self.push("( .. )"),
+                Fields::Record(_) => self.push("{ .. }"),
+            }
+            self.push(&format!("=> {i}isize ,"));
+        }
+        self.push("} ;");
+    }
+}
+
+/// Read the parts of a struct, enum or union from its tokens.
+fn parse(tokens: &[Token]) -> Option<Adt<'_>> {
+    let start = tokens.iter().position(|token| {
+        matches!(
+            token.kind,
+            SyntaxKind::STRUCT_KW | SyntaxKind::ENUM_KW | SyntaxKind::UNION_KW
+        )
+    })?;
+    let kind = match tokens[start].kind {
+        SyntaxKind::STRUCT_KW => AdtKind::Struct,
+        SyntaxKind::ENUM_KW => AdtKind::Enum,
+        _ => AdtKind::Union,
+    };
+    let (&name, mut rest) = tokens[start + 1..].split_first()?;
+    let mut params = Vec::new();
+    if rest.first()?.kind == SyntaxKind::L_ANGLE {
+        let len = group_len(rest)?;
+        params = split_top(&rest[1..len - 1])
+            .into_iter()
+            .filter_map(param)
+            .collect();
+        rest = &rest[len..];
+    }
+    let mut tuple = None;
+    if kind == AdtKind::Struct && rest.first()?.kind == SyntaxKind::L_PAREN {
+        let len = group_len(rest)?;
+        tuple = Some(&rest[1..len - 1]);
+        rest = &rest[len..];
+    }
+    let where_len = rest
+        .iter()
+        .position(|token| matches!(token.kind, SyntaxKind::L_CURLY | SyntaxKind::SEMICOLON))
+        .unwrap_or(rest.len());
+    let (where_clause, rest) = rest.split_at(where_len);
+    let body = match rest.first() {
+        Some(token) if token.kind == SyntaxKind::L_CURLY => Some(&rest[1..group_len(rest)? - 1]),
+        _ => None,
+    };
+    let variants = match (kind, tuple, body) {
+        (AdtKind::Enum, _, body) => split_top(body?).into_iter().filter_map(variant).collect(),
+        (_, Some(tuple), _) => vec![Variant {
+            name: None,
+            fields: tuple_fields(tuple),
+            default: false,
+        }],
+        (_, None, Some(body)) => vec![Variant {
+            name: None,
+            fields: record_fields(body),
+            default: false,
+        }],
+        (_, None, None) => vec![Variant {
+            name: None,
+            fields: Fields::Unit,
+            default: false,
+        }],
+    };
+    Some(Adt {
+        kind,
+        name,
+        params,
+        where_clause,
+        variants,
+    })
+}
+
+fn param(tokens: &[Token]) -> Option<Param<'_>> {
+    let (tokens, _) = strip_attrs(tokens);
+    let tokens = &tokens[..find_top(tokens, SyntaxKind::EQ).unwrap_or(tokens.len())];
+    let (kind, name) = match tokens.first()?.kind {
+        SyntaxKind::LIFETIME_IDENT => (ParamKind::Lifetime, tokens[0]),
+        SyntaxKind::CONST_KW => (ParamKind::Const, *tokens.get(1)?),
+        _ => (ParamKind::Type, tokens[0]),
+    };
+    Some(Param { kind, tokens, name })
+}
+
+fn variant(tokens: &[Token]) -> Option<Variant> {
+    let (tokens, default) = strip_attrs(tokens);
+    let (&name, rest) = strip_vis(tokens).split_first()?;
+    let fields = match rest.first().map(|token| token.kind) {
+        Some(SyntaxKind::L_PAREN) => tuple_fields(&rest[1..group_len(rest)? - 1]),
+        Some(SyntaxKind::L_CURLY) => record_fields(&rest[1..group_len(rest)? - 1]),
+        _ => Fields::Unit,
+    };
+    Some(Variant {
+        name: Some(name),
+        fields,
+        default,
+    })
+}
+
+fn tuple_fields(tokens: &[Token]) -> Fields {
+    let fields = split_top(tokens);
+    Fields::Tuple(
+        fields
+            .into_iter()
+            .filter(|field| !strip_attrs(field).0.is_empty())
+            .count(),
+    )
+}
+
+fn record_fields(tokens: &[Token]) -> Fields {
+    let fields = split_top(tokens).into_iter().filter_map(|field| {
+        let (field, _) = strip_attrs(field);
+        strip_vis(field).first().copied()
+    });
+    Fields::Record(fields.collect())
+}
+
+/// Skip the attributes at the start of a field, variant or generic parameter, returning the rest
+/// and whether one of them is `#[default]`. Those that a `#[cfg]` removed are left without
+/// anything after them.
+fn strip_attrs(mut tokens: &[Token]) -> (&[Token], bool) {
+    let mut default = false;
+    while let [pound, rest @ ..] = tokens {
+        if pound.kind != SyntaxKind::POUND {
+            break;
+        }
+        let Some(len) = group_len(rest) else { break };
+        if let [_, ident, _] = rest[..len] {
+            default |= ident.kind == SyntaxKind::IDENT && ident.text == sym::DEFAULT;
+        }
+        tokens = &rest[len..];
+    }
+    (tokens, default)
+}
+
+/// Skip the visibility at the start of a field.
+fn strip_vis(tokens: &[Token]) -> &[Token] {
+    match tokens {
+        [vis, rest @ ..] if vis.kind == SyntaxKind::PUB_KW => {
+            match rest.first().map(|token| token.kind) {
+                Some(SyntaxKind::L_PAREN) => &rest[group_len(rest).unwrap_or(0)..],
+                _ => rest,
+            }
+        }
+        _ => tokens,
+    }
+}
+
+/// The number of tokens in the delimited group or generic parameter list at the start of
+/// `tokens`, if it is closed.
+fn group_len(tokens: &[Token]) -> Option<usize> {
+    let angle = tokens.first()?.kind == SyntaxKind::L_ANGLE;
+    let (mut depth, mut angles) = (0usize, 0usize);
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            SyntaxKind::L_PAREN | SyntaxKind::L_BRACK | SyntaxKind::L_CURLY => depth += 1,
+            SyntaxKind::R_PAREN | SyntaxKind::R_BRACK | SyntaxKind::R_CURLY => {
+                depth = depth.checked_sub(1)?
+            }
+            SyntaxKind::L_ANGLE if angle && depth == 0 => angles += 1,
+            SyntaxKind::R_ANGLE if angle && depth == 0 => angles = angles.checked_sub(1)?,
+            _ => continue,
+        }
+        if depth == 0 && angles == 0 {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// The position of the first token of the given kind that isn't inside a delimited group or
+/// generic arguments.
+fn find_top(tokens: &[Token], kind: SyntaxKind) -> Option<usize> {
+    let (mut depth, mut angles) = (0usize, 0usize);
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            SyntaxKind::L_PAREN | SyntaxKind::L_BRACK | SyntaxKind::L_CURLY => depth += 1,
+            SyntaxKind::R_PAREN | SyntaxKind::R_BRACK | SyntaxKind::R_CURLY => {
+                depth = depth.saturating_sub(1)
+            }
+            SyntaxKind::L_ANGLE if depth == 0 => angles += 1,
+            SyntaxKind::R_ANGLE if depth == 0 => angles = angles.saturating_sub(1),
+            k if k == kind && depth == 0 && angles == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split tokens at their top-level commas, which can leave empty parts.
+fn split_top(mut tokens: &[Token]) -> Vec<&[Token]> {
+    let mut parts = Vec::new();
+    while let Some(i) = find_top(tokens, SyntaxKind::COMMA) {
+        parts.push(&tokens[..i]);
+        tokens = &tokens[i + 1..];
+    }
+    parts.push(tokens);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use ra_ap_parser::{Edition, SyntaxKind};
+
+    use crate::{
+        attr::Token,
+        derive::{expand, BuiltinDerive},
+        hygiene::SyntaxContext,
+        name::Names,
+        source::Source,
+    };
+
+    /// Expand a derive for an item, returning the text of the generated tokens or the first line of
+    /// the rendered error.
+    fn derive(derive: BuiltinDerive, item: &str) -> Result<String, String> {
+        let mut src = Source::new();
+        let mut names = Names::new();
+        let file = src.read("src/lib.rs", item.as_bytes()).unwrap();
+        let base = src.range(file).start;
+        let mut tokens = Token::lex(&mut names, Edition::Edition2021, base, item);
+        // The parser remaps the contextual keyword, which the lexer leaves as an identifier.
+        for token in &mut tokens {
+            if names.get(token.text) == "union" {
+                token.kind = SyntaxKind::UNION_KW;
+            }
+        }
+        match expand(
+            &mut names,
+            derive,
+            &tokens,
+            tokens[0].loc,
+            SyntaxContext::ROOT,
+        ) {
+            Ok(tokens) => Ok(tokens
+                .iter()
+                .map(|token| names.get(token.text))
+                .collect::<Vec<_>>()
+                .join(" ")),
+            Err(diagnostic) => Err(diagnostic.render(&src).lines().next().unwrap().to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_generics() {
+        assert_eq!(
+            derive(
+                BuiltinDerive::Clone,
+                "pub struct S<'a, T: Copy = u8, const N: usize = 1>(&'a T, [T; N]) where T: Eq;",
+            )
+            .unwrap(),
+            "impl < 'a , T : Copy + :: core :: clone :: Clone , const N : usize , > \
+             :: core :: clone :: Clone for S < 'a , T , N , > where T : Eq { \
+             fn clone ( & self ) -> Self { match self { Self ( __self_0 , __self_1 , ) => \
+             Self ( :: core :: clone :: Clone :: clone ( __self_0 ) , \
+             :: core :: clone :: Clone :: clone ( __self_1 ) , ) , } } }",
+        );
+    }
+
+    #[test]
+    fn test_enum() {
+        assert_eq!(
+            derive(
+                BuiltinDerive::PartialEq,
+                "enum E { A, B { x: Vec<Vec<u8>> } }"
+            )
+            .unwrap(),
+            "impl :: core :: cmp :: PartialEq for E { fn eq ( & self , other : & Self ) -> bool { \
+             match ( self , other ) { ( Self :: A , Self :: A ) => true , \
+             ( Self :: B { x : __self_0 , } , Self :: B { x : __arg1_0 , } ) => \
+             true && __self_0 == __arg1_0 , _ => false , } } }",
+        );
+        assert_eq!(
+            derive(BuiltinDerive::Default, "enum E { A(u8), #[default] B }").unwrap(),
+            "impl :: core :: default :: Default for E { fn default ( ) -> Self { Self :: B } }",
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            derive(BuiltinDerive::Debug, "union U { x: u8 }"),
+            Err("error: this trait cannot be derived for unions".to_owned()),
+        );
+        assert_eq!(
+            derive(BuiltinDerive::Default, "enum E { A, B }"),
+            Err("error: no default declared".to_owned()),
+        );
+        assert_eq!(
+            derive(
+                BuiltinDerive::Default,
+                "enum E { #[default] A, #[default] B }"
+            ),
+            Err("error: multiple declared defaults".to_owned()),
+        );
+        assert_eq!(
+            derive(BuiltinDerive::Default, "enum E { #[default] A(u8) }"),
+            Err(
+                "error: the `#[default]` attribute may only be used on unit enum variants"
+                    .to_owned()
+            ),
+        );
+    }
+}
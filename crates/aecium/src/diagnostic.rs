@@ -0,0 +1,92 @@
+use std::fmt::Write;
+
+use crate::source::{Source, SourceLoc};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Level {
+    Error,
+    Warning,
+}
+
+pub struct Diagnostic {
+    level: Level,
+    code: Option<&'static str>,
+    message: String,
+    loc: SourceLoc,
+    labels: Vec<(SourceLoc, String)>,
+    notes: Vec<String>,
+    help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(loc: SourceLoc, message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Error,
+            code: None,
+            message: message.into(),
+            loc,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn warning(loc: SourceLoc, message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Warning,
+            ..Self::error(loc, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+
+    pub fn loc(&self) -> SourceLoc {
+        self.loc
+    }
+
+    pub fn code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Attach a secondary location to this diagnostic.
+    pub fn label(mut self, loc: SourceLoc, message: impl Into<String>) -> Self {
+        self.labels.push((loc, message.into()));
+        self
+    }
+
+    pub fn note(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(message.into());
+        self
+    }
+
+    pub fn help(mut self, message: impl Into<String>) -> Self {
+        self.help.push(message.into());
+        self
+    }
+
+    pub fn render(&self, src: &Source) -> String {
+        let mut out = String::new();
+        out.push_str(match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        });
+        if let Some(code) = self.code {
+            write!(out, "[{code}]").unwrap();
+        }
+        writeln!(out, ": {}", self.message).unwrap();
+        writeln!(out, "  --> {}", src.describe(self.loc)).unwrap();
+        for (loc, message) in &self.labels {
+            writeln!(out, "  --> {}: {message}", src.describe(*loc)).unwrap();
+        }
+        for message in &self.notes {
+            writeln!(out, "   = note: {message}").unwrap();
+        }
+        for message in &self.help {
+            writeln!(out, "   = help: {message}").unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use derive_rustc_index::Idx;
+use ra_ap_rustc_index::IndexVec;
+
+use crate::{
+    encoding::{Decodable, Decoder, Encodable},
+    path::Path,
+    syntax::Node,
+};
+
+/// The macro expansions an identifier came out of, which decide which local variables, generic
+/// parameters and labels it can refer to.
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+pub struct SyntaxContext(u32);
+
+impl SyntaxContext {
+    /// The context of identifiers written directly in a source file.
+    pub const ROOT: Self = Self(0);
+}
+
+impl Encodable for SyntaxContext {
+    fn push(self, data: &mut Vec<u8>) {
+        self.0.push(data);
+    }
+
+    fn write(self, data: &mut [u8]) {
+        self.0.write(data);
+    }
+}
+
+impl Decodable for SyntaxContext {
+    fn decode(decoder: &mut Decoder) -> Self {
+        Self(u32::decode(decoder))
+    }
+}
+
+/// How much the identifiers a macro produces are kept apart from those around its call.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Transparency {
+    /// Call-site hygiene: identifiers resolve as if they were written at the call.
+    Transparent,
+    /// Mixed-site hygiene, used by `macro_rules!`: local variables and labels resolve at the
+    /// definition, while everything else resolves at the call.
+    SemiTransparent,
+    /// Def-site hygiene: identifiers resolve at the definition.
+    Opaque,
+}
+
+/// The expansion of a macro call, which identifiers that come from its definition are marked with.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Mark {
+    /// The [`SyntaxKind::MACRO_CALL`](ra_ap_parser::SyntaxKind::MACRO_CALL).
+    pub call: Node,
+    /// The definition of the macro, which is where identifiers with the mark resolve.
+    pub def: Node,
+    pub transparency: Transparency,
+}
+
+enum SyntaxContextData {
+    Root,
+    /// Another context with a mark applied to it.
+    Marked {
+        parent: SyntaxContext,
+        mark: Mark,
+        /// The same context without any marks that aren't [`Transparency::Opaque`], which is
+        /// what generic parameters are resolved by.
+        opaque: SyntaxContext,
+    },
+    /// The context of a `$crate` from the expansion of a macro, which refers to the root of the
+    /// crate that defines the macro wherever it ends up, so no marks apply to it.
+    DollarCrate(Path),
+}
+
+/// All syntax contexts, each created once for a given parent and mark.
+pub struct SyntaxContexts {
+    contexts: IndexVec<SyntaxContext, SyntaxContextData>,
+    interned: HashMap<(SyntaxContext, Mark), SyntaxContext>,
+    dollar_crates: HashMap<Path, SyntaxContext>,
+}
+
+impl SyntaxContexts {
+    pub fn new() -> Self {
+        let mut contexts = IndexVec::new();
+        contexts.push(SyntaxContextData::Root);
+        Self {
+            contexts,
+            interned: HashMap::new(),
+            dollar_crates: HashMap::new(),
+        }
+    }
+
+    /// The context of an identifier with the given context once it is produced by an expansion.
+    /// A [`Transparency::Transparent`] mark leaves the context as it was.
+    pub fn apply(&mut self, parent: SyntaxContext, mark: Mark) -> SyntaxContext {
+        if mark.transparency == Transparency::Transparent
+            || matches!(self.contexts[parent], SyntaxContextData::DollarCrate(_))
+        {
+            return parent;
+        }
+        if let Some(&ctx) = self.interned.get(&(parent, mark)) {
+            return ctx;
+        }
+        let opaque_parent = self.opaque(parent);
+        // An opaque mark on an opaque context is kept in that same context.
+        let opaque = match mark.transparency {
+            Transparency::Opaque if opaque_parent == parent => None,
+            Transparency::Opaque => Some(self.apply(opaque_parent, mark)),
+            _ => Some(opaque_parent),
+        };
+        let ctx = self.contexts.next_index();
+        self.contexts.push(SyntaxContextData::Marked {
+            parent,
+            mark,
+            opaque: opaque.unwrap_or(ctx),
+        });
+        self.interned.insert((parent, mark), ctx);
+        ctx
+    }
+
+    /// The context of a `$crate` in the expansion of a macro defined in the crate with the given
+    /// root.
+    pub fn dollar_crate(&mut self, root: Path) -> SyntaxContext {
+        *self
+            .dollar_crates
+            .entry(root)
+            .or_insert_with(|| self.contexts.push(SyntaxContextData::DollarCrate(root)))
+    }
+
+    /// The root of the crate that a `crate` keyword refers to if it came from a `$crate`.
+    pub fn dollar_crate_root(&self, ctx: SyntaxContext) -> Option<Path> {
+        match self.contexts[ctx] {
+            SyntaxContextData::DollarCrate(root) => Some(root),
+            _ => None,
+        }
+    }
+
+    /// The context without any marks that aren't [`Transparency::Opaque`].
+    pub fn opaque(&self, ctx: SyntaxContext) -> SyntaxContext {
+        match self.contexts[ctx] {
+            SyntaxContextData::Marked { opaque, .. } => opaque,
+            _ => ctx,
+        }
+    }
+
+    /// The context an identifier has outside of the definition `def`, where the mark of an
+    /// expansion of that definition no longer applies.
+    pub fn leave(&self, ctx: SyntaxContext, def: Node) -> SyntaxContext {
+        match self.contexts[ctx] {
+            SyntaxContextData::Marked { parent, mark, .. } if mark.def == def => parent,
+            _ => ctx,
+        }
+    }
+}
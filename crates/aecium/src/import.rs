@@ -0,0 +1,958 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    mem,
+};
+
+use derive_rustc_index::Idx;
+use ra_ap_parser::{Edition, SyntaxKind};
+use ra_ap_rustc_index::IndexVec;
+
+use crate::{
+    attr::Token,
+    diagnostic::Diagnostic,
+    hygiene::SyntaxContexts,
+    item::{Item, ItemKind, Items, Namespace, Parent},
+    krate::{Crates, Extern},
+    macros::{MacroCall, MacroRulesId, Macros},
+    name::{sym, Name, Names},
+    path::{Path, Paths},
+    prelude,
+    scope::{Scope, ScopeKind, Scopes},
+    source::SourceLoc,
+    syntax::{Node, Nodes},
+    vis::Visibility,
+};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+pub struct ImportId(u32);
+
+/// A segment of a path in a `use` tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Segment {
+    /// A leading `::`.
+    Root,
+    Crate,
+    /// A `$crate` from the expansion of a macro, which refers to the root of the crate that
+    /// defines the macro.
+    DollarCrate(Path),
+    SelfModule,
+    Super,
+    Name(Name),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImportKind {
+    /// Binds the last segment of the path or its rename, unless renamed to `_`.
+    Single(Option<Name>),
+    Glob,
+}
+
+/// One leaf of a `use` tree, like the `c` in `use a::{b, c};`.
+#[derive(Clone)]
+pub struct Import {
+    pub scope: Scope,
+    pub vis: Visibility,
+    pub path: Vec<(Segment, SourceLoc)>,
+    pub kind: ImportKind,
+    /// The latest `macro_rules!` definition in textual scope, which a single-segment import can
+    /// name.
+    pub macros: Option<MacroRulesId>,
+}
+
+impl Import {
+    fn loc(&self) -> SourceLoc {
+        self.path.last().unwrap().1
+    }
+}
+
+/// The path and kind of one leaf of a `use` tree.
+type Leaf = (Vec<(Segment, SourceLoc)>, ImportKind);
+
+/// Flatten the tokens of a `use` tree into the path and kind of each of its leaves.
+pub fn parse_use_tree(contexts: &SyntaxContexts, tokens: &[Token]) -> Option<Vec<Leaf>> {
+    let mut parser = UseParser {
+        contexts,
+        tokens,
+        leaves: Vec::new(),
+    };
+    parser.tree(&mut Vec::new())?;
+    parser.tokens.is_empty().then_some(parser.leaves)
+}
+
+struct UseParser<'a> {
+    contexts: &'a SyntaxContexts,
+    tokens: &'a [Token],
+    leaves: Vec<Leaf>,
+}
+
+impl UseParser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let (&first, rest) = self.tokens.split_first()?;
+        self.tokens = rest;
+        Some(first)
+    }
+
+    fn eat(&mut self, kind: SyntaxKind) -> Option<Token> {
+        match self.tokens.first() {
+            Some(&token) if token.kind == kind => {
+                self.tokens = &self.tokens[1..];
+                Some(token)
+            }
+            _ => None,
+        }
+    }
+
+    fn tree(&mut self, prefix: &mut Vec<(Segment, SourceLoc)>) -> Option<()> {
+        let len = prefix.len();
+        if len == 0 {
+            if let Some(token) = self.eat(SyntaxKind::COLON2) {
+                prefix.push((Segment::Root, token.loc));
+            }
+        }
+        loop {
+            let token = self.next()?;
+            let segment = match token.kind {
+                SyntaxKind::L_CURLY => {
+                    while self.eat(SyntaxKind::R_CURLY).is_none() {
+                        self.tree(prefix)?;
+                        if self.eat(SyntaxKind::COMMA).is_none() {
+                            self.eat(SyntaxKind::R_CURLY)?;
+                            break;
+                        }
+                    }
+                    break;
+                }
+                SyntaxKind::STAR => {
+                    self.leaves.push((prefix.clone(), ImportKind::Glob));
+                    break;
+                }
+                SyntaxKind::CRATE_KW => match self.contexts.dollar_crate_root(token.ctx) {
+                    Some(root) => Segment::DollarCrate(root),
+                    None => Segment::Crate,
+                },
+                SyntaxKind::SELF_KW => Segment::SelfModule,
+                SyntaxKind::SUPER_KW => Segment::Super,
+                SyntaxKind::IDENT => Segment::Name(token.text),
+                _ => return None,
+            };
+            prefix.push((segment, token.loc));
+            if self.eat(SyntaxKind::COLON2).is_some() {
+                continue;
+            }
+            let name = match self.eat(SyntaxKind::AS_KW) {
+                Some(_) => {
+                    let rename = self.next()?;
+                    match rename.kind {
+                        SyntaxKind::IDENT => Some(rename.text),
+                        SyntaxKind::UNDERSCORE => None,
+                        _ => return None,
+                    }
+                }
+                None => match (segment, prefix.iter().nth_back(1)) {
+                    (Segment::Name(name), _) => Some(name),
+                    // `use a::{self};` binds `a`.
+                    (Segment::SelfModule, Some(&(Segment::Name(name), _))) => Some(name),
+                    _ => None,
+                },
+            };
+            self.leaves.push((prefix.clone(), ImportKind::Single(name)));
+            break;
+        }
+        prefix.truncate(len);
+        Some(())
+    }
+}
+
+/// What a path resolves to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Res {
+    /// A module, including the root of a crate.
+    Module(Path),
+    /// Any other item, including an enum variant.
+    Item(Item),
+    /// Something in a crate whose sources aren't loaded, or that couldn't be resolved.
+    Opaque,
+}
+
+/// A name brought into scope by an import.
+#[derive(Clone, Copy, Debug)]
+pub struct Binding {
+    pub res: Res,
+    pub vis: Visibility,
+    pub import: ImportId,
+}
+
+/// All imports, and the names they bring into scope once resolved.
+pub struct Imports {
+    imports: IndexVec<ImportId, Import>,
+    bindings: HashMap<(Scope, Namespace), HashMap<Name, Binding>>,
+    /// The modules and enums whose names each scope imports with globs.
+    globs: HashMap<Scope, Vec<(Res, Visibility)>>,
+    /// Imports that haven't been resolved yet.
+    pending: Vec<ImportId>,
+    /// The number of pending single imports that could bind each name in each scope.
+    pending_names: HashMap<(Scope, Name), usize>,
+    /// The number of pending glob imports in each scope.
+    pending_globs: HashMap<Scope, usize>,
+}
+
+impl Imports {
+    pub fn new() -> Self {
+        Self {
+            imports: IndexVec::new(),
+            bindings: HashMap::new(),
+            globs: HashMap::new(),
+            pending: Vec::new(),
+            pending_names: HashMap::new(),
+            pending_globs: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, import: Import) -> ImportId {
+        match import.kind {
+            ImportKind::Single(Some(name)) => {
+                *self.pending_names.entry((import.scope, name)).or_default() += 1;
+            }
+            ImportKind::Single(None) => {}
+            ImportKind::Glob => *self.pending_globs.entry(import.scope).or_default() += 1,
+        }
+        let id = self.imports.push(import);
+        self.pending.push(id);
+        id
+    }
+
+    /// Stop counting an import as pending, because it has been resolved or reported.
+    fn settle(&mut self, id: ImportId) {
+        let import = &self.imports[id];
+        let count = match import.kind {
+            ImportKind::Single(Some(name)) => self.pending_names.get_mut(&(import.scope, name)),
+            ImportKind::Single(None) => None,
+            ImportKind::Glob => self.pending_globs.get_mut(&import.scope),
+        };
+        if let Some(count) = count {
+            *count -= 1;
+        }
+    }
+
+    pub fn binding(&self, scope: Scope, ns: Namespace, name: Name) -> Option<Binding> {
+        self.bindings.get(&(scope, ns))?.get(&name).copied()
+    }
+}
+
+/// The result of looking up a name in a scope.
+enum Lookup {
+    /// The name was found, with the visibility it has where it was found.
+    Found(Res, Visibility),
+    NotFound,
+    /// The name could still be brought into scope by a pending import.
+    Indeterminate,
+    /// The name is brought into scope by multiple glob imports that disagree.
+    Ambiguous,
+}
+
+/// Why an import can't be resolved, identifying a segment of its path by index.
+#[derive(Clone, Copy)]
+enum Failure {
+    NotFound(usize),
+    NotModule(usize, Res),
+    /// The segment isn't visible from the import.
+    Private(usize, Res),
+    TooManySupers(usize),
+    Ambiguous(usize),
+    /// The segment could refer to both a crate in the extern prelude and a name in scope.
+    AmbiguousExtern(usize),
+}
+
+enum Stop {
+    Indeterminate,
+    Failed(Failure),
+}
+
+/// Where the next segment of a path is looked up.
+enum Base {
+    /// In the scopes enclosing the import and in the extern prelude, for a 2018 edition path.
+    Lexical,
+    /// In the extern prelude only, after a leading `::` in the 2018 edition.
+    ExternPrelude,
+    In(Res),
+}
+
+/// Resolves imports to a fixpoint, the way rustc does: each pass resolves whatever imports it can,
+/// until no more progress is made and the rest are reported.
+pub struct Resolver<'a> {
+    pub names: &'a Names,
+    pub nodes: &'a Nodes,
+    pub paths: &'a Paths,
+    pub scopes: &'a Scopes,
+    pub items: &'a Items,
+    pub modules: &'a HashMap<Path, Scope>,
+    pub crates: &'a Crates,
+    pub extern_crates: &'a HashMap<Node, Path>,
+    pub macros: &'a Macros,
+    pub imports: &'a mut Imports,
+    pub unexpanded: &'a HashSet<Scope>,
+    pub expanding: &'a HashMap<Scope, usize>,
+    pub diagnostics: &'a mut Vec<Diagnostic>,
+    /// Whether names that pending imports could still bind are treated as missing, once no more
+    /// progress can be made otherwise.
+    pub finalizing: bool,
+}
+
+impl Resolver<'_> {
+    pub fn resolve(&mut self) {
+        for finalizing in [false, true] {
+            self.finalizing = finalizing;
+            self.resolve_some();
+        }
+        for id in mem::take(&mut self.imports.pending) {
+            if let Err(Stop::Failed(failure)) = self.resolve_import(id) {
+                self.report(id, failure);
+            }
+        }
+    }
+
+    /// Resolve whatever imports can be until no more progress is made, without reporting those
+    /// that can't.
+    pub fn resolve_some(&mut self) {
+        loop {
+            let mut progress = false;
+            for id in mem::take(&mut self.imports.pending) {
+                match self.resolve_import(id) {
+                    Ok(()) => {
+                        self.imports.settle(id);
+                        progress = true;
+                    }
+                    Err(_) => self.imports.pending.push(id),
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+    }
+
+    fn resolve_import(&mut self, id: ImportId) -> Result<(), Stop> {
+        let import = self.imports.imports[id].clone();
+        let len = import.path.len();
+        let name = match import.kind {
+            ImportKind::Glob => {
+                let (res, _) = self.walk(&import, len, Namespace::Type)?;
+                if !is_module_like(res) {
+                    return Err(Stop::Failed(Failure::NotModule(len - 1, res)));
+                }
+                let globs = self.imports.globs.entry(import.scope).or_default();
+                globs.push((res, import.vis));
+                return Ok(());
+            }
+            ImportKind::Single(name) => name,
+        };
+        let mut results = Vec::new();
+        match import.path[len - 1].0 {
+            // `use a::{self};` imports only the module or enum itself.
+            Segment::SelfModule if len > 1 => {
+                let (res, vis) = self.walk(&import, len - 1, Namespace::Type)?;
+                if !is_module_like(res) {
+                    return Err(Stop::Failed(Failure::NotModule(len - 2, res)));
+                }
+                results.push((Namespace::Type, res, vis));
+            }
+            Segment::Name(_) => {
+                let mut failure = None;
+                for ns in Namespace::ALL {
+                    match self.walk(&import, len, ns) {
+                        Ok((res, vis)) => results.push((ns, res, vis)),
+                        // A name can be missing or private in some namespaces but not others.
+                        Err(Stop::Failed(Failure::NotFound(i))) if i == len - 1 => {
+                            failure.get_or_insert(Failure::NotFound(i));
+                        }
+                        Err(Stop::Failed(private @ Failure::Private(i, _))) if i == len - 1 => {
+                            failure = Some(private);
+                        }
+                        Err(stop) => return Err(stop),
+                    }
+                }
+                if results.is_empty() {
+                    return Err(Stop::Failed(failure.unwrap()));
+                }
+            }
+            _ => {
+                let (res, vis) = self.walk(&import, len, Namespace::Type)?;
+                results.push((Namespace::Type, res, vis));
+            }
+        }
+        if let Some(name) = name {
+            let mut conflict = None;
+            for (ns, res, vis) in results {
+                let binding = Binding {
+                    res,
+                    vis: import.vis.min(vis, self.paths),
+                    import: id,
+                };
+                conflict = conflict.or(self.bind(&import, ns, name, binding));
+            }
+            if let Some(diagnostic) = conflict {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the first `end` segments of an import path, looking up the last of them in `ns`,
+    /// along with the visibility of what it resolves to.
+    fn walk(&self, import: &Import, end: usize, ns: Namespace) -> Result<(Res, Visibility), Stop> {
+        let root = self.paths.root_of(self.scopes.module(import.scope));
+        let base = match self.crates.edition(self.crates.of_root(root)) {
+            Edition::Edition2015 => Base::In(Res::Module(root)),
+            _ => Base::Lexical,
+        };
+        self.walk_from(import, end, ns, base)
+    }
+
+    /// Like [`Self::walk`], but starting from `base` rather than where the edition's import paths
+    /// start.
+    fn walk_from(
+        &self,
+        import: &Import,
+        end: usize,
+        ns: Namespace,
+        mut base: Base,
+    ) -> Result<(Res, Visibility), Stop> {
+        let module = self.scopes.module(import.scope);
+        let root = self.paths.root_of(module);
+        let edition = self.crates.edition(self.crates.of_root(root));
+        let (mut res, mut vis) = (Res::Opaque, Visibility::Public);
+        for (i, &(segment, _)) in import.path[..end].iter().enumerate() {
+            let ns = if i + 1 == end { ns } else { Namespace::Type };
+            let failed = |failure| Err(Stop::Failed(failure));
+            (res, vis) = match segment {
+                Segment::Root if i == 0 => {
+                    if edition != Edition::Edition2015 {
+                        base = Base::ExternPrelude;
+                    }
+                    continue;
+                }
+                Segment::Crate if i == 0 || import.path[0].0 == Segment::Root && i == 1 => {
+                    (Res::Module(root), Visibility::Public)
+                }
+                Segment::DollarCrate(root) if i == 0 => (Res::Module(root), Visibility::Public),
+                Segment::SelfModule if i == 0 => (Res::Module(module), Visibility::Public),
+                Segment::Super => {
+                    let current = match (i, res) {
+                        (0, _) => module,
+                        (_, Res::Module(path))
+                            if matches!(
+                                import.path[i - 1].0,
+                                Segment::SelfModule | Segment::Super
+                            ) =>
+                        {
+                            path
+                        }
+                        _ => return failed(Failure::NotFound(i)),
+                    };
+                    match self.paths.parent(current) {
+                        Some(parent) => (Res::Module(parent), Visibility::Public),
+                        None => return failed(Failure::TooManySupers(i)),
+                    }
+                }
+                Segment::Name(name) => match base {
+                    Base::Lexical if end == 1 && ns == Namespace::Macro => {
+                        match self.macros.textual(import.macros, name) {
+                            Some(item) => (Res::Item(item), item.vis),
+                            None => self.lookup_lexical(import.scope, ns, name, i)?,
+                        }
+                    }
+                    Base::Lexical => self.lookup_lexical(import.scope, ns, name, i)?,
+                    Base::ExternPrelude => match self.extern_prelude(root, name) {
+                        Some(res) if ns == Namespace::Type => (res, Visibility::Public),
+                        _ => return failed(Failure::NotFound(i)),
+                    },
+                    // The crate root has an `extern crate std;` injected into it, which is where
+                    // 2015 edition paths start.
+                    Base::In(Res::Module(path))
+                        if path == root
+                            && ns == Namespace::Type
+                            && self.injected_crate(root, name).is_some() =>
+                    {
+                        (self.injected_crate(root, name).unwrap(), Visibility::Public)
+                    }
+                    Base::In(parent) => match self.lookup_in(parent, ns, name, &mut Vec::new()) {
+                        Lookup::Found(res, vis) if !vis.is_accessible_from(module, self.paths) => {
+                            return failed(Failure::Private(i, res));
+                        }
+                        Lookup::Found(res, vis) => (res, vis),
+                        Lookup::NotFound => return failed(Failure::NotFound(i)),
+                        Lookup::Indeterminate => return Err(Stop::Indeterminate),
+                        Lookup::Ambiguous => return failed(Failure::Ambiguous(i)),
+                    },
+                },
+                _ => return failed(Failure::NotFound(i)),
+            };
+            if i + 1 < end && !is_module_like(res) {
+                return failed(Failure::NotModule(i, res));
+            }
+            base = Base::In(res);
+        }
+        Ok((res, vis))
+    }
+
+    /// Look up a name in a namespace from a scope, once imports have been resolved.
+    pub fn lookup(&self, scope: Scope, ns: Namespace, name: Name) -> Option<Res> {
+        match self.lookup_lexical(scope, ns, name, 0) {
+            Ok((res, _)) => Some(res),
+            // Ambiguities are reported where the name is imported.
+            Err(Stop::Failed(Failure::Ambiguous(_) | Failure::AmbiguousExtern(_))) => {
+                Some(Res::Opaque)
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Resolve the path of a macro call, returning `None` if that depends on imports or macro
+    /// calls that aren't resolved yet.
+    ///
+    /// A single identifier is looked up in textual scope first, then among the names in scope,
+    /// and last among the macros imported by `#[macro_use] extern crate`.
+    pub fn resolve_macro(&self, call: &MacroCall) -> Result<Option<Res>, Diagnostic> {
+        if let [(Segment::Name(name), loc)] = call.path[..] {
+            let root = self.paths.root_of(self.scopes.module(call.scope));
+            if let Some(item) = self.macros.textual(call.textual, name) {
+                return Ok(Some(Res::Item(item)));
+            }
+            match self.lookup_lexical(call.scope, Namespace::Macro, name, 0) {
+                Ok((res, _)) => return Ok(Some(res)),
+                Err(Stop::Indeterminate) => return Ok(None),
+                // Ambiguities are reported where the name is imported.
+                Err(Stop::Failed(Failure::Ambiguous(_) | Failure::AmbiguousExtern(_))) => {
+                    return Ok(Some(Res::Opaque))
+                }
+                Err(Stop::Failed(_)) => {}
+            }
+            if let Some(res) = self.lookup_macro_use(root, name) {
+                return Ok(Some(res));
+            }
+            let name = self.names.get(name);
+            return Err(Diagnostic::error(
+                loc,
+                format!("cannot find {} `{name}` in this scope", call.kind.descr()),
+            ));
+        }
+        let import = Import {
+            scope: call.scope,
+            vis: Visibility::Public,
+            path: call.path.clone(),
+            kind: ImportKind::Single(None),
+            macros: call.textual,
+        };
+        match self.walk(&import, import.path.len(), Namespace::Macro) {
+            Ok((res, _)) => Ok(Some(res)),
+            Err(Stop::Indeterminate) if self.finalizing => Ok(Some(Res::Opaque)),
+            Err(Stop::Indeterminate) => Ok(None),
+            Err(Stop::Failed(failure)) => Err(self.diagnose(&import, failure, false)),
+        }
+    }
+
+    /// Resolve the path of an expression, type, or struct expression or pattern, once imports have
+    /// been resolved. Unlike an import path, it starts in the scopes enclosing it in every edition.
+    ///
+    /// Only a segment that names something private is reported, since paths can go on to name
+    /// associated items, which aren't resolved here.
+    pub fn resolve_path(
+        &self,
+        scope: Scope,
+        path: &[(Segment, SourceLoc)],
+        ns: Namespace,
+    ) -> Result<Option<Res>, Diagnostic> {
+        let import = Import {
+            scope,
+            vis: Visibility::Public,
+            path: path.to_vec(),
+            kind: ImportKind::Single(None),
+            macros: None,
+        };
+        match self.walk_from(&import, path.len(), ns, Base::Lexical) {
+            Ok((res, _)) => Ok(Some(res)),
+            Err(Stop::Failed(private @ Failure::Private(..))) => {
+                Err(self.diagnose(&import, private, false))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Look up a macro imported by `#[macro_use] extern crate`, including the one that is
+    /// implicitly loaded for the standard library crate whose prelude is in scope.
+    fn lookup_macro_use(&self, root: Path, name: Name) -> Option<Res> {
+        let krate = self.crates.of_root(root);
+        for (dep, names) in self.macros.macro_use(krate) {
+            if names.as_ref().is_some_and(|names| !names.contains(&name)) {
+                continue;
+            }
+            if let Some(res) = self.exported_macro(*dep, name) {
+                return Some(res);
+            }
+        }
+        match self.crates.prelude(krate)? {
+            (std, Extern::Opaque) => prelude::exports_macro(std == sym::CORE, self.names.get(name))
+                .then_some(Res::Opaque),
+            (_, dep) => self.exported_macro(dep, name),
+        }
+    }
+
+    /// Look up a public macro at the root of a crate.
+    fn exported_macro(&self, dep: Extern, name: Name) -> Option<Res> {
+        let root = match dep {
+            Extern::Crate(dep) => self.crates.root(dep),
+            Extern::Opaque => return Some(Res::Opaque),
+        };
+        match self.lookup_in(Res::Module(root), Namespace::Macro, name, &mut Vec::new()) {
+            Lookup::Found(res, Visibility::Public) => Some(res),
+            _ => None,
+        }
+    }
+
+    /// Look up the first segment of a 2018 edition path, which is segment `i` of the path.
+    fn lookup_lexical(
+        &self,
+        scope: Scope,
+        ns: Namespace,
+        name: Name,
+        i: usize,
+    ) -> Result<(Res, Visibility), Stop> {
+        let mut local = Lookup::NotFound;
+        let (mut unexpanded, mut expanding) = (false, false);
+        for s in self.scopes.ancestors(scope) {
+            local = self.lookup_scope(s, ns, name, &mut Vec::new());
+            if !matches!(local, Lookup::NotFound) {
+                break;
+            }
+            unexpanded |= self.unexpanded.contains(&s);
+            expanding |= self.expanding.contains_key(&s);
+            // Names outside a module aren't in scope inside it.
+            if self.scopes.kind(s) == ScopeKind::Module {
+                break;
+            }
+        }
+        let root = self.paths.root_of(self.scopes.module(scope));
+        let external = match ns {
+            Namespace::Type => self.extern_prelude(root, name),
+            _ => None,
+        };
+        match (local, external) {
+            (Lookup::Found(res, _), Some(external))
+                if res != external && res != Res::Opaque && external != Res::Opaque =>
+            {
+                Err(Stop::Failed(Failure::AmbiguousExtern(i)))
+            }
+            (Lookup::Found(res, vis), _) => Ok((res, vis)),
+            (Lookup::Indeterminate, _) => Err(Stop::Indeterminate),
+            (Lookup::Ambiguous, _) => Err(Stop::Failed(Failure::Ambiguous(i))),
+            (Lookup::NotFound, Some(external)) => Ok((external, Visibility::Public)),
+            (Lookup::NotFound, None) if expanding && !self.finalizing => Err(Stop::Indeterminate),
+            (Lookup::NotFound, None) if unexpanded || expanding => {
+                Ok((Res::Opaque, Visibility::Public))
+            }
+            (Lookup::NotFound, None) => match self.lookup_prelude(root, ns, name) {
+                Lookup::Found(res, _) => Ok((res, Visibility::Public)),
+                Lookup::NotFound => Err(Stop::Failed(Failure::NotFound(i))),
+                Lookup::Indeterminate => Err(Stop::Indeterminate),
+                Lookup::Ambiguous => Err(Stop::Failed(Failure::Ambiguous(i))),
+            },
+        }
+    }
+
+    /// Look up a name in the standard library prelude for the edition of a crate, which is the
+    /// outermost scope of every module.
+    fn lookup_prelude(&self, root: Path, ns: Namespace, name: Name) -> Lookup {
+        let krate = self.crates.of_root(root);
+        let Some((std, dep)) = self.crates.prelude(krate) else {
+            return Lookup::NotFound;
+        };
+        let edition = self.crates.edition(krate);
+        let module = match dep {
+            Extern::Crate(dep) => {
+                let std_root = self.crates.root(dep);
+                self.paths
+                    .get(std_root, sym::PRELUDE)
+                    .and_then(|prelude| self.paths.get(prelude, prelude::module(edition)))
+            }
+            Extern::Opaque => None,
+        };
+        match module {
+            Some(module) => self.lookup_in(Res::Module(module), ns, name, &mut Vec::new()),
+            None if prelude::contains(edition, std == sym::CORE, ns, self.names.get(name)) => {
+                Lookup::Found(Res::Opaque, Visibility::Public)
+            }
+            None => Lookup::NotFound,
+        }
+    }
+
+    /// The standard library crate injected as an `extern crate` item at the root of a crate, if it
+    /// has the given name: `std`, or `core` under `#![no_std]`.
+    fn injected_crate(&self, root: Path, name: Name) -> Option<Res> {
+        match self.crates.prelude(self.crates.of_root(root))? {
+            (std, _) if std != name => None,
+            (_, Extern::Crate(krate)) => Some(Res::Module(self.crates.root(krate))),
+            (_, Extern::Opaque) => Some(Res::Opaque),
+        }
+    }
+
+    /// Look up a name in the extern prelude, which has the crate's dependencies along with the
+    /// `extern crate` items at its root.
+    fn extern_prelude(&self, root: Path, name: Name) -> Option<Res> {
+        match self.crates.dep(self.crates.of_root(root), name) {
+            Some(Extern::Crate(krate)) => Some(Res::Module(self.crates.root(krate))),
+            Some(Extern::Opaque) => Some(Res::Opaque),
+            None => {
+                let scope = *self.modules.get(&root)?;
+                let item = self
+                    .items
+                    .get(Parent::Scope(scope), Namespace::Type, name)?;
+                (item.kind == ItemKind::ExternCrate).then(|| self.item_res(scope, name, item))
+            }
+        }
+    }
+
+    /// Look up a name inside a module or enum.
+    fn lookup_in(&self, res: Res, ns: Namespace, name: Name, visited: &mut Vec<Scope>) -> Lookup {
+        match res {
+            Res::Module(path) => match self.modules.get(&path) {
+                Some(&scope) => match self.lookup_scope(scope, ns, name, visited) {
+                    Lookup::NotFound if self.expanding.contains_key(&scope) && !self.finalizing => {
+                        Lookup::Indeterminate
+                    }
+                    // Assume that macros which won't be expanded define whatever is missing.
+                    Lookup::NotFound
+                        if self.unexpanded.contains(&scope)
+                            || self.expanding.contains_key(&scope) =>
+                    {
+                        Lookup::Found(Res::Opaque, Visibility::Public)
+                    }
+                    lookup => lookup,
+                },
+                // The module's file couldn't be loaded.
+                None => Lookup::Found(Res::Opaque, Visibility::Public),
+            },
+            Res::Item(item) if item.kind == ItemKind::Enum => {
+                match self.items.get(Parent::Enum(item.node), ns, name) {
+                    Some(variant) => Lookup::Found(Res::Item(variant), variant.vis),
+                    None => Lookup::NotFound,
+                }
+            }
+            Res::Item(_) => Lookup::NotFound,
+            Res::Opaque => Lookup::Found(Res::Opaque, Visibility::Public),
+        }
+    }
+
+    /// Look up a name defined or imported in a module or block scope. The `visited` scopes are
+    /// those whose glob imports are already being followed.
+    fn lookup_scope(
+        &self,
+        scope: Scope,
+        ns: Namespace,
+        name: Name,
+        visited: &mut Vec<Scope>,
+    ) -> Lookup {
+        if let Some(item) = self.items.get(Parent::Scope(scope), ns, name) {
+            return Lookup::Found(self.item_res(scope, name, item), item.vis);
+        }
+        if let Some(binding) = self.imports.binding(scope, ns, name) {
+            return Lookup::Found(binding.res, binding.vis);
+        }
+        let pending = |count: Option<&usize>| !self.finalizing && count.is_some_and(|&n| n > 0);
+        if pending(self.imports.pending_names.get(&(scope, name))) {
+            return Lookup::Indeterminate;
+        }
+        if visited.contains(&scope) {
+            return Lookup::NotFound;
+        }
+        visited.push(scope);
+        let mut found = None;
+        let mut indeterminate = pending(self.imports.pending_globs.get(&scope));
+        let module = self.scopes.module(scope);
+        for &(source, glob_vis) in self.imports.globs.get(&scope).into_iter().flatten() {
+            match self.lookup_in(source, ns, name, visited) {
+                // Glob imports skip names that aren't visible to them.
+                Lookup::Found(_, vis) if !vis.is_accessible_from(module, self.paths) => {}
+                Lookup::Found(res, vis) => match found {
+                    Some((prev, _)) if prev != res => {
+                        visited.pop();
+                        return Lookup::Ambiguous;
+                    }
+                    _ => found = Some((res, glob_vis.min(vis, self.paths))),
+                },
+                Lookup::NotFound => {}
+                Lookup::Indeterminate => indeterminate = true,
+                Lookup::Ambiguous => {
+                    visited.pop();
+                    return Lookup::Ambiguous;
+                }
+            }
+        }
+        visited.pop();
+        match found {
+            _ if indeterminate => Lookup::Indeterminate,
+            Some((res, vis)) => Lookup::Found(res, vis),
+            None => Lookup::NotFound,
+        }
+    }
+
+    fn item_res(&self, scope: Scope, name: Name, item: Item) -> Res {
+        let path = match item.kind {
+            ItemKind::Module => self.paths.get(self.scopes.module(scope), name),
+            ItemKind::ExternCrate => self.extern_crates.get(&item.node).copied(),
+            _ => return Res::Item(item),
+        };
+        path.map_or(Res::Opaque, Res::Module)
+    }
+
+    /// Bind a name in the import's scope, returning a diagnostic if it conflicts with another.
+    fn bind(
+        &mut self,
+        import: &Import,
+        ns: Namespace,
+        name: Name,
+        binding: Binding,
+    ) -> Option<Diagnostic> {
+        let scope = import.scope;
+        // Names of opaque imports may not exist in every namespace, so they never conflict.
+        let opaque = binding.res == Res::Opaque;
+        let (code, prev, loc) = match self.items.get(Parent::Scope(scope), ns, name) {
+            Some(_) if opaque => return None,
+            Some(item) => ("E0255", "definition", self.nodes.loc(item.node)),
+            None => {
+                let bindings = self.imports.bindings.entry((scope, ns)).or_default();
+                match bindings.entry(name) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(binding);
+                        return None;
+                    }
+                    Entry::Occupied(entry) => {
+                        let prev = entry.get();
+                        if opaque || prev.res == Res::Opaque {
+                            return None;
+                        }
+                        ("E0252", "import", self.imports.imports[prev.import].loc())
+                    }
+                }
+            }
+        };
+        let name = self.names.get(name);
+        let container = match self.scopes.kind(scope) {
+            ScopeKind::Module => "module",
+            _ => "block",
+        };
+        let descr = ns.descr();
+        Some(
+            Diagnostic::error(
+                import.loc(),
+                format!("the name `{name}` is defined multiple times"),
+            )
+            .code(code)
+            .label(loc, format!("previous {prev} of the {descr} `{name}` here"))
+            .note(format!(
+                "`{name}` must be defined only once in the {descr} namespace of this {container}",
+            )),
+        )
+    }
+
+    fn report(&mut self, id: ImportId, failure: Failure) {
+        let import = self.imports.imports[id].clone();
+        self.imports.settle(id);
+        if let ImportKind::Single(Some(name)) = import.kind {
+            let bindings = self.imports.bindings.entry((import.scope, Namespace::Type));
+            // Avoid cascading errors from uses of the name.
+            bindings.or_default().entry(name).or_insert(Binding {
+                res: Res::Opaque,
+                vis: import.vis,
+                import: id,
+            });
+        }
+        let diagnostic = self.diagnose(&import, failure, true);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Explain why the path of an import, or else of a macro call, can't be resolved.
+    fn diagnose(&self, import: &Import, failure: Failure, is_import: bool) -> Diagnostic {
+        let display = |segments: &[(Segment, SourceLoc)]| -> String {
+            let segments = segments.iter().map(|&(segment, _)| match segment {
+                Segment::Root => "",
+                Segment::Crate => "crate",
+                Segment::DollarCrate(_) => "$crate",
+                Segment::SelfModule => "self",
+                Segment::Super => "super",
+                Segment::Name(name) => self.names.get(name),
+            });
+            segments.collect::<Vec<_>>().join("::")
+        };
+        let path = display(&import.path);
+        let (Failure::NotFound(i)
+        | Failure::NotModule(i, _)
+        | Failure::Private(i, _)
+        | Failure::TooManySupers(i)
+        | Failure::Ambiguous(i)
+        | Failure::AmbiguousExtern(i)) = failure;
+        let name = display(&import.path[i..=i]);
+        let loc = import.path[i].1;
+        let unresolved = |label: String| match is_import {
+            true => Diagnostic::error(loc, format!("unresolved import `{path}`"))
+                .code("E0432")
+                .label(loc, label),
+            false => Diagnostic::error(loc, format!("failed to resolve: {label}"))
+                .code("E0433")
+                .label(loc, label),
+        };
+        let last = is_import && i + 1 == import.path.len();
+        match failure {
+            Failure::NotFound(_) => unresolved(match (i, display(&import.path[..i]).as_str()) {
+                (0, _) if import.path.len() == 1 => format!("no external crate `{name}`"),
+                (0, _) => format!("use of undeclared crate or module `{name}`"),
+                (_, "" | "crate") if last => format!("no `{name}` in the root"),
+                (_, prefix) if last => format!("no `{name}` in `{prefix}`"),
+                (_, "" | "crate") => format!("could not find `{name}` in the crate root"),
+                (_, prefix) => format!("could not find `{name}` in `{prefix}`"),
+            }),
+            Failure::NotModule(_, res) => {
+                unresolved(format!("`{name}` is a {}, not a module", res_descr(res)))
+            }
+            Failure::Private(_, res) => {
+                let descr = res_descr(res);
+                Diagnostic::error(loc, format!("{descr} `{name}` is private"))
+                    .code("E0603")
+                    .label(loc, format!("private {descr}"))
+            }
+            Failure::TooManySupers(_) => Diagnostic::error(
+                loc,
+                "failed to resolve: there are too many leading `super` keywords",
+            )
+            .code("E0433"),
+            Failure::Ambiguous(_) => Diagnostic::error(loc, format!("`{name}` is ambiguous"))
+                .code("E0659")
+                .note("ambiguous because of multiple glob imports of a name in the same module"),
+            Failure::AmbiguousExtern(_) => Diagnostic::error(loc, format!("`{name}` is ambiguous"))
+                .code("E0659")
+                .note(format!(
+                    "`{name}` could refer to a crate in the extern prelude or to a name in scope",
+                ))
+                .help(format!(
+                    "use `::{name}` to refer to the crate unambiguously"
+                ))
+                .help(format!(
+                    "use `self::{name}` to refer to the name in scope unambiguously"
+                )),
+        }
+    }
+}
+
+fn res_descr(res: Res) -> &'static str {
+    match res {
+        Res::Item(item) => item.kind.descr(),
+        _ => "module",
+    }
+}
+
+/// Whether a path can continue after something, because it has names inside it.
+fn is_module_like(res: Res) -> bool {
+    match res {
+        Res::Module(_) | Res::Opaque => true,
+        Res::Item(item) => item.kind == ItemKind::Enum,
+    }
+}
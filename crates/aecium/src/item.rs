@@ -0,0 +1,139 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::{name::Name, scope::Scope, syntax::Node, vis::Visibility};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Namespace {
+    Type,
+    Value,
+    Macro,
+}
+
+impl Namespace {
+    pub const ALL: [Namespace; 3] = [Namespace::Type, Namespace::Value, Namespace::Macro];
+
+    pub fn descr(self) -> &'static str {
+        match self {
+            Namespace::Type => "type",
+            Namespace::Value => "value",
+            Namespace::Macro => "macro",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ItemKind {
+    Const,
+    Enum,
+    ExternCrate,
+    Fn,
+    /// A `macro` definition, as opposed to `macro_rules!`.
+    Macro,
+    MacroRules,
+    Module,
+    Static,
+    Struct,
+    Trait,
+    TypeAlias,
+    Union,
+    Variant,
+}
+
+impl ItemKind {
+    pub fn descr(self) -> &'static str {
+        match self {
+            ItemKind::Const => "constant",
+            ItemKind::Enum => "enum",
+            ItemKind::ExternCrate => "extern crate",
+            ItemKind::Fn => "function",
+            ItemKind::Macro | ItemKind::MacroRules => "macro",
+            ItemKind::Module => "module",
+            ItemKind::Static => "static",
+            ItemKind::Struct => "struct",
+            ItemKind::Trait => "trait",
+            ItemKind::TypeAlias => "type alias",
+            ItemKind::Union => "union",
+            ItemKind::Variant => "variant",
+        }
+    }
+}
+
+/// A named item, pointing at the node that defines it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub node: Node,
+    pub vis: Visibility,
+}
+
+/// A field of a struct, union or variant.
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    /// The name of a record field, or `None` for a tuple field.
+    pub name: Option<Name>,
+    pub node: Node,
+    pub vis: Visibility,
+}
+
+/// Something that items are defined in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Parent {
+    /// A module or block.
+    Scope(Scope),
+    /// An enum, whose variants are defined in it.
+    Enum(Node),
+}
+
+/// The items defined directly in each parent, in each namespace.
+pub struct Items {
+    defs: HashMap<(Parent, Namespace), HashMap<Name, Item>>,
+    /// The fields of each struct, union and variant, in order.
+    fields: HashMap<Node, Vec<Field>>,
+}
+
+impl Items {
+    pub fn new() -> Self {
+        Self {
+            defs: HashMap::new(),
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Define an item, returning the earlier item it conflicts with if there is one.
+    ///
+    /// A `macro_rules!` definition shadows an earlier one with the same name instead.
+    pub fn define(
+        &mut self,
+        parent: Parent,
+        ns: Namespace,
+        name: Name,
+        item: Item,
+    ) -> Option<Item> {
+        match self.defs.entry((parent, ns)).or_default().entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(item);
+                None
+            }
+            Entry::Occupied(mut entry) => {
+                let prev = *entry.get();
+                if item.kind == ItemKind::MacroRules && prev.kind == ItemKind::MacroRules {
+                    entry.insert(item);
+                    return None;
+                }
+                Some(prev)
+            }
+        }
+    }
+
+    pub fn get(&self, parent: Parent, ns: Namespace, name: Name) -> Option<Item> {
+        self.defs.get(&(parent, ns))?.get(&name).copied()
+    }
+
+    pub fn push_field(&mut self, owner: Node, field: Field) {
+        self.fields.entry(owner).or_default().push(field);
+    }
+
+    pub fn fields(&self, owner: Node) -> &[Field] {
+        self.fields.get(&owner).map_or(&[], Vec::as_slice)
+    }
+}
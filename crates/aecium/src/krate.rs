@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use derive_rustc_index::Idx;
+use ra_ap_parser::Edition;
+use ra_ap_rustc_index::IndexVec;
+
+use crate::{
+    cfg::CfgSet,
+    name::{sym, Name},
+    path::Path,
+};
+
+/// A crate in the graph being checked, identified by the order it was added in.
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+pub struct Crate(u16);
+
+/// What the name of a crate refers to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Extern {
+    Crate(Crate),
+    /// A crate that exists but whose sources aren't loaded, like a registry dependency.
+    Opaque,
+}
+
+struct CrateData {
+    /// The name of the crate itself, which `module_path!` starts with.
+    name: Name,
+    root: Path,
+    edition: Edition,
+    cfg: CfgSet,
+    /// The environment variables that `env!` and `option_env!` can read.
+    env: HashMap<String, String>,
+    /// Each dependency under the name this crate knows it by.
+    deps: Vec<(Name, Extern)>,
+    /// Whether the crate root has `#![no_std]`, so `std` isn't in its extern prelude.
+    no_std: bool,
+    /// Whether the crate root has `#![no_core]`, so neither `core` nor `std` is.
+    no_core: bool,
+}
+
+/// The crate graph. Dependencies are always added before the crates that depend on them.
+pub struct Crates {
+    data: IndexVec<Crate, CrateData>,
+    roots: HashMap<Path, Crate>,
+    /// The standard library crates, which any crate can name in an `extern crate` item.
+    sysroot: HashMap<Name, Crate>,
+}
+
+impl Crates {
+    pub fn new() -> Self {
+        Self {
+            data: IndexVec::new(),
+            roots: HashMap::new(),
+            sysroot: HashMap::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        name: Name,
+        root: Path,
+        edition: Edition,
+        cfg: CfgSet,
+        env: HashMap<String, String>,
+        deps: Vec<(Name, Extern)>,
+    ) -> Crate {
+        let krate = self.data.push(CrateData {
+            name,
+            root,
+            edition,
+            cfg,
+            env,
+            deps,
+            no_std: false,
+            no_core: false,
+        });
+        self.roots.insert(root, krate);
+        krate
+    }
+
+    /// The crate whose root module is the given path.
+    pub fn of_root(&self, root: Path) -> Crate {
+        self.roots[&root]
+    }
+
+    pub fn name(&self, krate: Crate) -> Name {
+        self.data[krate].name
+    }
+
+    pub fn root(&self, krate: Crate) -> Path {
+        self.data[krate].root
+    }
+
+    pub fn edition(&self, krate: Crate) -> Edition {
+        self.data[krate].edition
+    }
+
+    pub fn cfg(&self, krate: Crate) -> &CfgSet {
+        &self.data[krate].cfg
+    }
+
+    pub fn env(&self, krate: Crate) -> &HashMap<String, String> {
+        &self.data[krate].env
+    }
+
+    pub fn add_sysroot(&mut self, name: Name, krate: Crate) {
+        self.sysroot.insert(name, krate);
+    }
+
+    pub fn is_sysroot(&self, krate: Crate) -> bool {
+        self.sysroot.values().any(|&k| k == krate)
+    }
+
+    pub fn set_no_std(&mut self, krate: Crate) {
+        self.data[krate].no_std = true;
+    }
+
+    pub fn set_no_core(&mut self, krate: Crate) {
+        self.data[krate].no_core = true;
+    }
+
+    /// Look up a name in the crate's extern prelude.
+    pub fn dep(&self, krate: Crate, name: Name) -> Option<Extern> {
+        let data = &self.data[krate];
+        if let Some(&(_, dep)) = data.deps.iter().find(|&&(n, _)| n == name) {
+            return Some(dep);
+        }
+        let injected = match name {
+            sym::CORE => !data.no_core,
+            sym::STD => !data.no_core && !data.no_std,
+            _ => false,
+        };
+        injected.then(|| self.sysroot_crate(name))?
+    }
+
+    /// The standard library crate whose prelude is in scope throughout the crate, along with its
+    /// name: `std`, or `core` under `#![no_std]`, or neither under `#![no_core]`.
+    pub fn prelude(&self, krate: Crate) -> Option<(Name, Extern)> {
+        let data = &self.data[krate];
+        let name = match (data.no_core, data.no_std) {
+            (true, _) => return None,
+            (false, true) => sym::CORE,
+            (false, false) => sym::STD,
+        };
+        Some((name, self.sysroot_crate(name)?))
+    }
+
+    /// Look up the crate named by an `extern crate` item, which can also load any sysroot crate.
+    pub fn extern_crate(&self, krate: Crate, name: Name) -> Option<Extern> {
+        self.dep(krate, name).or_else(|| self.sysroot_crate(name))
+    }
+
+    fn sysroot_crate(&self, name: Name) -> Option<Extern> {
+        match self.sysroot.get(&name) {
+            Some(&krate) => Some(Extern::Crate(krate)),
+            // Without a sysroot, or for sysroot crates that aren't loaded, trust that it exists.
+            None => matches!(
+                name,
+                sym::ALLOC | sym::CORE | sym::PROC_MACRO | sym::STD | sym::TEST
+            )
+            .then_some(Extern::Opaque),
+        }
+    }
+}
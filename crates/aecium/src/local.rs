@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+
+use derive_rustc_index::Idx;
+use ra_ap_rustc_index::{Idx, IndexVec};
+
+use crate::{
+    diagnostic::Diagnostic,
+    hygiene::{SyntaxContext, SyntaxContexts},
+    import::Res,
+    item::{ItemKind, Namespace},
+    name::{Name, Names},
+    prelude,
+    scope::Scope,
+    source::SourceLoc,
+    syntax::Node,
+};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+pub struct LocalId(u32);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LocalKind {
+    /// A variable bound by a pattern, which could instead refer to a constant.
+    Variable,
+    ConstParam,
+    TypeParam,
+    LifetimeParam,
+    /// A `macro_rules!` definition in a body, before which identifiers from its expansions
+    /// resolve as if they were written there.
+    Macro,
+    /// The expansion of a macro call in statement position, which is parsed later and whose
+    /// bindings lead back to those before the call.
+    Expansion,
+}
+
+/// A name bound in a function body or generic parameter list, linked to the one bound before it,
+/// so that each point in a body can refer to the bindings in scope there by the latest of them.
+struct Local {
+    name: Name,
+    ctx: SyntaxContext,
+    /// The [`SyntaxKind::IDENT_PAT`](ra_ap_parser::SyntaxKind::IDENT_PAT),
+    /// [`SyntaxKind::CONST_PARAM`](ra_ap_parser::SyntaxKind::CONST_PARAM) or
+    /// [`SyntaxKind::TYPE_PARAM`](ra_ap_parser::SyntaxKind::TYPE_PARAM) binding the name, the
+    /// [`SyntaxKind::LIFETIME`](ra_ap_parser::SyntaxKind::LIFETIME) of a lifetime parameter, or
+    /// the [`SyntaxKind::MACRO_RULES`](ra_ap_parser::SyntaxKind::MACRO_RULES) or
+    /// [`SyntaxKind::MACRO_CALL`](ra_ap_parser::SyntaxKind::MACRO_CALL) of a macro.
+    node: Node,
+    scope: Scope,
+    kind: LocalKind,
+    /// Whether a field of the struct, enum or union declaring this generic parameter refers to it.
+    used: bool,
+    prev: Option<LocalId>,
+}
+
+/// What a use of a name can refer to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UseKind {
+    Value,
+    Type,
+    Trait,
+    /// A type that is a generic argument, which can also name a constant.
+    GenericArg,
+    /// The first segment of a longer type path, which can also name a module or crate.
+    Prefix,
+    Lifetime,
+}
+
+impl UseKind {
+    fn matches(self, kind: LocalKind) -> bool {
+        match self {
+            UseKind::Value => matches!(kind, LocalKind::Variable | LocalKind::ConstParam),
+            UseKind::Type | UseKind::Trait | UseKind::Prefix => kind == LocalKind::TypeParam,
+            UseKind::GenericArg => matches!(kind, LocalKind::TypeParam | LocalKind::ConstParam),
+            UseKind::Lifetime => kind == LocalKind::LifetimeParam,
+        }
+    }
+
+    /// Whether identifiers with the two syntax contexts refer to the same bindings. Local
+    /// variables and const parameters have mixed-site hygiene, while type and lifetime parameters
+    /// only tell apart identifiers from def-site macros.
+    fn same_context(self, contexts: &SyntaxContexts, a: SyntaxContext, b: SyntaxContext) -> bool {
+        match self {
+            UseKind::Value => a == b,
+            _ => contexts.opaque(a) == contexts.opaque(b),
+        }
+    }
+
+    /// The namespaces of items the use can refer to, in the order they are tried.
+    fn namespaces(self) -> &'static [Namespace] {
+        match self {
+            UseKind::Value => &[Namespace::Value],
+            UseKind::Type | UseKind::Trait | UseKind::Prefix => &[Namespace::Type],
+            UseKind::GenericArg => &[Namespace::Type, Namespace::Value],
+            UseKind::Lifetime => &[],
+        }
+    }
+}
+
+/// A single identifier used as an expression, a type or the start of a type path, or a lifetime,
+/// to be resolved once imports are.
+pub struct LocalUse {
+    /// The [`SyntaxKind::PATH_EXPR`](ra_ap_parser::SyntaxKind::PATH_EXPR),
+    /// [`SyntaxKind::PATH_TYPE`](ra_ap_parser::SyntaxKind::PATH_TYPE) or
+    /// [`SyntaxKind::LIFETIME`](ra_ap_parser::SyntaxKind::LIFETIME).
+    pub node: Node,
+    pub kind: UseKind,
+    pub name: Name,
+    pub ctx: SyntaxContext,
+    pub loc: SourceLoc,
+    pub scope: Scope,
+    /// The latest local variable in scope.
+    pub locals: Option<LocalId>,
+    /// The latest generic parameter in scope.
+    pub generics: Option<LocalId>,
+}
+
+/// What a path expression, path type or lifetime resolves to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathRes {
+    /// A local variable or generic parameter, identified by the node binding it.
+    Local(Node),
+    Item(Res),
+    /// A builtin type like `u8` or `str`.
+    Primitive,
+}
+
+/// Local variables and generic parameters, and the identifiers that might refer to them.
+pub struct Locals {
+    locals: IndexVec<LocalId, Local>,
+    uses: Vec<LocalUse>,
+    /// The resolution of each use that has been resolved.
+    res: HashMap<Node, PathRes>,
+    /// The latest binding at the end of each macro call's expansion.
+    expansion_ends: HashMap<Node, Option<LocalId>>,
+}
+
+impl Locals {
+    pub fn new() -> Self {
+        Self {
+            locals: IndexVec::new(),
+            uses: Vec::new(),
+            res: HashMap::new(),
+            expansion_ends: HashMap::new(),
+        }
+    }
+
+    /// Bind a name with the syntax context it was written in after the `prev` binding, returning
+    /// the new latest binding.
+    pub fn bind(
+        &mut self,
+        prev: Option<LocalId>,
+        (name, ctx): (Name, SyntaxContext),
+        node: Node,
+        scope: Scope,
+        kind: LocalKind,
+    ) -> LocalId {
+        self.locals.push(Local {
+            name,
+            ctx,
+            node,
+            scope,
+            kind,
+            used: false,
+            prev,
+        })
+    }
+
+    /// Record the latest binding at the end of the expansion of a macro call.
+    pub fn set_expansion_end(&mut self, call: Node, end: Option<LocalId>) {
+        self.expansion_ends.insert(call, end);
+    }
+
+    /// The binding before `local`, which is the end of its expansion for a macro call that has
+    /// been expanded.
+    fn prev(&self, local: &Local) -> Option<LocalId> {
+        match local.kind {
+            LocalKind::Expansion => match self.expansion_ends.get(&local.node) {
+                Some(&end) => end,
+                None => local.prev,
+            },
+            _ => local.prev,
+        }
+    }
+
+    /// The number of uses so far, which is the index of the next one.
+    pub fn next_use(&self) -> usize {
+        self.uses.len()
+    }
+
+    pub fn push_use(&mut self, local_use: LocalUse) {
+        self.uses.push(local_use);
+    }
+
+    /// Forget the uses in the nodes from `start` on, which have been removed.
+    pub fn strip(&mut self, start: Node) {
+        while self
+            .uses
+            .last()
+            .is_some_and(|local_use| local_use.node.index() >= start.index())
+        {
+            self.uses.pop();
+        }
+    }
+
+    /// Let the uses from index `first` on, which are in a generic parameter list, see all of its
+    /// parameters, which are those bound after `outer` up to `generics`.
+    pub fn forward(&mut self, first: usize, outer: Option<LocalId>, generics: Option<LocalId>) {
+        let mut list = Vec::new();
+        let mut next = generics;
+        while next != outer {
+            let id = next.unwrap();
+            list.push(id);
+            next = self.locals[id].prev;
+        }
+        for local_use in &mut self.uses[first..] {
+            let chain = local_use.generics;
+            if chain == outer || chain.is_some_and(|id| list.contains(&id)) {
+                local_use.generics = generics;
+            }
+        }
+    }
+
+    /// Treat the last use of a type at the given node as a trait.
+    pub fn set_trait(&mut self, node: Node) {
+        if let Some(local_use) = self.uses.iter_mut().rev().find(|u| u.node == node) {
+            if local_use.kind == UseKind::Type {
+                local_use.kind = UseKind::Trait;
+            }
+        }
+    }
+
+    /// Record that a field refers to the generic parameter that a name would refer to.
+    pub fn mark_used(
+        &mut self,
+        contexts: &SyntaxContexts,
+        generics: Option<LocalId>,
+        kind: UseKind,
+        ident: (Name, SyntaxContext),
+    ) {
+        let accept = |_, local: &Local| kind.matches(local.kind);
+        if let Some(id) = self.find(contexts, generics, kind, ident, accept) {
+            self.locals[id].used = true;
+        }
+    }
+
+    /// The type and lifetime parameters bound after `outer` up to `generics` that no field refers
+    /// to, in the order they were declared.
+    pub fn unused(
+        &self,
+        outer: Option<LocalId>,
+        generics: Option<LocalId>,
+    ) -> Vec<(LocalKind, Name, Node)> {
+        let mut unused = Vec::new();
+        let mut next = generics;
+        while next != outer {
+            let local = &self.locals[next.unwrap()];
+            if !local.used && local.kind != LocalKind::ConstParam {
+                unused.push((local.kind, local.name, local.node));
+            }
+            next = local.prev;
+        }
+        unused.reverse();
+        unused
+    }
+
+    #[cfg(test)]
+    pub fn res(&self, node: Node) -> Option<PathRes> {
+        self.res.get(&node).copied()
+    }
+
+    /// The latest binding from `next` on that a use of the given kind of a name in the given
+    /// syntax context can refer to, and that satisfies `accept`.
+    fn find(
+        &self,
+        contexts: &SyntaxContexts,
+        mut next: Option<LocalId>,
+        kind: UseKind,
+        (name, mut ctx): (Name, SyntaxContext),
+        accept: impl Fn(LocalId, &Local) -> bool,
+    ) -> Option<LocalId> {
+        while let Some(id) = next {
+            let local = &self.locals[id];
+            match local.kind {
+                LocalKind::Macro => ctx = contexts.leave(ctx, local.node),
+                LocalKind::Expansion => {}
+                _ if local.name == name
+                    && kind.same_context(contexts, local.ctx, ctx)
+                    && accept(id, local) =>
+                {
+                    return Some(id);
+                }
+                _ => {}
+            }
+            next = self.prev(local);
+        }
+        None
+    }
+
+    /// The context that an identifier in `ctx` has before the bindings after `outer` up to
+    /// `next`, outside of the `macro_rules!` definitions among them.
+    pub fn leave(
+        &self,
+        contexts: &SyntaxContexts,
+        mut next: Option<LocalId>,
+        outer: Option<LocalId>,
+        mut ctx: SyntaxContext,
+    ) -> SyntaxContext {
+        while next != outer {
+            let local = &self.locals[next.unwrap()];
+            if local.kind == LocalKind::Macro {
+                ctx = contexts.leave(ctx, local.node);
+            }
+            next = self.prev(local);
+        }
+        ctx
+    }
+
+    /// Resolve every use to a local, or else to an item using `lookup` to find names, returning
+    /// diagnostics for those that can't be resolved.
+    pub fn resolve(
+        &mut self,
+        names: &Names,
+        contexts: &SyntaxContexts,
+        lookup: impl Fn(Scope, Namespace, Name) -> Option<Res>,
+    ) -> Vec<Diagnostic> {
+        // A pattern like `None` or `FOO` refers to a constant, unit struct or unit variant of that
+        // name if there is one, instead of binding a new variable.
+        let bindings: IndexVec<LocalId, bool> = self
+            .locals
+            .iter()
+            .map(|local| {
+                local.kind != LocalKind::Variable
+                    || !matches!(
+                        lookup(local.scope, Namespace::Value, local.name),
+                        Some(Res::Item(item))
+                            if matches!(
+                                item.kind,
+                                ItemKind::Const | ItemKind::Struct | ItemKind::Variant,
+                            )
+                    )
+            })
+            .collect();
+        let mut diagnostics = Vec::new();
+        for local_use in &self.uses {
+            let chains = match local_use.kind {
+                UseKind::Value => vec![local_use.locals, local_use.generics],
+                _ => vec![local_use.generics],
+            };
+            let ident = (local_use.name, local_use.ctx);
+            let local = chains.into_iter().find_map(|next| {
+                self.find(contexts, next, local_use.kind, ident, |id, local| {
+                    local_use.kind.matches(local.kind) && bindings[id]
+                })
+            });
+            let item = || {
+                let namespaces = local_use.kind.namespaces().iter();
+                namespaces
+                    .filter_map(|&ns| lookup(local_use.scope, ns, local_use.name))
+                    .next()
+            };
+            let primitive = || {
+                !matches!(local_use.kind, UseKind::Value | UseKind::Trait)
+                    && prelude::is_primitive(names.get(local_use.name))
+            };
+            let res = if let Some(id) = local {
+                PathRes::Local(self.locals[id].node)
+            } else if let Some(res) = item() {
+                PathRes::Item(res)
+            } else if primitive() {
+                PathRes::Primitive
+            } else {
+                diagnostics.push(not_found(local_use, names.get(local_use.name)));
+                continue;
+            };
+            self.res.insert(local_use.node, res);
+        }
+        diagnostics
+    }
+}
+
+fn not_found(local_use: &LocalUse, name: &str) -> Diagnostic {
+    let loc = local_use.loc;
+    let (message, code, label) = match local_use.kind {
+        UseKind::Value => (
+            format!("cannot find value `{name}` in this scope"),
+            "E0425",
+            "not found in this scope".to_owned(),
+        ),
+        UseKind::Type | UseKind::GenericArg => (
+            format!("cannot find type `{name}` in this scope"),
+            "E0412",
+            "not found in this scope".to_owned(),
+        ),
+        UseKind::Trait => (
+            format!("cannot find trait `{name}` in this scope"),
+            "E0405",
+            "not found in this scope".to_owned(),
+        ),
+        UseKind::Prefix => {
+            let label = match name.starts_with(char::is_uppercase) {
+                true => format!("use of undeclared type `{name}`"),
+                false => format!("use of undeclared crate or module `{name}`"),
+            };
+            (format!("failed to resolve: {label}"), "E0433", label)
+        }
+        UseKind::Lifetime => (
+            format!("use of undeclared lifetime name `{name}`"),
+            "E0261",
+            "undeclared lifetime".to_owned(),
+        ),
+    };
+    Diagnostic::error(loc, message).code(code).label(loc, label)
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use derive_rustc_index::Idx;
+use ra_ap_rustc_index::IndexVec;
+
+use crate::{
+    builtin::BuiltinMacro,
+    import::Segment,
+    item::Item,
+    krate::{Crate, Extern},
+    mbe::MacroRules,
+    name::Name,
+    path::Path,
+    scope::Scope,
+    source::SourceLoc,
+    syntax::Node,
+};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+pub struct MacroRulesId(u32);
+
+/// A link in a chain of `macro_rules!` definitions in textual scope, which is linked to the one
+/// before it so that each point in a crate can refer to the definitions in scope there by the
+/// latest of them.
+#[derive(Clone, Copy)]
+enum Link {
+    Def(Name, Item),
+    /// The definitions at the end of a `#[macro_use]` module whose file is loaded later, which
+    /// lead back to those before its declaration.
+    MacroUse(Path),
+    /// The definitions at the end of the expansion of a macro call in item or statement position,
+    /// which is parsed later and leads back to those before the call.
+    Expansion(Node),
+}
+
+/// Whether a macro is called like a function or used in a `#[derive]`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MacroKind {
+    Bang,
+    Derive,
+}
+
+impl MacroKind {
+    pub fn descr(self) -> &'static str {
+        match self {
+            Self::Bang => "macro",
+            Self::Derive => "derive macro",
+        }
+    }
+}
+
+/// A macro call, to be resolved once imports are.
+pub struct MacroCall {
+    /// The [`SyntaxKind::MACRO_CALL`](ra_ap_parser::SyntaxKind::MACRO_CALL), which for a derive
+    /// is one added after the item it is applied to.
+    pub node: Node,
+    pub kind: MacroKind,
+    pub path: Vec<(Segment, SourceLoc)>,
+    pub scope: Scope,
+    /// The latest `macro_rules!` definition in textual scope.
+    pub textual: Option<MacroRulesId>,
+}
+
+/// A crate loaded with `#[macro_use] extern crate`, along with the names of the macros it
+/// imports, or `None` for all of them.
+pub type MacroUse = (Extern, Option<Vec<Name>>);
+
+/// The `macro_rules!` definitions of all crates, and the crates whose macros each crate imports.
+pub struct Macros {
+    links: IndexVec<MacroRulesId, (Link, Option<MacroRulesId>)>,
+    /// The latest definition in textual scope at the end of each module file.
+    ends: HashMap<Path, Option<MacroRulesId>>,
+    /// The latest definition in textual scope at the end of each macro call's expansion.
+    expansion_ends: HashMap<Node, Option<MacroRulesId>>,
+    macro_use: HashMap<Crate, Vec<MacroUse>>,
+    /// The compiled rules of each definition whose rules are valid, by its
+    /// [`SyntaxKind::MACRO_RULES`](ra_ap_parser::SyntaxKind::MACRO_RULES) node.
+    rules: HashMap<Node, MacroRules>,
+    /// The built-in macro that each `#[rustc_builtin_macro]` definition stands for, if it is one
+    /// that can be expanded.
+    builtins: HashMap<Node, BuiltinMacro>,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self {
+            links: IndexVec::new(),
+            ends: HashMap::new(),
+            expansion_ends: HashMap::new(),
+            macro_use: HashMap::new(),
+            rules: HashMap::new(),
+            builtins: HashMap::new(),
+        }
+    }
+
+    /// Define a macro after the `prev` definition, returning the new latest definition.
+    pub fn define(&mut self, prev: Option<MacroRulesId>, name: Name, item: Item) -> MacroRulesId {
+        self.links.push((Link::Def(name, item), prev))
+    }
+
+    /// Bring the definitions of a `#[macro_use]` module into scope after the `prev` definition,
+    /// returning the new latest definition.
+    pub fn macro_use_module(&mut self, prev: Option<MacroRulesId>, path: Path) -> MacroRulesId {
+        self.links.push((Link::MacroUse(path), prev))
+    }
+
+    /// Bring the definitions of the expansion of the macro call at `node` into scope after the
+    /// `prev` definition, returning the new latest definition.
+    pub fn expansion(&mut self, prev: Option<MacroRulesId>, node: Node) -> MacroRulesId {
+        self.links.push((Link::Expansion(node), prev))
+    }
+
+    pub fn set_end(&mut self, module: Path, end: Option<MacroRulesId>) {
+        self.ends.insert(module, end);
+    }
+
+    pub fn set_expansion_end(&mut self, node: Node, end: Option<MacroRulesId>) {
+        self.expansion_ends.insert(node, end);
+    }
+
+    pub fn macro_use_crate(&mut self, krate: Crate, dep: Extern, names: Option<Vec<Name>>) {
+        self.macro_use.entry(krate).or_default().push((dep, names));
+    }
+
+    pub fn macro_use(&self, krate: Crate) -> &[MacroUse] {
+        self.macro_use.get(&krate).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn set_rules(&mut self, node: Node, rules: MacroRules) {
+        self.rules.insert(node, rules);
+    }
+
+    pub fn rules(&self, node: Node) -> Option<&MacroRules> {
+        self.rules.get(&node)
+    }
+
+    pub fn set_builtin(&mut self, node: Node, builtin: BuiltinMacro) {
+        self.builtins.insert(node, builtin);
+    }
+
+    pub fn builtin(&self, node: Node) -> Option<BuiltinMacro> {
+        self.builtins.get(&node).copied()
+    }
+
+    /// The latest definition from `next` on with the given name.
+    pub fn textual(&self, mut next: Option<MacroRulesId>, name: Name) -> Option<Item> {
+        while let Some(id) = next {
+            let (link, prev) = self.links[id];
+            next = match link {
+                Link::Def(n, item) if n == name => return Some(item),
+                Link::Def(..) => prev,
+                // The module's file couldn't be loaded if it has no end.
+                Link::MacroUse(module) => self.ends.get(&module).copied().unwrap_or(prev),
+                // Likewise if the call hasn't been expanded.
+                Link::Expansion(node) => self.expansion_ends.get(&node).copied().unwrap_or(prev),
+            };
+        }
+        None
+    }
+}
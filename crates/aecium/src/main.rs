@@ -1,10 +1,27 @@
+mod attr;
+mod builtin;
+mod cfg;
 mod cli;
+mod derive;
+mod diagnostic;
 mod encoding;
+mod hygiene;
+mod import;
+mod item;
+mod krate;
+mod local;
+mod macros;
+mod manifest;
+mod mbe;
 mod name;
 mod path;
+mod prelude;
 mod scope;
 mod source;
 mod syntax;
+mod sysroot;
+mod target;
+mod vis;
 
 fn main() -> std::process::ExitCode {
     cli::cli()
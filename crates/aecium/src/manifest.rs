@@ -0,0 +1,763 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{self, Path, PathBuf},
+};
+
+use ra_ap_parser::Edition;
+
+use crate::cfg::CfgFlag;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TargetKind {
+    Lib,
+    Bin,
+}
+
+/// A crate built from a package: its library or one of its binaries.
+#[derive(Debug)]
+pub struct CrateTarget {
+    pub kind: TargetKind,
+    pub name: String,
+    pub root: PathBuf,
+    pub edition: Edition,
+    /// Whether this is a procedural macro library, which can use the `proc_macro` crate without
+    /// depending on it.
+    pub proc_macro: bool,
+}
+
+/// An entry in `[dependencies]`.
+#[derive(Debug)]
+pub struct Dependency {
+    /// The key in `[dependencies]`.
+    pub key: String,
+    /// Whether the key renames the package via `package = "..."`.
+    pub renamed: bool,
+    /// The dependency's `Cargo.toml`, if it has a `path` so it can be loaded.
+    pub manifest: Option<PathBuf>,
+    pub features: Vec<String>,
+    pub default_features: bool,
+    pub optional: bool,
+}
+
+/// The parts of a `Cargo.toml` that determine how its crates are checked.
+#[derive(Debug)]
+pub struct Manifest {
+    pub name: String,
+    pub targets: Vec<CrateTarget>,
+    /// The `CARGO_PKG_*` and `CARGO_MANIFEST_DIR` environment variables Cargo sets when compiling
+    /// the package's crates.
+    pub env: Vec<(String, String)>,
+    /// Each feature and the feature strings it enables, including implicit optional dependency
+    /// features.
+    features: BTreeMap<String, Vec<String>>,
+    dependencies: Vec<Dependency>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path:?}: {e}"))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(dir, &text).map_err(|e| format!("failed to parse {path:?}: {e}"))
+    }
+
+    fn parse(dir: &Path, text: &str) -> Result<Self, String> {
+        let toml: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let package = table(&toml, "package")?.ok_or("missing `[package]`")?;
+        let package = &inherit(dir, package)?;
+        let name = string(package, "name")?.ok_or("missing `package.name`")?;
+        let edition = parse_edition(package)?.unwrap_or(Edition::Edition2015);
+        let mut targets = Vec::new();
+
+        let lib = table(&toml, "lib")?;
+        let lib_path = match lib.map(|lib| string(lib, "path")).transpose()?.flatten() {
+            Some(path) => Some(dir.join(path)),
+            None => Some(dir.join("src/lib.rs")).filter(|path| path.exists()),
+        };
+        if let Some(root) = lib_path {
+            let lib_name = lib.map(|lib| string(lib, "name")).transpose()?.flatten();
+            targets.push(CrateTarget {
+                kind: TargetKind::Lib,
+                name: lib_name.unwrap_or_else(|| name.replace('-', "_")),
+                root,
+                edition: lib
+                    .map(parse_edition)
+                    .transpose()?
+                    .flatten()
+                    .unwrap_or(edition),
+                proc_macro: lib
+                    .and_then(|lib| lib.get("proc-macro"))
+                    .and_then(toml::Value::as_bool)
+                    .unwrap_or(false),
+            });
+        }
+
+        let bins = match toml.get("bin") {
+            None => &Vec::new(),
+            Some(toml::Value::Array(bins)) => bins,
+            Some(_) => return Err("`bin` must be an array of tables".to_owned()),
+        };
+        for bin in bins {
+            let bin = bin.as_table().ok_or("`bin` must be an array of tables")?;
+            let bin_name = string(bin, "name")?.ok_or("missing `bin.name`")?;
+            let root = match string(bin, "path")? {
+                Some(path) => dir.join(path),
+                None => {
+                    let mut candidates = vec![
+                        dir.join(format!("src/bin/{bin_name}.rs")),
+                        dir.join(format!("src/bin/{bin_name}/main.rs")),
+                    ];
+                    if bin_name == name {
+                        candidates.push(dir.join("src/main.rs"));
+                    }
+                    candidates
+                        .into_iter()
+                        .find(|path| path.exists())
+                        .ok_or_else(|| format!("can't find `{bin_name}` bin"))?
+                }
+            };
+            targets.push(CrateTarget {
+                kind: TargetKind::Bin,
+                name: bin_name,
+                root,
+                edition: parse_edition(bin)?.unwrap_or(edition),
+                proc_macro: false,
+            });
+        }
+        let autobins = match package.get("autobins") {
+            None => true,
+            Some(toml::Value::Boolean(b)) => *b,
+            Some(_) => return Err("`package.autobins` must be a boolean".to_owned()),
+        };
+        if autobins {
+            for (bin_name, root) in discover_bins(dir, &name) {
+                let explicit = targets.iter().any(|target| {
+                    target.kind == TargetKind::Bin
+                        && (target.name == bin_name || target.root == root)
+                });
+                if !explicit {
+                    targets.push(CrateTarget {
+                        kind: TargetKind::Bin,
+                        name: bin_name,
+                        root,
+                        edition,
+                        proc_macro: false,
+                    });
+                }
+            }
+        }
+        if targets.is_empty() {
+            return Err("no targets specified in the manifest".to_owned());
+        }
+
+        let mut features: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        if let Some(table) = table(&toml, "features")? {
+            for (feature, enables) in table {
+                let enables = enables
+                    .as_array()
+                    .and_then(|xs| xs.iter().map(|x| x.as_str().map(str::to_owned)).collect())
+                    .ok_or_else(|| format!("feature `{feature}` must be a list of strings"))?;
+                features.insert(feature.clone(), enables);
+            }
+        }
+        // Optional dependencies get an implicit feature unless some feature refers to them with
+        // `dep:`.
+        let explicit: BTreeSet<&str> = features
+            .values()
+            .flatten()
+            .filter_map(|x| x.strip_prefix("dep:"))
+            .collect();
+        let mut implicit = Vec::new();
+        for deps in dependency_tables(&toml) {
+            for (dep, spec) in deps {
+                let optional = spec.get("optional").and_then(toml::Value::as_bool);
+                if optional == Some(true) && !explicit.contains(dep.as_str()) {
+                    implicit.push(dep.clone());
+                }
+            }
+        }
+        for dep in implicit {
+            features
+                .entry(dep.clone())
+                .or_insert_with(|| vec![format!("dep:{dep}")]);
+        }
+
+        let mut dependencies = Vec::new();
+        for deps in normal_dependency_tables(&toml) {
+            for (key, spec) in deps {
+                let empty = toml::Table::new();
+                let spec = spec.as_table().unwrap_or(&empty);
+                let path = string(spec, "path")?;
+                let features = match spec.get("features") {
+                    None => Vec::new(),
+                    Some(features) => features
+                        .as_array()
+                        .and_then(|xs| xs.iter().map(|x| x.as_str().map(str::to_owned)).collect())
+                        .ok_or_else(|| format!("`{key}.features` must be a list of strings"))?,
+                };
+                let flag = |name: &str| spec.get(name).and_then(toml::Value::as_bool);
+                dependencies.push(Dependency {
+                    key: key.clone(),
+                    renamed: spec.contains_key("package"),
+                    manifest: path.map(|path| dir.join(path).join("Cargo.toml")),
+                    features,
+                    default_features: flag("default-features").unwrap_or(true),
+                    optional: flag("optional").unwrap_or(false),
+                });
+            }
+        }
+
+        let env = package_env(dir, &name, package);
+        Ok(Self {
+            name,
+            targets,
+            env,
+            features,
+            dependencies,
+        })
+    }
+
+    pub fn lib(&self) -> Option<&CrateTarget> {
+        self.targets
+            .iter()
+            .find(|target| target.kind == TargetKind::Lib)
+    }
+
+    /// Resolve a feature selection, returning every feature it enables.
+    fn enabled_features(
+        &self,
+        requested: &BTreeSet<String>,
+        default: bool,
+    ) -> Result<BTreeSet<&str>, String> {
+        let mut stack: Vec<&str> = Vec::new();
+        if default && self.features.contains_key("default") {
+            stack.push("default");
+        }
+        for feature in requested {
+            let Some((feature, _)) = self.features.get_key_value(feature) else {
+                return Err(format!(
+                    "package `{}` does not have the feature `{feature}`",
+                    self.name,
+                ));
+            };
+            stack.push(feature);
+        }
+        let mut enabled = BTreeSet::new();
+        while let Some(feature) = stack.pop() {
+            if !enabled.insert(feature) {
+                continue;
+            }
+            for enable in &self.features[feature] {
+                if enable.starts_with("dep:") {
+                    continue;
+                }
+                // Enabling a dependency's feature with `dep/feature` also enables that optional
+                // dependency's implicit feature, unless written as `dep?/feature`.
+                let enable = match enable.split_once('/') {
+                    Some((dep, _)) if dep.ends_with('?') => continue,
+                    Some((dep, _)) => dep,
+                    None => enable,
+                };
+                if self.features.contains_key(enable) {
+                    stack.push(enable);
+                }
+            }
+        }
+        Ok(enabled)
+    }
+
+    /// The dependencies activated by the given features, along with the features each one should
+    /// have enabled.
+    fn active_deps(&self, enabled: &BTreeSet<&str>) -> Vec<(usize, Vec<String>)> {
+        let enables: Vec<&str> = enabled
+            .iter()
+            .flat_map(|feature| &self.features[*feature])
+            .map(String::as_str)
+            .collect();
+        let mut active = Vec::new();
+        for (i, dep) in self.dependencies.iter().enumerate() {
+            let key = dep.key.as_str();
+            let on = !dep.optional
+                || enables.iter().any(|enable| {
+                    enable.strip_prefix("dep:") == Some(key)
+                        || enable.split_once('/').is_some_and(|(name, _)| name == key)
+                });
+            if !on {
+                continue;
+            }
+            let mut features = dep.features.clone();
+            for enable in &enables {
+                if let Some((name, feature)) = enable.split_once('/') {
+                    if name.strip_suffix('?').unwrap_or(name) == key {
+                        features.push(feature.to_owned());
+                    }
+                }
+            }
+            active.push((i, features));
+        }
+        active
+    }
+}
+
+/// A package in a dependency graph, with its features resolved.
+pub struct Package {
+    pub manifest: Manifest,
+    /// The `feature = "..."` options to enable.
+    pub features: Vec<CfgFlag>,
+    /// The extern name of each enabled dependency that has a library, along with its index if it
+    /// was loaded.
+    pub deps: Vec<(String, Option<usize>)>,
+}
+
+/// The features requested of a package by the command line or its dependents.
+#[derive(Clone, Default)]
+struct Request {
+    features: BTreeSet<String>,
+    default: bool,
+}
+
+/// Load a package and all of its path dependencies, unifying features across the graph like
+/// Cargo's `--features`, `--all-features` and `--no-default-features`. Packages are ordered with
+/// dependencies before their dependents, so the root package comes last.
+pub fn resolve(
+    root: &Path,
+    requested: &[String],
+    all_features: bool,
+    no_default_features: bool,
+) -> Result<Vec<Package>, String> {
+    let canonical =
+        |path: &Path| fs::canonicalize(path).map_err(|e| format!("failed to read {path:?}: {e}"));
+    let mut manifests = vec![Manifest::load(root)?];
+    let mut indices = HashMap::from([(canonical(root)?, 0)]);
+    // For each package, the index of the package each of its path dependencies refers to.
+    let mut edges: Vec<Vec<Option<usize>>> = Vec::new();
+    while edges.len() < manifests.len() {
+        let paths: Vec<Option<PathBuf>> = manifests[edges.len()]
+            .dependencies
+            .iter()
+            .map(|dep| dep.manifest.clone())
+            .collect();
+        let mut targets = Vec::new();
+        for path in paths {
+            let Some(path) = path else {
+                targets.push(None);
+                continue;
+            };
+            let index = *indices.entry(canonical(&path)?).or_insert(manifests.len());
+            if index == manifests.len() {
+                manifests.push(Manifest::load(&path)?);
+            }
+            targets.push(Some(index));
+        }
+        edges.push(targets);
+    }
+
+    let mut requests = vec![Request::default(); manifests.len()];
+    requests[0] = Request {
+        features: requested.iter().cloned().collect(),
+        default: !no_default_features,
+    };
+    if all_features {
+        requests[0]
+            .features
+            .extend(manifests[0].features.keys().cloned());
+    }
+    let mut active = Vec::new();
+    loop {
+        let mut changed = false;
+        active.clear();
+        for (i, manifest) in manifests.iter().enumerate() {
+            let enabled = manifest.enabled_features(&requests[i].features, requests[i].default)?;
+            let deps = manifest.active_deps(&enabled);
+            for (dep, features) in &deps {
+                let Some(j) = edges[i][*dep] else {
+                    continue;
+                };
+                let request = &mut requests[j];
+                if manifest.dependencies[*dep].default_features && !request.default {
+                    request.default = true;
+                    changed = true;
+                }
+                for feature in features {
+                    changed |= request.features.insert(feature.clone());
+                }
+            }
+            active.push(deps);
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // Order the packages reachable from the root so that dependencies come first.
+    let mut order = Vec::new();
+    let mut state = vec![None; manifests.len()];
+    let mut stack = vec![(0, 0)];
+    state[0] = Some(false);
+    while let Some((i, next)) = stack.pop() {
+        let Some(&(dep, _)) = active[i].get(next) else {
+            state[i] = Some(true);
+            order.push(i);
+            continue;
+        };
+        stack.push((i, next + 1));
+        let Some(j) = edges[i][dep] else {
+            continue;
+        };
+        match state[j] {
+            None => {
+                state[j] = Some(false);
+                stack.push((j, 0));
+            }
+            Some(false) => {
+                let name = &manifests[j].name;
+                return Err(format!("cyclic dependency on package `{name}`"));
+            }
+            Some(true) => {}
+        }
+    }
+
+    let mut positions = vec![0; manifests.len()];
+    for (position, &i) in order.iter().enumerate() {
+        positions[i] = position;
+    }
+    let mut packages = Vec::new();
+    for &i in &order {
+        let manifest = &manifests[i];
+        let enabled = manifest.enabled_features(&requests[i].features, requests[i].default)?;
+        let features = enabled
+            .into_iter()
+            .map(|feature| CfgFlag {
+                key: "feature".to_owned(),
+                value: Some(feature.to_owned()),
+            })
+            .collect();
+        let mut deps = Vec::new();
+        for &(dep, _) in &active[i] {
+            let dependency = &manifest.dependencies[dep];
+            let Some(j) = edges[i][dep] else {
+                deps.push((dependency.key.replace('-', "_"), None));
+                continue;
+            };
+            // Like Cargo, ignore dependencies that are missing a library.
+            let Some(lib) = manifests[j].lib() else {
+                continue;
+            };
+            let name = match dependency.renamed {
+                true => dependency.key.replace('-', "_"),
+                false => lib.name.clone(),
+            };
+            deps.push((name, Some(positions[j])));
+        }
+        packages.push((i, features, deps));
+    }
+    let mut manifests: Vec<Option<Manifest>> = manifests.into_iter().map(Some).collect();
+    Ok(packages
+        .into_iter()
+        .map(|(i, features, deps)| Package {
+            manifest: manifests[i].take().unwrap(),
+            features,
+            deps,
+        })
+        .collect())
+}
+
+fn table<'a>(toml: &'a toml::Table, key: &str) -> Result<Option<&'a toml::Table>, String> {
+    match toml.get(key) {
+        None => Ok(None),
+        Some(toml::Value::Table(table)) => Ok(Some(table)),
+        Some(_) => Err(format!("`{key}` must be a table")),
+    }
+}
+
+fn string(toml: &toml::Table, key: &str) -> Result<Option<String>, String> {
+    match toml.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("`{key}` must be a string")),
+    }
+}
+
+/// A `[package]` table of a manifest in `dir` with the fields it inherits from its workspace, like
+/// `edition.workspace = true`, filled in from `[workspace.package]`.
+fn inherit(dir: &Path, package: &toml::Table) -> Result<toml::Table, String> {
+    let inherited = |value: &toml::Value| {
+        let workspace = value.get("workspace").and_then(toml::Value::as_bool);
+        workspace == Some(true)
+    };
+    let mut package = package.clone();
+    if !package.values().any(inherited) {
+        return Ok(package);
+    }
+    let workspace = workspace_package(dir, &package)?;
+    for (key, value) in &mut package {
+        if inherited(value) {
+            *value = workspace
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("`package.{key}` is not in `[workspace.package]`"))?;
+        }
+    }
+    Ok(package)
+}
+
+/// The `[workspace.package]` table of the workspace that the package in `dir` belongs to: the one
+/// at `package.workspace`, or else the nearest one in the directory or its parents.
+fn workspace_package(dir: &Path, package: &toml::Table) -> Result<toml::Table, String> {
+    let dir = path::absolute(dir).unwrap_or(dir.to_path_buf());
+    let roots: Vec<PathBuf> = match string(package, "workspace")? {
+        Some(root) => vec![dir.join(root)],
+        None => dir.ancestors().map(Path::to_path_buf).collect(),
+    };
+    for root in roots {
+        let path = root.join("Cargo.toml");
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let toml: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| format!("failed to parse {path:?}: {e}"))?;
+        if let Some(workspace) = table(&toml, "workspace")? {
+            return Ok(table(workspace, "package")?.cloned().unwrap_or_default());
+        }
+    }
+    Err("fields are inherited from a workspace, but there is none".to_owned())
+}
+
+/// The environment variables Cargo sets from the `[package]` table of a manifest in `dir`.
+fn package_env(dir: &Path, name: &str, package: &toml::Table) -> Vec<(String, String)> {
+    let field = |key: &str| package.get(key).and_then(toml::Value::as_str).unwrap_or("");
+    let version = match package.get("version") {
+        None => "0.0.0",
+        Some(version) => version.as_str().unwrap_or(""),
+    };
+    let (version_core, _) = version.split_once('+').unwrap_or((version, ""));
+    let (numbers, pre) = version_core.split_once('-').unwrap_or((version_core, ""));
+    let mut numbers = numbers.splitn(3, '.');
+    let mut number = || numbers.next().unwrap_or("").to_owned();
+    let authors = package.get("authors").and_then(toml::Value::as_array);
+    let authors = authors
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_str);
+    // Like Cargo, give the directory as an absolute path.
+    let manifest = path::absolute(dir.join("Cargo.toml")).unwrap_or(dir.join("Cargo.toml"));
+    let manifest_dir = manifest.parent().unwrap_or(dir);
+    let env = [
+        (
+            "CARGO_MANIFEST_DIR",
+            manifest_dir.to_string_lossy().into_owned(),
+        ),
+        ("CARGO_PKG_AUTHORS", authors.collect::<Vec<_>>().join(":")),
+        ("CARGO_PKG_DESCRIPTION", field("description").to_owned()),
+        ("CARGO_PKG_HOMEPAGE", field("homepage").to_owned()),
+        ("CARGO_PKG_LICENSE", field("license").to_owned()),
+        ("CARGO_PKG_LICENSE_FILE", field("license-file").to_owned()),
+        ("CARGO_PKG_NAME", name.to_owned()),
+        ("CARGO_PKG_README", field("readme").to_owned()),
+        ("CARGO_PKG_REPOSITORY", field("repository").to_owned()),
+        ("CARGO_PKG_RUST_VERSION", field("rust-version").to_owned()),
+        ("CARGO_PKG_VERSION", version.to_owned()),
+        ("CARGO_PKG_VERSION_MAJOR", number()),
+        ("CARGO_PKG_VERSION_MINOR", number()),
+        ("CARGO_PKG_VERSION_PATCH", number()),
+        ("CARGO_PKG_VERSION_PRE", pre.to_owned()),
+    ];
+    env.into_iter()
+        .map(|(key, value)| (key.to_owned(), value))
+        .collect()
+}
+
+fn parse_edition(toml: &toml::Table) -> Result<Option<Edition>, String> {
+    string(toml, "edition")?
+        .map(|edition| edition.parse().map_err(|e| format!("{e}")))
+        .transpose()
+}
+
+/// All `[dependencies]`-like tables, including target-specific ones.
+fn dependency_tables(toml: &toml::Table) -> impl Iterator<Item = &toml::Table> {
+    const KEYS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let targets = toml
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(toml::Value::as_table));
+    std::iter::once(toml)
+        .chain(targets)
+        .flat_map(|toml| KEYS.iter().filter_map(|key| toml.get(*key)))
+        .filter_map(toml::Value::as_table)
+}
+
+/// The `[dependencies]` tables, including target-specific ones.
+fn normal_dependency_tables(toml: &toml::Table) -> impl Iterator<Item = &toml::Table> {
+    let targets = toml
+        .get("target")
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values().filter_map(toml::Value::as_table));
+    std::iter::once(toml)
+        .chain(targets)
+        .filter_map(|toml| toml.get("dependencies"))
+        .filter_map(toml::Value::as_table)
+}
+
+/// Binaries found by Cargo's target auto-discovery: `src/main.rs`, `src/bin/*.rs` and
+/// `src/bin/*/main.rs`.
+fn discover_bins(dir: &Path, package: &str) -> Vec<(String, PathBuf)> {
+    let mut bins = Vec::new();
+    let main = dir.join("src/main.rs");
+    if main.exists() {
+        bins.push((package.to_owned(), main));
+    }
+    let Ok(entries) = fs::read_dir(dir.join("src/bin")) else {
+        return bins;
+    };
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if path.extension().is_some_and(|ext| ext == "rs") {
+            found.push((stem.to_owned(), path));
+        } else if path.join("main.rs").exists() {
+            found.push((stem.to_owned(), path.join("main.rs")));
+        }
+    }
+    found.sort();
+    bins.extend(found);
+    bins
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
+
+    use ra_ap_parser::Edition;
+
+    use crate::manifest::{resolve, Manifest, TargetKind};
+
+    fn fixture_path(package: &str) -> PathBuf {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        dir.join(package).join("Cargo.toml")
+    }
+
+    fn fixture() -> Manifest {
+        Manifest::load(&fixture_path("package")).unwrap()
+    }
+
+    #[test]
+    fn test_targets() {
+        let manifest = fixture();
+        let targets: Vec<_> = manifest
+            .targets
+            .iter()
+            .map(|target| (target.kind, target.name.as_str(), target.edition))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (TargetKind::Lib, "my_package", Edition::Edition2021),
+                (TargetKind::Bin, "tool", Edition::Edition2018),
+                (TargetKind::Bin, "my-package", Edition::Edition2021),
+                (TargetKind::Bin, "extra", Edition::Edition2021),
+            ],
+        );
+        assert!(manifest.targets[1].root.ends_with("src/tool.rs"));
+    }
+
+    #[test]
+    fn test_env() {
+        let manifest = fixture();
+        let env: HashMap<_, _> = manifest
+            .env
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(env["CARGO_PKG_NAME"], "my-package");
+        assert_eq!(env["CARGO_PKG_VERSION"], "1.2.3-beta.1+build");
+        assert_eq!(env["CARGO_PKG_VERSION_MAJOR"], "1");
+        assert_eq!(env["CARGO_PKG_VERSION_PATCH"], "3");
+        assert_eq!(env["CARGO_PKG_VERSION_PRE"], "beta.1");
+        assert_eq!(env["CARGO_PKG_AUTHORS"], "A:B <b@example.com>");
+        assert_eq!(env["CARGO_PKG_DESCRIPTION"], "");
+        assert!(Path::new(env["CARGO_MANIFEST_DIR"]).is_absolute());
+        assert!(env["CARGO_MANIFEST_DIR"].ends_with("fixtures/package"));
+    }
+
+    #[test]
+    fn test_workspace() {
+        let manifest = Manifest::load(&fixture_path("workspace/member")).unwrap();
+        assert_eq!(manifest.targets[0].edition, Edition::Edition2021);
+        let env: HashMap<_, _> = manifest.env.into_iter().collect();
+        assert_eq!(env["CARGO_PKG_VERSION"], "0.3.1");
+        assert_eq!(env["CARGO_PKG_VERSION_MINOR"], "3");
+        assert_eq!(env["CARGO_PKG_DESCRIPTION"], "A member of a workspace");
+    }
+
+    #[test]
+    fn test_features() {
+        let manifest = fixture_path("package");
+        let features = |requested: &[&str], all: bool, no_default: bool| {
+            let requested: Vec<String> = requested.iter().map(|&f| f.to_owned()).collect();
+            let packages = resolve(&manifest, &requested, all, no_default)?;
+            let flags = &packages.last().unwrap().features;
+            let values = flags.iter().map(|flag| flag.value.clone().unwrap());
+            Ok::<Vec<String>, String>(values.collect())
+        };
+        assert_eq!(features(&[], false, false).unwrap(), ["default", "std"]);
+        assert!(features(&[], false, true).unwrap().is_empty());
+        assert_eq!(
+            features(&["derive"], false, true).unwrap(),
+            ["derive", "serde"]
+        );
+        assert_eq!(
+            features(&[], true, false).unwrap(),
+            ["default", "derive", "serde", "std"],
+        );
+        assert!(features(&["nope"], false, false).is_err());
+    }
+
+    #[test]
+    fn test_graph() {
+        let manifest = fixture_path("graph/app");
+        let graph = |requested: &[String]| {
+            let packages = resolve(&manifest, requested, false, false).unwrap();
+            packages
+                .iter()
+                .map(|package| {
+                    let features = package.features.iter();
+                    let features = features.map(|flag| flag.value.clone().unwrap()).collect();
+                    (
+                        package.manifest.name.clone(),
+                        features,
+                        package.deps.clone(),
+                    )
+                })
+                .collect::<Vec<(String, Vec<String>, Vec<(String, Option<usize>)>)>>()
+        };
+        let deps = |deps: &[(&str, usize)]| -> Vec<(String, Option<usize>)> {
+            deps.iter()
+                .map(|&(name, i)| (name.to_owned(), Some(i)))
+                .collect()
+        };
+        assert_eq!(
+            graph(&[]),
+            [
+                ("helper-lib".to_owned(), vec![], deps(&[])),
+                (
+                    "util".to_owned(),
+                    vec!["default".to_owned(), "fmt".to_owned(), "std".to_owned()],
+                    deps(&[("h", 0)]),
+                ),
+                ("app".to_owned(), vec![], deps(&[("util", 1)])),
+            ],
+        );
+        let extra = graph(&["extra".to_owned()]);
+        assert_eq!(extra[0].1, ["fast"]);
+        assert_eq!(extra[2].2, deps(&[("helper_lib", 0), ("util", 1)]));
+    }
+}
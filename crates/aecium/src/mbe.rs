@@ -0,0 +1,1375 @@
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use ra_ap_parser::{Edition, Input, PrefixEntryPoint, Step, SyntaxKind};
+use ra_ap_rustc_index::Idx;
+
+use crate::{
+    attr::Token,
+    diagnostic::Diagnostic,
+    hygiene::{Mark, SyntaxContexts},
+    name::{sym, Name, Names},
+    path::Path,
+    source::SourceLoc,
+};
+
+/// What a metavariable matches, like the `expr` in `$e:expr`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Fragment {
+    Block,
+    Expr,
+    /// An `expr_2021`, or an `expr` before the 2024 edition, which doesn't match `_` or `const`
+    /// blocks.
+    Expr2021,
+    Ident,
+    Item,
+    Lifetime,
+    Literal,
+    Meta,
+    /// A `pat` since the 2021 edition, which matches top-level or-patterns.
+    Pat,
+    PatParam,
+    Path,
+    Stmt,
+    Tt,
+    Ty,
+    Vis,
+}
+
+impl Fragment {
+    fn parse(name: &str, edition: Edition) -> Option<Self> {
+        Some(match name {
+            "block" => Self::Block,
+            "expr" if edition >= Edition::Edition2024 => Self::Expr,
+            "expr" | "expr_2021" => Self::Expr2021,
+            "ident" => Self::Ident,
+            "item" => Self::Item,
+            "lifetime" => Self::Lifetime,
+            "literal" => Self::Literal,
+            "meta" => Self::Meta,
+            "pat" if edition >= Edition::Edition2021 => Self::Pat,
+            "pat" | "pat_param" => Self::PatParam,
+            "path" => Self::Path,
+            "stmt" => Self::Stmt,
+            "tt" => Self::Tt,
+            "ty" => Self::Ty,
+            "vis" => Self::Vis,
+            _ => return None,
+        })
+    }
+
+    fn descr(self) -> &'static str {
+        match self {
+            Self::Block => "a block",
+            Self::Expr | Self::Expr2021 => "an expression",
+            Self::Ident => "an identifier",
+            Self::Item => "an item",
+            Self::Lifetime => "a lifetime",
+            Self::Literal => "a literal",
+            Self::Meta => "an attribute",
+            Self::Pat | Self::PatParam => "a pattern",
+            Self::Path => "a path",
+            Self::Stmt => "a statement",
+            Self::Tt => "a token tree",
+            Self::Ty => "a type",
+            Self::Vis => "a visibility",
+        }
+    }
+
+    /// Whether a fragment of this kind can start with the given token. Like rustc, the matcher
+    /// only tries to parse a fragment where this holds, so that it doesn't consider a call
+    /// ambiguous when a literal token of the matcher could come next instead.
+    fn may_begin_with(self, kind: SyntaxKind, edition: Edition) -> bool {
+        use SyntaxKind as K;
+        match self {
+            Self::Expr => can_begin_expr(kind) && kind != K::LET_KW,
+            Self::Expr2021 => {
+                can_begin_expr(kind) && !matches!(kind, K::LET_KW | K::UNDERSCORE | K::CONST_KW)
+            }
+            Self::Ty => can_begin_type(kind, edition),
+            Self::Ident => is_ident(kind, edition),
+            Self::Lifetime => kind == K::LIFETIME_IDENT,
+            Self::Literal => {
+                kind.is_literal() || matches!(kind, K::TRUE_KW | K::FALSE_KW | K::MINUS)
+            }
+            Self::Vis => {
+                is_ident(kind, edition) || kind == K::COMMA || can_begin_type(kind, edition)
+            }
+            Self::Block => kind == K::L_CURLY,
+            Self::Path | Self::Meta => {
+                is_ident(kind, edition) || matches!(kind, K::COLON | K::DOLLAR)
+            }
+            Self::Pat | Self::PatParam => {
+                is_ident(kind, edition)
+                    || kind.is_literal()
+                    || matches!(
+                        kind,
+                        K::L_PAREN
+                            | K::L_BRACK
+                            | K::L_ANGLE
+                            | K::AMP
+                            | K::MINUS
+                            | K::DOT
+                            | K::COLON
+                            | K::UNDERSCORE
+                            | K::DOLLAR
+                    )
+                    || self == Self::Pat && kind == K::PIPE
+            }
+            Self::Item | Self::Stmt | Self::Tt => !is_close(kind),
+        }
+    }
+
+    /// Parse a fragment of this kind at the start of `tokens`, returning how many tokens it takes
+    /// up.
+    fn parse_prefix(self, names: &Names, tokens: &[Token], edition: Edition) -> Option<usize> {
+        use SyntaxKind as K;
+        let first = tokens.first()?;
+        let entry = match self {
+            Self::Tt => return tree_len(tokens),
+            Self::Ident => return is_ident(first.kind, edition).then_some(1),
+            Self::Lifetime => return (first.kind == K::LIFETIME_IDENT).then_some(1),
+            Self::Literal => {
+                let minus = usize::from(first.kind == K::MINUS);
+                let lit = tokens.get(minus)?;
+                let bool = minus == 0 && matches!(lit.kind, K::TRUE_KW | K::FALSE_KW);
+                return (lit.kind.is_literal() || bool).then_some(minus + 1);
+            }
+            Self::Block => PrefixEntryPoint::Block,
+            Self::Expr | Self::Expr2021 => PrefixEntryPoint::Expr,
+            Self::Item => PrefixEntryPoint::Item,
+            Self::Meta => PrefixEntryPoint::MetaItem,
+            Self::Pat => PrefixEntryPoint::PatTop,
+            Self::PatParam => PrefixEntryPoint::Pat,
+            Self::Path => PrefixEntryPoint::Path,
+            Self::Stmt => PrefixEntryPoint::Stmt,
+            Self::Ty => PrefixEntryPoint::Ty,
+            Self::Vis => PrefixEntryPoint::Vis,
+        };
+        let output = entry.parse(&to_input(names, tokens, edition), edition);
+        let mut len = 0;
+        for step in output.iter() {
+            match step {
+                Step::Token { n_input_tokens, .. } => len += usize::from(n_input_tokens),
+                Step::FloatSplit { .. } => len += 1,
+                Step::Enter { .. } | Step::Exit => {}
+                Step::Error { .. } => return None,
+            }
+        }
+        (len > 0 || self == Self::Vis).then_some(len)
+    }
+}
+
+fn is_ident(kind: SyntaxKind, edition: Edition) -> bool {
+    kind == SyntaxKind::IDENT || kind.is_keyword(edition)
+}
+
+fn is_open(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::L_PAREN | SyntaxKind::L_BRACK | SyntaxKind::L_CURLY
+    )
+}
+
+fn is_close(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::R_PAREN | SyntaxKind::R_BRACK | SyntaxKind::R_CURLY
+    )
+}
+
+fn can_begin_expr(kind: SyntaxKind) -> bool {
+    use SyntaxKind as K;
+    kind.is_literal()
+        || matches!(
+            kind,
+            K::IDENT
+                | K::LIFETIME_IDENT
+                | K::L_PAREN
+                | K::L_BRACK
+                | K::L_CURLY
+                | K::BANG
+                | K::MINUS
+                | K::STAR
+                | K::AMP
+                | K::PIPE
+                | K::DOT
+                | K::L_ANGLE
+                | K::COLON
+                | K::POUND
+                | K::UNDERSCORE
+                | K::DOLLAR
+                | K::ASYNC_KW
+                | K::BOX_KW
+                | K::BREAK_KW
+                | K::CONST_KW
+                | K::CONTINUE_KW
+                | K::CRATE_KW
+                | K::DO_KW
+                | K::FALSE_KW
+                | K::FOR_KW
+                | K::GEN_KW
+                | K::IF_KW
+                | K::LET_KW
+                | K::LOOP_KW
+                | K::MATCH_KW
+                | K::MOVE_KW
+                | K::RETURN_KW
+                | K::SELF_KW
+                | K::SELF_TYPE_KW
+                | K::STATIC_KW
+                | K::SUPER_KW
+                | K::TRUE_KW
+                | K::TRY_KW
+                | K::UNSAFE_KW
+                | K::WHILE_KW
+                | K::YIELD_KW
+        )
+}
+
+fn can_begin_type(kind: SyntaxKind, edition: Edition) -> bool {
+    use SyntaxKind as K;
+    matches!(
+        kind,
+        K::IDENT
+            | K::LIFETIME_IDENT
+            | K::L_PAREN
+            | K::L_BRACK
+            | K::BANG
+            | K::STAR
+            | K::AMP
+            | K::L_ANGLE
+            | K::COLON
+            | K::UNDERSCORE
+            | K::QUESTION
+            | K::DOLLAR
+            | K::FN_KW
+            | K::UNSAFE_KW
+            | K::EXTERN_KW
+            | K::IMPL_KW
+            | K::FOR_KW
+            | K::SELF_TYPE_KW
+            | K::SELF_KW
+            | K::SUPER_KW
+            | K::CRATE_KW
+    ) || kind == K::DYN_KW && edition >= Edition::Edition2018
+}
+
+/// The number of tokens in the token tree at the start of `tokens`, if any.
+fn tree_len(tokens: &[Token]) -> Option<usize> {
+    let first = tokens.first()?;
+    if is_close(first.kind) {
+        return None;
+    }
+    if !is_open(first.kind) {
+        return Some(1);
+    }
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if is_open(token.kind) {
+            depth += 1;
+        } else if is_close(token.kind) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Whether `b` immediately follows `a` in the source.
+fn joint(names: &Names, a: Token, b: Token) -> bool {
+    a.loc.plus(names.get(a.text).len()) == b.loc
+}
+
+/// Convert tokens from token trees, in which punctuation is split into single characters, to
+/// input for the parser, which puts adjacent punctuation back together as needed.
+pub fn to_input(names: &Names, tokens: &[Token], edition: Edition) -> Input {
+    let mut input = Input::default();
+    for (i, &token) in tokens.iter().enumerate() {
+        let text = names.get(token.text);
+        if token.kind == SyntaxKind::IDENT {
+            let contextual = SyntaxKind::from_contextual_keyword(text, edition);
+            input.push_ident(contextual.unwrap_or(SyntaxKind::IDENT));
+            continue;
+        }
+        input.push(token.kind);
+        let joint = match token.kind {
+            // Lets the parser split a float for nested tuple field access like `x.0.1`.
+            SyntaxKind::FLOAT_NUMBER => !text.ends_with('.'),
+            _ => tokens
+                .get(i + 1)
+                .is_some_and(|&next| next.kind != SyntaxKind::IDENT && joint(names, token, next)),
+        };
+        if joint {
+            input.was_joint();
+        }
+    }
+    input
+}
+
+/// A token tree of a `macro_rules!` definition.
+#[derive(Clone, Copy)]
+enum Tree<'a> {
+    Leaf(Token),
+    /// A delimited group, with the tokens between its delimiters.
+    Group(Token, &'a [Token], Token),
+}
+
+fn trees(tokens: &[Token]) -> Vec<Tree<'_>> {
+    let mut trees = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match tree_len(&tokens[i..]) {
+            Some(len) if len > 1 => {
+                let inner = &tokens[i + 1..i + len - 1];
+                trees.push(Tree::Group(tokens[i], inner, tokens[i + len - 1]));
+                i += len;
+            }
+            _ => {
+                trees.push(Tree::Leaf(tokens[i]));
+                i += 1;
+            }
+        }
+    }
+    trees
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RepeatOp {
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+    /// `?`
+    ZeroOrOne,
+}
+
+/// One step of a compiled matcher, which is matched against a call the way rustc does it: by
+/// keeping every position in the matcher that the tokens so far could have led to.
+enum MatcherLoc {
+    Token(Token),
+    /// The start of a repetition, which declares the metavariables numbered `vars` and is nested
+    /// in `depth` others. Skipping it leads to `end`.
+    Sequence {
+        op: RepeatOp,
+        vars: Range<usize>,
+        depth: usize,
+        end: usize,
+    },
+    /// The end of a repetition without a separator, which can go back to `first`.
+    SequenceOp {
+        op: RepeatOp,
+        first: usize,
+    },
+    /// The first token of a repetition's separator, without which it ends at `end`.
+    SequenceSep {
+        sep: Token,
+        end: usize,
+    },
+    /// The end of a repetition after its separator, which goes back to `first`.
+    SequenceOpAfterSep {
+        first: usize,
+    },
+    MetaVar {
+        name: Token,
+        fragment: Fragment,
+        var: usize,
+        depth: usize,
+    },
+    Eof,
+}
+
+/// Whether the body of a repetition from `first` to the end of the matcher, which has no
+/// separator, can match no tokens at all and so let the repetition go around forever. Like rustc,
+/// this only looks for bodies made up of `vis` fragments and repetitions that may be skipped.
+fn matches_empty(matcher: &[MatcherLoc], first: usize) -> bool {
+    let mut i = first;
+    while i < matcher.len() {
+        match matcher[i] {
+            MatcherLoc::MetaVar {
+                fragment: Fragment::Vis,
+                ..
+            } => i += 1,
+            MatcherLoc::Sequence { op, end, .. } if op != RepeatOp::OneOrMore => i = end,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Part of a transcriber, in which delimited groups other than repetitions are flattened.
+enum Transcribe {
+    Token(Token),
+    /// A metavariable, with its `$`.
+    Var(Token, Token),
+    /// A `$crate`, with its `$`.
+    DollarCrate(Token),
+    Repeat {
+        body: Vec<Transcribe>,
+        sep: Vec<Token>,
+        op: RepeatOp,
+        dollar: Token,
+    },
+}
+
+struct Rule {
+    matcher: Vec<MatcherLoc>,
+    /// The number of each metavariable that the matcher declares.
+    vars: HashMap<Name, usize>,
+    transcriber: Vec<Transcribe>,
+    /// The opening delimiter of the matcher.
+    loc: SourceLoc,
+}
+
+/// What a metavariable matched, indexing into the call's tokens.
+#[derive(Clone)]
+enum Match {
+    Seq(Vec<Match>),
+    Fragment(Fragment, Range<usize>),
+}
+
+/// Record a match for the metavariable `var` in the innermost repetition so far at `depth`.
+fn push_match(matches: &mut Rc<Vec<Match>>, var: usize, depth: usize, m: Match) {
+    let slot = &mut Rc::make_mut(matches)[var];
+    if depth == 0 {
+        *slot = m;
+        return;
+    }
+    let mut seq = slot;
+    for _ in 1..depth {
+        seq = match seq {
+            Match::Seq(seq) => seq.last_mut().unwrap(),
+            Match::Fragment(..) => unreachable!(),
+        };
+    }
+    match seq {
+        Match::Seq(seq) => seq.push(m),
+        Match::Fragment(..) => unreachable!(),
+    }
+}
+
+/// A position in a matcher, along with what its metavariables matched on the way there.
+#[derive(Clone)]
+struct MatcherPos {
+    idx: usize,
+    matches: Rc<Vec<Match>>,
+}
+
+enum FailureKind {
+    /// Nothing in the matcher could take the token, with the one thing that could have if there
+    /// is one.
+    NoMatch(Option<(SourceLoc, String)>),
+    /// The token starts a fragment that couldn't be parsed.
+    Fragment(Fragment),
+    /// Several metavariables could take the token, or the call could end in several ways.
+    Ambiguity(Vec<String>),
+}
+
+/// Why a call didn't match a rule.
+struct Failure {
+    /// The index of the token that couldn't be matched, or the number of tokens at the end.
+    pos: usize,
+    kind: FailureKind,
+    /// The opening delimiter of the rule's matcher.
+    rule: SourceLoc,
+}
+
+/// The tokens of an expansion so far, in which those that come from the definition rather than
+/// the input are marked with the expansion.
+struct Transcription<'a> {
+    contexts: &'a mut SyntaxContexts,
+    mark: Mark,
+    tokens: Vec<Token>,
+}
+
+impl Transcription<'_> {
+    fn push_def(&mut self, token: Token) {
+        let ctx = self.contexts.apply(token.ctx, self.mark);
+        self.tokens.push(Token { ctx, ..token });
+    }
+}
+
+/// A compiled `macro_rules!` definition, which expands a call using the first of its rules whose
+/// matcher matches it.
+pub struct MacroRules {
+    name: Name,
+    loc: SourceLoc,
+    /// The root of the crate that defines the macro, which `$crate` refers to.
+    root: Path,
+    edition: Edition,
+    rules: Vec<Rule>,
+}
+
+impl MacroRules {
+    /// Compile the token tree of a `macro_rules!` definition after its name, whose first token is
+    /// at `loc`, in the crate with the given `root`.
+    pub fn parse(
+        names: &Names,
+        name: Name,
+        loc: SourceLoc,
+        root: Path,
+        body: &[Token],
+        edition: Edition,
+    ) -> Result<Self, Diagnostic> {
+        let compiler = Compiler { names, edition };
+        let rules = match trees(body)[..] {
+            [Tree::Group(_, arms, _), ..] => compiler.rules(arms)?,
+            _ => return Err(Diagnostic::error(loc, "expected a delimited macro body")),
+        };
+        Ok(Self {
+            name,
+            loc,
+            root,
+            edition,
+            rules,
+        })
+    }
+
+    pub fn name(&self) -> Name {
+        self.name
+    }
+
+    /// Expand a call with the `input` tokens between its delimiters, the closing one of which is
+    /// at `close`, marking the tokens that come from the definition with `mark`.
+    pub fn expand(
+        &self,
+        names: &Names,
+        (contexts, mark): (&mut SyntaxContexts, Mark),
+        input: &[Token],
+        close: SourceLoc,
+    ) -> Result<Vec<Token>, Diagnostic> {
+        let mut best: Option<Failure> = None;
+        for rule in &self.rules {
+            match self.match_rule(names, rule, input) {
+                Ok(matches) => {
+                    let mut out = Transcription {
+                        contexts,
+                        mark,
+                        tokens: Vec::new(),
+                    };
+                    let mut idxs = Vec::new();
+                    let rule_input = (rule, matches.as_slice(), input);
+                    self.transcribe(names, rule_input, &rule.transcriber, &mut idxs, &mut out)?;
+                    return Ok(out.tokens);
+                }
+                Err(failure) => {
+                    if best.as_ref().is_none_or(|best| failure.pos > best.pos) {
+                        best = Some(failure);
+                    }
+                }
+            }
+        }
+        let failure = best.unwrap_or(Failure {
+            pos: 0,
+            kind: FailureKind::NoMatch(None),
+            rule: self.loc,
+        });
+        let token = input.get(failure.pos);
+        let (loc, found) = match token {
+            Some(token) => (token.loc, format!("`{}`", names.get(token.text))),
+            None => (close, "end of macro arguments".to_owned()),
+        };
+        let diagnostic = match failure.kind {
+            FailureKind::NoMatch(expected) => {
+                let diagnostic = match token {
+                    Some(_) => {
+                        Diagnostic::error(loc, format!("no rules expected the token {found}"))
+                            .label(loc, "no rules expected this token in macro call")
+                    }
+                    None => Diagnostic::error(loc, "unexpected end of macro invocation")
+                        .label(loc, "missing tokens in macro arguments"),
+                };
+                match expected {
+                    Some((loc, expected)) => {
+                        diagnostic.label(loc, format!("while trying to match {expected}"))
+                    }
+                    None => diagnostic,
+                }
+            }
+            FailureKind::Fragment(fragment) => {
+                let descr = fragment.descr();
+                Diagnostic::error(loc, format!("expected {descr}, found {found}"))
+                    .label(loc, format!("expected {descr}"))
+                    .label(failure.rule, "while trying to match this rule")
+            }
+            FailureKind::Ambiguity(options) => {
+                let name = names.get(self.name);
+                let message = format!(
+                    "local ambiguity when calling macro `{name}`: multiple parsing options: {}",
+                    options.join(" or "),
+                );
+                Diagnostic::error(loc, message)
+            }
+        };
+        Err(diagnostic.label(self.loc, "when calling this macro"))
+    }
+
+    /// Match a call's tokens against a rule, returning what each of its metavariables matched.
+    fn match_rule(
+        &self,
+        names: &Names,
+        rule: &Rule,
+        input: &[Token],
+    ) -> Result<Vec<Match>, Failure> {
+        let failure = |pos, kind| Failure {
+            pos,
+            kind,
+            rule: rule.loc,
+        };
+        let matches = Rc::new(vec![Match::Seq(Vec::new()); rule.vars.len()]);
+        let mut cur = vec![MatcherPos { idx: 0, matches }];
+        let mut pos = 0;
+        loop {
+            let token = input.get(pos);
+            let mut next = Vec::new();
+            let mut bb = Vec::new();
+            let mut eof = Vec::new();
+            let mut expected = Vec::new();
+            let same = |t: &Token| token.is_some_and(|token| same_token(t, token));
+            while let Some(mut mp) = cur.pop() {
+                match &rule.matcher[mp.idx] {
+                    MatcherLoc::Token(t) => {
+                        if same(t) {
+                            mp.idx += 1;
+                            next.push(mp);
+                        } else {
+                            expected.push((t.loc, format!("`{}`", names.get(t.text))));
+                        }
+                    }
+                    MatcherLoc::Sequence {
+                        op,
+                        vars,
+                        depth,
+                        end,
+                    } => {
+                        for var in vars.clone() {
+                            push_match(&mut mp.matches, var, *depth, Match::Seq(Vec::new()));
+                        }
+                        if *op != RepeatOp::OneOrMore {
+                            cur.push(MatcherPos {
+                                idx: *end,
+                                matches: Rc::clone(&mp.matches),
+                            });
+                        }
+                        mp.idx += 1;
+                        cur.push(mp);
+                    }
+                    MatcherLoc::SequenceOp { op, first } => {
+                        cur.push(MatcherPos {
+                            idx: mp.idx + 1,
+                            matches: Rc::clone(&mp.matches),
+                        });
+                        if *op != RepeatOp::ZeroOrOne {
+                            mp.idx = *first;
+                            cur.push(mp);
+                        }
+                    }
+                    MatcherLoc::SequenceSep { sep, end } => {
+                        cur.push(MatcherPos {
+                            idx: *end,
+                            matches: Rc::clone(&mp.matches),
+                        });
+                        if same(sep) {
+                            mp.idx += 1;
+                            next.push(mp);
+                        } else {
+                            expected.push((sep.loc, format!("`{}`", names.get(sep.text))));
+                        }
+                    }
+                    MatcherLoc::SequenceOpAfterSep { first } => {
+                        mp.idx = *first;
+                        cur.push(mp);
+                    }
+                    MatcherLoc::MetaVar { name, fragment, .. } => match token {
+                        Some(token) if fragment.may_begin_with(token.kind, self.edition) => {
+                            bb.push(mp)
+                        }
+                        _ => {
+                            let name = names.get(name.text);
+                            let descr = format!("{} `${name}`", fragment.descr());
+                            expected.push((name_loc(&rule.matcher[mp.idx]), descr));
+                        }
+                    },
+                    MatcherLoc::Eof => {
+                        if token.is_none() {
+                            eof.push(mp);
+                        }
+                    }
+                }
+            }
+            let expected = match <[_; 1]>::try_from(expected) {
+                Ok([expected]) => Some(expected),
+                Err(_) => None,
+            };
+            if token.is_none() {
+                return match eof.len() {
+                    0 => Err(failure(pos, FailureKind::NoMatch(expected))),
+                    1 => Ok(Rc::unwrap_or_clone(eof.pop().unwrap().matches)),
+                    _ => Err(failure(pos, FailureKind::Ambiguity(Vec::new()))),
+                };
+            }
+            match (next.is_empty(), bb.len()) {
+                (true, 0) => return Err(failure(pos, FailureKind::NoMatch(expected))),
+                (false, 0) => {
+                    cur = next;
+                    pos += 1;
+                }
+                (true, 1) => {
+                    let mut mp = bb.pop().unwrap();
+                    let MatcherLoc::MetaVar {
+                        fragment,
+                        var,
+                        depth,
+                        ..
+                    } = rule.matcher[mp.idx]
+                    else {
+                        unreachable!()
+                    };
+                    let Some(len) = fragment.parse_prefix(names, &input[pos..], self.edition)
+                    else {
+                        return Err(failure(pos, FailureKind::Fragment(fragment)));
+                    };
+                    let m = Match::Fragment(fragment, pos..pos + len);
+                    push_match(&mut mp.matches, var, depth, m);
+                    mp.idx += 1;
+                    cur = vec![mp];
+                    pos += len;
+                }
+                _ => {
+                    let mut options: Vec<String> = bb
+                        .iter()
+                        .map(|mp| match &rule.matcher[mp.idx] {
+                            MatcherLoc::MetaVar { name, fragment, .. } => {
+                                format!("{} `${}`", fragment.descr(), names.get(name.text))
+                            }
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                    if !next.is_empty() {
+                        options.push(format!("the token `{}`", names.get(input[pos].text)));
+                    }
+                    return Err(failure(pos, FailureKind::Ambiguity(options)));
+                }
+            }
+        }
+    }
+
+    /// Transcribe part of a rule for the current iteration `idxs` of each repetition it is in.
+    fn transcribe(
+        &self,
+        names: &Names,
+        (rule, matches, input): (&Rule, &[Match], &[Token]),
+        items: &[Transcribe],
+        idxs: &mut Vec<usize>,
+        out: &mut Transcription,
+    ) -> Result<(), Diagnostic> {
+        let lookup = |var: usize, idxs: &[usize]| {
+            let mut m = &matches[var];
+            for &i in idxs {
+                match m {
+                    Match::Seq(seq) => m = &seq[i],
+                    Match::Fragment(..) => break,
+                }
+            }
+            m
+        };
+        for item in items {
+            match item {
+                Transcribe::Token(token) => out.push_def(*token),
+                Transcribe::DollarCrate(dollar) => out.tokens.push(Token {
+                    kind: SyntaxKind::CRATE_KW,
+                    text: sym::CRATE,
+                    loc: dollar.loc,
+                    ctx: out.contexts.dollar_crate(self.root),
+                }),
+                Transcribe::Var(dollar, name) => match rule.vars.get(&name.text) {
+                    None => {
+                        out.push_def(*dollar);
+                        out.push_def(*name);
+                    }
+                    Some(&var) => match lookup(var, idxs) {
+                        Match::Fragment(fragment, range) => {
+                            let fragment_tokens = &input[range.clone()];
+                            let expr = matches!(fragment, Fragment::Expr | Fragment::Expr2021);
+                            // Keep the expression together wherever it ends up, like rustc does
+                            // by keeping it as a single node.
+                            if expr && tree_len(fragment_tokens) != Some(fragment_tokens.len()) {
+                                let (first, last) = (fragment_tokens[0], input[range.end - 1]);
+                                out.tokens.push(Token {
+                                    kind: SyntaxKind::L_PAREN,
+                                    text: sym::L_PAREN,
+                                    loc: first.loc,
+                                    ctx: first.ctx,
+                                });
+                                out.tokens.extend(fragment_tokens);
+                                out.tokens.push(Token {
+                                    kind: SyntaxKind::R_PAREN,
+                                    text: sym::R_PAREN,
+                                    loc: last.loc,
+                                    ctx: last.ctx,
+                                });
+                            } else {
+                                out.tokens.extend(fragment_tokens);
+                            }
+                        }
+                        Match::Seq(_) => {
+                            let name = names.get(name.text);
+                            let message =
+                                format!("variable `{name}` is still repeating at this depth");
+                            return Err(Diagnostic::error(dollar.loc, message));
+                        }
+                    },
+                },
+                Transcribe::Repeat {
+                    body,
+                    sep,
+                    op,
+                    dollar,
+                } => {
+                    let mut vars = Vec::new();
+                    collect_vars(body, &mut vars);
+                    let mut len: Option<(usize, Name)> = None;
+                    for name in vars {
+                        let Some(&var) = rule.vars.get(&name) else {
+                            continue;
+                        };
+                        let Match::Seq(seq) = lookup(var, idxs) else {
+                            continue;
+                        };
+                        match len {
+                            None => len = Some((seq.len(), name)),
+                            Some((n, other)) if n != seq.len() => {
+                                let message = format!(
+                                    "meta-variable `{}` repeats {n} times, but `{}` repeats {} times",
+                                    names.get(other),
+                                    names.get(name),
+                                    seq.len(),
+                                );
+                                return Err(Diagnostic::error(dollar.loc, message));
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                    let Some((len, _)) = len else {
+                        return Err(Diagnostic::error(
+                            dollar.loc,
+                            "attempted to repeat an expression containing no syntax variables \
+                             matched as repeating at this depth",
+                        ));
+                    };
+                    match op {
+                        RepeatOp::ZeroOrOne if len > 1 => {
+                            return Err(Diagnostic::error(
+                                dollar.loc,
+                                "this must repeat at most once",
+                            ))
+                        }
+                        RepeatOp::OneOrMore if len == 0 => {
+                            return Err(Diagnostic::error(
+                                dollar.loc,
+                                "this must repeat at least once",
+                            ))
+                        }
+                        _ => {}
+                    }
+                    for i in 0..len {
+                        if i > 0 {
+                            for &token in sep {
+                                out.push_def(token);
+                            }
+                        }
+                        idxs.push(i);
+                        self.transcribe(names, (rule, matches, input), body, idxs, out)?;
+                        idxs.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn same_token(a: &Token, b: &Token) -> bool {
+    a.kind == b.kind && a.text == b.text
+}
+
+fn name_loc(loc: &MatcherLoc) -> SourceLoc {
+    match loc {
+        MatcherLoc::MetaVar { name, .. } => name.loc,
+        _ => unreachable!(),
+    }
+}
+
+fn collect_vars(items: &[Transcribe], vars: &mut Vec<Name>) {
+    for item in items {
+        match item {
+            Transcribe::Token(_) | Transcribe::DollarCrate(_) => {}
+            Transcribe::Var(_, name) => vars.push(name.text),
+            Transcribe::Repeat { body, .. } => collect_vars(body, vars),
+        }
+    }
+}
+
+struct Compiler<'a> {
+    names: &'a Names,
+    edition: Edition,
+}
+
+impl Compiler<'_> {
+    /// Compile the rules between the delimiters of a definition's body.
+    fn rules(&self, tokens: &[Token]) -> Result<Vec<Rule>, Diagnostic> {
+        let mut rules = Vec::new();
+        let trees = trees(tokens);
+        let mut i = 0;
+        while i < trees.len() {
+            let (open, lhs) =
+                match trees[i] {
+                    Tree::Group(open, lhs, _) => (open, lhs),
+                    Tree::Leaf(token) => return Err(Diagnostic::error(
+                        token.loc,
+                        "invalid macro matcher; matchers must be contained in balanced delimiters",
+                    )),
+                };
+            match trees.get(i + 1..i + 3) {
+                Some([Tree::Leaf(eq), Tree::Leaf(gt)])
+                    if eq.kind == SyntaxKind::EQ
+                        && gt.kind == SyntaxKind::R_ANGLE
+                        && joint(self.names, *eq, *gt) => {}
+                _ => {
+                    return Err(Diagnostic::error(
+                        open.loc,
+                        "expected `=>` after macro matcher",
+                    ))
+                }
+            }
+            let rhs = match trees.get(i + 3) {
+                Some(Tree::Group(_, rhs, _)) => rhs,
+                Some(Tree::Leaf(token)) => {
+                    return Err(Diagnostic::error(token.loc, "macro rhs must be delimited"))
+                }
+                None => return Err(Diagnostic::error(open.loc, "expected macro rhs after `=>`")),
+            };
+            let mut matcher = Vec::new();
+            let mut vars = HashMap::new();
+            self.matcher(lhs, 0, &mut matcher, &mut vars)?;
+            matcher.push(MatcherLoc::Eof);
+            rules.push(Rule {
+                matcher,
+                vars,
+                transcriber: self.transcriber(rhs)?,
+                loc: open.loc,
+            });
+            i += 4;
+            match trees.get(i) {
+                Some(Tree::Leaf(semi)) if semi.kind == SyntaxKind::SEMICOLON => i += 1,
+                Some(Tree::Leaf(token)) | Some(Tree::Group(token, ..)) => {
+                    return Err(Diagnostic::error(
+                        token.loc,
+                        "expected `;` after macro rule",
+                    ))
+                }
+                None => {}
+            }
+        }
+        Ok(rules)
+    }
+
+    fn matcher(
+        &self,
+        tokens: &[Token],
+        depth: usize,
+        matcher: &mut Vec<MatcherLoc>,
+        vars: &mut HashMap<Name, usize>,
+    ) -> Result<(), Diagnostic> {
+        let trees = trees(tokens);
+        let mut i = 0;
+        while i < trees.len() {
+            match trees[i] {
+                Tree::Leaf(dollar) if dollar.kind == SyntaxKind::DOLLAR => match trees.get(i + 1) {
+                    Some(&Tree::Group(open, inner, _)) if open.kind == SyntaxKind::L_PAREN => {
+                        let (op, sep, len) = self.repetition(&trees[i + 2..], dollar)?;
+                        let start = matcher.len();
+                        matcher.push(MatcherLoc::Eof);
+                        let first_var = vars.len();
+                        self.matcher(inner, depth + 1, matcher, vars)?;
+                        let first = start + 1;
+                        let sep_start = matcher.len();
+                        if sep.is_empty() && matches_empty(matcher, first) {
+                            return Err(Diagnostic::error(
+                                dollar.loc,
+                                "repetition matches empty token tree",
+                            ));
+                        }
+                        match sep.split_first() {
+                            None => matcher.push(MatcherLoc::SequenceOp { op, first }),
+                            Some((&sep, rest)) => {
+                                matcher.push(MatcherLoc::SequenceSep { sep, end: 0 });
+                                matcher.extend(rest.iter().map(|&token| MatcherLoc::Token(token)));
+                                matcher.push(MatcherLoc::SequenceOpAfterSep { first });
+                            }
+                        }
+                        let end = matcher.len();
+                        if let MatcherLoc::SequenceSep { end: sep_end, .. } =
+                            &mut matcher[sep_start]
+                        {
+                            *sep_end = end;
+                        }
+                        matcher[start] = MatcherLoc::Sequence {
+                            op,
+                            vars: first_var..vars.len(),
+                            depth,
+                            end,
+                        };
+                        i += 2 + len;
+                    }
+                    Some(&Tree::Leaf(name)) if self.is_var(name) => {
+                        let fragment = match trees.get(i + 2..i + 4) {
+                            Some([Tree::Leaf(colon), Tree::Leaf(fragment)])
+                                if colon.kind == SyntaxKind::COLON
+                                    && is_ident(fragment.kind, self.edition) =>
+                            {
+                                *fragment
+                            }
+                            _ => {
+                                return Err(Diagnostic::error(
+                                    name.loc,
+                                    "missing fragment specifier",
+                                ))
+                            }
+                        };
+                        let text = self.names.get(fragment.text);
+                        let Some(fragment) = Fragment::parse(text, self.edition) else {
+                            return Err(Diagnostic::error(
+                                fragment.loc,
+                                format!("invalid fragment specifier `{text}`"),
+                            )
+                            .help(
+                                "valid fragment specifiers are `ident`, `block`, `stmt`, `expr`, \
+                                 `pat`, `ty`, `lifetime`, `literal`, `path`, `meta`, `tt`, \
+                                 `item` and `vis`",
+                            ));
+                        };
+                        let var = vars.len();
+                        if vars.insert(name.text, var).is_some() {
+                            return Err(Diagnostic::error(name.loc, "duplicate matcher binding"));
+                        }
+                        matcher.push(MatcherLoc::MetaVar {
+                            name,
+                            fragment,
+                            var,
+                            depth,
+                        });
+                        i += 4;
+                    }
+                    _ => {
+                        matcher.push(MatcherLoc::Token(dollar));
+                        i += 1;
+                    }
+                },
+                Tree::Leaf(token) => {
+                    matcher.push(MatcherLoc::Token(token));
+                    i += 1;
+                }
+                Tree::Group(open, inner, close) => {
+                    matcher.push(MatcherLoc::Token(open));
+                    self.matcher(inner, depth, matcher, vars)?;
+                    matcher.push(MatcherLoc::Token(close));
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn transcriber(&self, tokens: &[Token]) -> Result<Vec<Transcribe>, Diagnostic> {
+        let trees = trees(tokens);
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < trees.len() {
+            match trees[i] {
+                Tree::Leaf(dollar) if dollar.kind == SyntaxKind::DOLLAR => match trees.get(i + 1) {
+                    Some(&Tree::Group(open, inner, _)) if open.kind == SyntaxKind::L_PAREN => {
+                        let (op, sep, len) = self.repetition(&trees[i + 2..], dollar)?;
+                        let body = self.transcriber(inner)?;
+                        items.push(Transcribe::Repeat {
+                            body,
+                            sep,
+                            op,
+                            dollar,
+                        });
+                        i += 2 + len;
+                    }
+                    Some(&Tree::Leaf(name)) if self.is_var(name) => {
+                        items.push(Transcribe::Var(dollar, name));
+                        i += 2;
+                    }
+                    Some(&Tree::Leaf(name)) if name.kind == SyntaxKind::CRATE_KW => {
+                        items.push(Transcribe::DollarCrate(dollar));
+                        i += 2;
+                    }
+                    _ => {
+                        items.push(Transcribe::Token(dollar));
+                        i += 1;
+                    }
+                },
+                Tree::Leaf(token) => {
+                    items.push(Transcribe::Token(token));
+                    i += 1;
+                }
+                Tree::Group(open, inner, close) => {
+                    items.push(Transcribe::Token(open));
+                    items.extend(self.transcriber(inner)?);
+                    items.push(Transcribe::Token(close));
+                    i += 1;
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// Whether a token after a `$` names a metavariable, unlike in `$crate`.
+    fn is_var(&self, token: Token) -> bool {
+        is_ident(token.kind, self.edition) && token.kind != SyntaxKind::CRATE_KW
+    }
+
+    /// Parse the separator and operator after the parenthesized part of a repetition, returning
+    /// them along with how many trees they take up.
+    fn repetition(
+        &self,
+        trees: &[Tree],
+        dollar: Token,
+    ) -> Result<(RepeatOp, Vec<Token>, usize), Diagnostic> {
+        let op = |tree: &Tree| match tree {
+            Tree::Leaf(token) => match token.kind {
+                SyntaxKind::STAR => Some(RepeatOp::ZeroOrMore),
+                SyntaxKind::PLUS => Some(RepeatOp::OneOrMore),
+                SyntaxKind::QUESTION => Some(RepeatOp::ZeroOrOne),
+                _ => None,
+            },
+            Tree::Group(..) => None,
+        };
+        if let Some(op) = trees.first().and_then(op) {
+            // Since the 2018 edition, `?` is an operator, so it can't separate a `*` or `+`.
+            if let (RepeatOp::ZeroOrOne, [Tree::Leaf(question), Tree::Leaf(next), ..]) = (op, trees)
+            {
+                let repeat = matches!(next.kind, SyntaxKind::STAR | SyntaxKind::PLUS);
+                if repeat && joint(self.names, *question, *next) {
+                    return Err(Diagnostic::error(
+                        dollar.loc,
+                        "`?` cannot be used as a separator of a macro repetition",
+                    ));
+                }
+            }
+            return Ok((op, Vec::new(), 1));
+        }
+        // The separator is a single token, which may be punctuation of several characters.
+        let mut sep: Vec<Token> = Vec::new();
+        for (i, tree) in trees.iter().enumerate() {
+            let &Tree::Leaf(token) = tree else {
+                break;
+            };
+            if let (Some(op), false) = (op(tree), sep.is_empty()) {
+                if op == RepeatOp::ZeroOrOne {
+                    return Err(Diagnostic::error(
+                        token.loc,
+                        "the `?` macro repetition operator does not take a separator",
+                    ));
+                }
+                return Ok((op, sep, i + 1));
+            }
+            if let Some(&prev) = sep.last() {
+                let punct = prev.kind.is_punct() && token.kind.is_punct();
+                if !punct || !joint(self.names, prev, token) {
+                    break;
+                }
+            }
+            sep.push(token);
+        }
+        Err(Diagnostic::error(
+            dollar.loc,
+            "expected one of: `*`, `+`, or `?`",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ra_ap_parser::Edition;
+    use ra_ap_rustc_index::Idx;
+
+    use crate::{
+        attr::Token,
+        diagnostic::Diagnostic,
+        hygiene::{Mark, SyntaxContexts, Transparency},
+        mbe::MacroRules,
+        name::Names,
+        path::Paths,
+        source::Source,
+        syntax::Node,
+    };
+
+    /// Expand a call to a definition, returning the expansion or a rendered diagnostic.
+    fn expand_in(edition: Edition, def: &str, call: &str) -> Result<String, String> {
+        let mut src = Source::new();
+        let mut names = Names::new();
+        let file = src
+            .read("lib.rs", format!("{def}\n{call}").as_bytes())
+            .unwrap();
+        let base = src.range(file).start;
+        let name = names.make("m");
+        let body = Token::lex(&mut names, edition, base, def);
+        let render = |diagnostic: Diagnostic| diagnostic.render(&src);
+        let root = Paths::new().root();
+        let rules = MacroRules::parse(&names, name, base, root, &body, edition).map_err(render)?;
+        let input = Token::lex(&mut names, edition, base.plus(def.len() + 1), call);
+        let close = src.range(file).end;
+        let mut contexts = SyntaxContexts::new();
+        let mark = Mark {
+            call: Node::new(1),
+            def: Node::new(0),
+            transparency: Transparency::SemiTransparent,
+        };
+        let output = rules
+            .expand(&names, (&mut contexts, mark), &input, close)
+            .map_err(render)?;
+        let texts: Vec<&str> = output.iter().map(|token| names.get(token.text)).collect();
+        Ok(texts.join(" "))
+    }
+
+    /// The message of the error from expanding a call.
+    fn error(def: &str, call: &str) -> String {
+        let error = expand(def, call).unwrap_err();
+        error.lines().next().unwrap().to_owned()
+    }
+
+    fn expand(def: &str, call: &str) -> Result<String, String> {
+        expand_in(Edition::Edition2021, def, call)
+    }
+
+    #[test]
+    fn test_fragments() {
+        let def = "{ ($a:expr, $b:ident) => { let $b = $a * 2; }; }";
+        assert_eq!(expand(def, "1 + 2, x").unwrap(), "let x = ( 1 + 2 ) * 2 ;");
+        assert_eq!(expand(def, "(1), x").unwrap(), "let x = ( 1 ) * 2 ;");
+        let def = "{
+            ($v:vis fn $f:ident<$l:lifetime>($p:pat) -> $t:ty $b:block) => { $v $t $p $l $b };
+        }";
+        assert_eq!(
+            expand(def, "fn f<'a>(Some(x) | None) -> Vec<&'a u8> { x }").unwrap(),
+            "Vec < & 'a u8 > Some ( x ) | None 'a { x }"
+        );
+        let def = "{ ($l:literal $p:path; $m:meta; $i:item $s:stmt) => { $s; $i $m $p $l } }";
+        assert_eq!(
+            expand(def, "-1 a::b<c>; cfg(x); struct S; let y = 2").unwrap(),
+            "let y = 2 ; struct S ; cfg ( x ) a : : b < c > - 1"
+        );
+        let def = "{ ($($t:tt)*) => { $($t)* } }";
+        assert_eq!(expand(def, "a (b [c]) {}").unwrap(), "a ( b [ c ] ) { }");
+    }
+
+    #[test]
+    fn test_editions() {
+        let def = "{ ($e:expr) => { e }; (_) => { underscore }; }";
+        assert_eq!(expand(def, "_").unwrap(), "underscore");
+        assert_eq!(expand_in(Edition::Edition2024, def, "_").unwrap(), "e");
+        let def = "{ ($p:pat) => { p }; ($p:pat | $q:pat) => { or }; }";
+        assert_eq!(expand(def, "a | b").unwrap(), "p");
+        assert_eq!(expand_in(Edition::Edition2018, def, "a | b").unwrap(), "or");
+    }
+
+    #[test]
+    fn test_repetitions() {
+        let def = "{ ($($k:ident => $v:expr),* $(,)?) => { [$(($k, $v)),*] } }";
+        assert_eq!(
+            expand(def, "a => 1, b => 2,").unwrap(),
+            "[ ( a , 1 ) , ( b , 2 ) ]"
+        );
+        assert_eq!(expand(def, "").unwrap(), "[ ]");
+        let def = "{ ($($f:ident($($t:ty),*));+) => { $(fn $f($(_: $t),*) {})+ } }";
+        assert_eq!(
+            expand(def, "f(u8, i32); g()").unwrap(),
+            "fn f ( _ : u8 , _ : i32 ) { } fn g ( ) { }"
+        );
+        let def = "{ ($($a:ident)&&+) => { $($a)||+ } }";
+        assert_eq!(expand(def, "a && b && c").unwrap(), "a | | b | | c");
+        let def = "{ ($x:ident $($y:ident)*) => { $(($x $y))* } }";
+        assert_eq!(expand(def, "a b c").unwrap(), "( a b ) ( a c )");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            expand("{ (a b) => {}; (a) => {}; }", "a c").unwrap_err(),
+            "error: no rules expected the token `c`
+  --> lib.rs:2:3
+  --> lib.rs:2:3: no rules expected this token in macro call
+  --> lib.rs:1:6: while trying to match `b`
+  --> lib.rs:1:1: when calling this macro
+"
+        );
+        assert_eq!(
+            error("{ (a b) => {} }", "a"),
+            "error: unexpected end of macro invocation"
+        );
+        assert_eq!(
+            error("{ ($x:expr) => {} }", "struct"),
+            "error: no rules expected the token `struct`"
+        );
+        assert_eq!(
+            error("{ ($x:ty) => {} }", "&"),
+            "error: expected a type, found `&`"
+        );
+        assert_eq!(
+            error("{ ($($i:ident)* $j:ident) => {} }", "a"),
+            "error: local ambiguity when calling macro `m`: multiple parsing options: \
+             an identifier `$i` or an identifier `$j`"
+        );
+        assert_eq!(
+            error(
+                "{ ($($a:ident)* ; $($b:ident)*) => { $(($a $b))* } }",
+                "x ; y z"
+            ),
+            "error: meta-variable `a` repeats 1 times, but `b` repeats 2 times"
+        );
+        assert_eq!(
+            error("{ ($($a:ident)*) => { $a } }", "x"),
+            "error: variable `a` is still repeating at this depth"
+        );
+        assert_eq!(
+            error("{ ($a:ident) => { $($a)* } }", "x"),
+            "error: attempted to repeat an expression containing no syntax variables matched \
+             as repeating at this depth"
+        );
+        assert_eq!(
+            error("{ ($a) => {} }", ""),
+            "error: missing fragment specifier"
+        );
+        assert_eq!(
+            error("{ ($a:foo) => {} }", ""),
+            "error: invalid fragment specifier `foo`"
+        );
+        assert_eq!(
+            error("{ ($($a:ident),?) => {} }", ""),
+            "error: the `?` macro repetition operator does not take a separator"
+        );
+        assert_eq!(
+            error("{ ($(a)?*) => {} }", ""),
+            "error: `?` cannot be used as a separator of a macro repetition"
+        );
+        assert_eq!(
+            error("{ ($($($x:ident)*)*) => {} }", ""),
+            "error: repetition matches empty token tree"
+        );
+        assert_eq!(
+            error("{ ($($v:vis $(a)?)+) => {} }", ""),
+            "error: repetition matches empty token tree"
+        );
+        assert_eq!(
+            error("{ ($()*) => {} }", ""),
+            "error: repetition matches empty token tree"
+        );
+        let def = "{ ($($($x:ident)+);*) => { $($($x)+)* } }";
+        assert_eq!(expand(def, "a b; c").unwrap(), "a b c");
+    }
+}
@@ -28,6 +28,53 @@ impl Decodable for Name {
     }
 }
 
+macro_rules! symbols {
+    ($($name:ident: $text:literal,)*) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[repr(u32)]
+        enum Symbol {
+            $($name,)*
+        }
+
+        /// Names interned up front by [`Names::new`], so they can be compared without a lookup.
+        pub mod sym {
+            $(pub const $name: super::Name = super::Name(super::Symbol::$name as u32);)*
+        }
+
+        const SYMBOLS: &[&str] = &[$($text,)*];
+    };
+}
+
+symbols! {
+    ALLOC: "alloc",
+    CFG: "cfg",
+    CFG_ATTR: "cfg_attr",
+    CORE: "core",
+    CRATE: "crate",
+    DEFAULT: "default",
+    DERIVE: "derive",
+    L_PAREN: "(",
+    MACRO_EXPORT: "macro_export",
+    MACRO_USE: "macro_use",
+    NO_CORE: "no_core",
+    NO_STD: "no_std",
+    PATH: "path",
+    PRELUDE: "prelude",
+    PROC_MACRO: "proc_macro",
+    PROC_MACRO_ATTRIBUTE: "proc_macro_attribute",
+    PROC_MACRO_DERIVE: "proc_macro_derive",
+    R_PAREN: ")",
+    RUSTC_BUILTIN_MACRO: "rustc_builtin_macro",
+    RUST_2015: "rust_2015",
+    RUST_2018: "rust_2018",
+    RUST_2021: "rust_2021",
+    RUST_2024: "rust_2024",
+    STATIC_LIFETIME: "'static",
+    STD: "std",
+    TEST: "test",
+    UNDERSCORE_LIFETIME: "'_",
+}
+
 #[derive(Clone, Copy)]
 struct NameLoc(u32);
 
@@ -38,10 +85,14 @@ pub struct Names {
 
 impl Names {
     pub fn new() -> Self {
-        Self {
+        let mut names = Self {
             data: String::new(),
             names: IndexMap::new(),
+        };
+        for &symbol in SYMBOLS {
+            names.make(symbol);
         }
+        names
     }
 
     pub fn get(&self, id: Name) -> &str {
@@ -70,7 +121,7 @@ impl Names {
 
 #[cfg(test)]
 mod tests {
-    use crate::name::Names;
+    use crate::name::{sym, Names, SYMBOLS};
 
     #[test]
     fn test_same() {
@@ -80,6 +131,16 @@ mod tests {
         assert_eq!(a, b);
     }
 
+    #[test]
+    fn test_symbols() {
+        let mut names = Names::new();
+        for &symbol in SYMBOLS {
+            let name = names.make(symbol);
+            assert_eq!(names.get(name), symbol);
+        }
+        assert_eq!(names.make("path"), sym::PATH);
+    }
+
     #[test]
     fn test_different() {
         let mut names = Names::new();
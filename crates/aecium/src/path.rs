@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use derive_rustc_index::Idx;
 use ra_ap_rustc_index::{Idx, IndexVec};
 
-use crate::name::Name;
+use crate::name::{Name, Names};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
 pub struct Path(u32);
@@ -20,17 +20,27 @@ pub struct Paths {
 
 impl Paths {
     pub fn new() -> Self {
-        let mut data = IndexVec::new();
-        data.push(PathData {
+        Self {
+            data: IndexVec::new(),
+            children: HashMap::new(),
+        }
+    }
+
+    /// Create the root path of a new crate.
+    pub fn root(&mut self) -> Path {
+        let path = self.data.next_index();
+        self.data.push(PathData {
             stem: Name::new(0), // dummy value, shouldn't be used
-            parent: Path(0),
-        });
-        let children = HashMap::new();
-        Self { data, children }
+            parent: path,
+        })
     }
 
-    pub fn root(&self) -> Path {
-        Path(0)
+    /// The root path of the crate containing the given path.
+    pub fn root_of(&self, mut path: Path) -> Path {
+        while let Some(parent) = self.parent(path) {
+            path = parent;
+        }
+        path
     }
 
     pub fn stem(&self, path: Path) -> Option<Name> {
@@ -51,6 +61,42 @@ impl Paths {
         }
     }
 
+    /// Whether `ancestor` is `path` itself or one of its ancestors.
+    pub fn is_ancestor(&self, ancestor: Path, mut path: Path) -> bool {
+        loop {
+            if path == ancestor {
+                return true;
+            }
+            match self.parent(path) {
+                Some(parent) => path = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Format a path like `crate::foo::bar`.
+    pub fn display(&self, names: &Names, path: Path) -> String {
+        self.display_from(names, "crate", path)
+    }
+
+    /// Format a path like `display`, but starting from the given name for the crate root, as
+    /// `module_path!` does with the crate's name.
+    pub fn display_from(&self, names: &Names, root: &str, mut path: Path) -> String {
+        let mut components = Vec::new();
+        while let Some(stem) = self.stem(path) {
+            components.push(names.get(stem));
+            path = self.parent(path).unwrap();
+        }
+        components.push(root);
+        components.reverse();
+        components.join("::")
+    }
+
+    /// The child with the given stem, if it has been created.
+    pub fn get(&self, parent: Path, stem: Name) -> Option<Path> {
+        self.children.get(&(parent, stem)).copied()
+    }
+
     pub fn child(&mut self, parent: Path, stem: Name) -> Path {
         *self
             .children
@@ -0,0 +1,172 @@
+use ra_ap_parser::Edition;
+
+use crate::{
+    item::Namespace,
+    name::{sym, Name},
+};
+
+/// Names in the prelude of every edition, in the type namespace.
+const TYPES: &[&str] = &[
+    "AsMut",
+    "AsRef",
+    "Clone",
+    "Copy",
+    "Default",
+    "DoubleEndedIterator",
+    "Drop",
+    "Eq",
+    "Err",
+    "ExactSizeIterator",
+    "Extend",
+    "Fn",
+    "FnMut",
+    "FnOnce",
+    "From",
+    "Into",
+    "IntoIterator",
+    "Iterator",
+    "None",
+    "Ok",
+    "Option",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+    "Result",
+    "Send",
+    "Sized",
+    "Some",
+    "Sync",
+    "Unpin",
+];
+
+/// Names in the prelude of every edition, in the value namespace.
+const VALUES: &[&str] = &[
+    "Err",
+    "None",
+    "Ok",
+    "Some",
+    "align_of",
+    "align_of_val",
+    "drop",
+    "size_of",
+    "size_of_val",
+];
+
+/// Names in the prelude of every edition, in the macro namespace, including derives.
+const MACROS: &[&str] = &[
+    "Clone",
+    "Copy",
+    "Debug",
+    "Default",
+    "Eq",
+    "Hash",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+    "assert",
+    "bench",
+    "cfg",
+    "cfg_accessible",
+    "cfg_eval",
+    "column",
+    "compile_error",
+    "concat",
+    "derive",
+    "env",
+    "file",
+    "format_args",
+    "global_allocator",
+    "include",
+    "include_bytes",
+    "include_str",
+    "line",
+    "module_path",
+    "option_env",
+    "stringify",
+    "test",
+    "test_case",
+];
+
+/// Macros exported from the root of both `core` and `std`, which every crate can use through the
+/// implicit `#[macro_use]` on the standard library crate it loads.
+const EXPORTED_MACROS: &[&str] = &[
+    "assert_eq",
+    "assert_ne",
+    "debug_assert",
+    "debug_assert_eq",
+    "debug_assert_ne",
+    "matches",
+    "panic",
+    "todo",
+    "r#try",
+    "unimplemented",
+    "unreachable",
+    "write",
+    "writeln",
+];
+
+/// Macros exported from the root of `std` but not `core`.
+const STD_EXPORTED_MACROS: &[&str] = &[
+    "dbg",
+    "eprint",
+    "eprintln",
+    "format",
+    "is_x86_feature_detected",
+    "print",
+    "println",
+    "thread_local",
+    "vec",
+];
+
+/// Types in the prelude of `std` but not `core`.
+const STD_TYPES: &[&str] = &["Box", "String", "ToOwned", "ToString", "Vec"];
+
+/// Types added to the prelude in the 2021 edition.
+const TYPES_2021: &[&str] = &["FromIterator", "TryFrom", "TryInto"];
+
+/// Types added to the prelude in the 2024 edition.
+const TYPES_2024: &[&str] = &["Future", "IntoFuture"];
+
+/// Builtin types, which are in scope everywhere unless shadowed, even without a prelude.
+const PRIMITIVES: &[&str] = &[
+    "bool", "char", "f16", "f32", "f64", "f128", "i8", "i16", "i32", "i64", "i128", "isize", "str",
+    "u8", "u16", "u32", "u64", "u128", "usize",
+];
+
+/// The name of the prelude module for an edition, like the `rust_2021` in
+/// `std::prelude::rust_2021`.
+pub fn module(edition: Edition) -> Name {
+    match edition {
+        Edition::Edition2015 => sym::RUST_2015,
+        Edition::Edition2018 => sym::RUST_2018,
+        Edition::Edition2021 => sym::RUST_2021,
+        Edition::Edition2024 => sym::RUST_2024,
+    }
+}
+
+/// Whether the prelude of `std`, or of `core` if `core_only`, has a name in the given namespace.
+///
+/// This is only used when the sources of the standard library aren't loaded.
+pub fn contains(edition: Edition, core_only: bool, ns: Namespace, name: &str) -> bool {
+    match ns {
+        Namespace::Type => {
+            TYPES.contains(&name)
+                || !core_only && STD_TYPES.contains(&name)
+                || edition >= Edition::Edition2021 && TYPES_2021.contains(&name)
+                || edition >= Edition::Edition2024 && TYPES_2024.contains(&name)
+        }
+        Namespace::Value => VALUES.contains(&name),
+        Namespace::Macro => MACROS.contains(&name),
+    }
+}
+
+/// Whether the root of `std`, or of `core` if `core_only`, exports a macro with the given name.
+///
+/// This is only used when the sources of the standard library aren't loaded.
+pub fn exports_macro(core_only: bool, name: &str) -> bool {
+    EXPORTED_MACROS.contains(&name) || !core_only && STD_EXPORTED_MACROS.contains(&name)
+}
+
+pub fn is_primitive(name: &str) -> bool {
+    PRIMITIVES.contains(&name)
+}
@@ -1,38 +1,97 @@
+use std::iter;
+
 use derive_rustc_index::Idx;
 use ra_ap_rustc_index::IndexVec;
 
-use crate::{path::Path, syntax::Node};
+use crate::{name::Name, path::Path, source::SourceLoc};
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
 pub struct Scope(u32);
 
+/// What introduces a scope.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScopeKind {
+    /// A source file or the body of an inline module, outside of which no names are in scope.
+    Module,
+    Block,
+    /// The block that is the body of a function.
+    FnBody,
+    Impl,
+    Trait,
+    /// The generic parameter list of an item or a `for<...>` binder, which is in scope for the
+    /// rest of it.
+    Generics,
+    Closure,
+    /// A label on a loop or block, which is in scope for the rest of it.
+    Label(Name),
+}
+
 struct ScopeData {
+    kind: ScopeKind,
     module: Path,
     parent: Scope,
-    node: Node,
+    /// Where the scope starts, which is no later than its first token.
+    start: SourceLoc,
+    /// Where the scope ends, just after its last token.
+    end: SourceLoc,
+    children: Vec<Scope>,
 }
 
+/// The tree of all scopes, in which each module is nested in the scope declaring it, if it is
+/// inline.
+///
+/// Scopes in macro expansions are pushed after the rest of their file, and are empty, at the
+/// outermost macro call they came from.
 pub struct Scopes {
     data: IndexVec<Scope, ScopeData>,
+    /// The scopes of the files read, which are each located after the ones read before them.
+    roots: Vec<Scope>,
 }
 
 impl Scopes {
     pub fn new() -> Self {
         Self {
             data: IndexVec::new(),
+            roots: Vec::new(),
         }
     }
 
-    pub fn push(&mut self, module: Path, parent: Option<Scope>, node: Node) -> Scope {
+    /// Add a scope that starts at `start`, whose end is set later by [`Self::set_end`].
+    pub fn push(
+        &mut self,
+        kind: ScopeKind,
+        module: Path,
+        parent: Option<Scope>,
+        start: SourceLoc,
+    ) -> Scope {
         let scope = self.data.next_index();
         self.data.push(ScopeData {
+            kind,
             module,
             parent: parent.unwrap_or(scope),
-            node,
+            start,
+            end: start,
+            children: Vec::new(),
         });
+        match parent {
+            Some(parent) => {
+                let children = &self.data[parent].children;
+                let i = children.partition_point(|&child| self.data[child].start <= start);
+                self.data[parent].children.insert(i, scope);
+            }
+            None => self.roots.push(scope),
+        }
         scope
     }
 
+    pub fn set_end(&mut self, scope: Scope, end: SourceLoc) {
+        self.data[scope].end = end;
+    }
+
+    pub fn kind(&self, scope: Scope) -> ScopeKind {
+        self.data[scope].kind
+    }
+
     pub fn module(&self, scope: Scope) -> Path {
         self.data[scope].module
     }
@@ -46,7 +105,33 @@ impl Scopes {
         }
     }
 
-    pub fn node(&self, scope: Scope) -> Node {
-        self.data[scope].node
+    /// The given scope followed by the scopes enclosing it, innermost first.
+    pub fn ancestors(&self, scope: Scope) -> impl Iterator<Item = Scope> + '_ {
+        iter::successors(Some(scope), |&scope| self.parent(scope))
+    }
+
+    /// The scopes directly nested in the given one, in the order they start.
+    #[cfg(test)]
+    pub fn children(&self, scope: Scope) -> impl Iterator<Item = Scope> + '_ {
+        self.data[scope].children.iter().copied()
+    }
+
+    /// The innermost scope containing a location, if any.
+    #[cfg(test)]
+    pub fn at(&self, loc: SourceLoc) -> Option<Scope> {
+        let mut scope = self.containing(&self.roots, loc)?;
+        while let Some(child) = self.containing(&self.data[scope].children, loc) {
+            scope = child;
+        }
+        Some(scope)
+    }
+
+    /// The one of the given scopes, which are sorted by where they start and don't overlap, that
+    /// contains a location, if any.
+    #[cfg(test)]
+    fn containing(&self, scopes: &[Scope], loc: SourceLoc) -> Option<Scope> {
+        let i = scopes.partition_point(|&scope| self.data[scope].start <= loc);
+        let &scope = scopes[..i].last()?;
+        (loc < self.data[scope].end).then_some(scope)
     }
 }
@@ -11,7 +11,7 @@ pub struct SourceFile(u16);
 #[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
 struct FilenameLoc(u32);
 
-#[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Idx, Ord, PartialEq, PartialOrd)]
 pub struct SourceLoc(u32);
 
 impl Encodable for SourceLoc {
@@ -34,6 +34,8 @@ pub struct Source {
     filenames: String,
     code: String,
     files: IndexVec<SourceFile, (FilenameLoc, SourceLoc)>,
+    /// The start of every line of every file, in order.
+    lines: Vec<SourceLoc>,
 }
 
 impl Source {
@@ -42,6 +44,7 @@ impl Source {
             filenames: String::new(),
             code: String::new(),
             files: IndexVec::new(),
+            lines: Vec::new(),
         }
     }
 
@@ -50,9 +53,21 @@ impl Source {
         self.filenames.push_str(name);
         let source_loc = SourceLoc::new(self.code.len());
         code.read_to_string(&mut self.code)?;
+        // The previous file may have ended with a newline, which already started this line.
+        if self.lines.last() != Some(&source_loc) {
+            self.lines.push(source_loc);
+        }
+        let newlines = self.code[source_loc.index()..].match_indices('\n');
+        let starts = newlines.map(|(i, _)| source_loc.plus(i + 1));
+        self.lines.extend(starts);
         Ok(self.files.push((filename_loc, source_loc)))
     }
 
+    #[cfg(test)]
+    pub fn files(&self) -> impl Iterator<Item = SourceFile> {
+        self.files.indices()
+    }
+
     pub fn name(&self, file: SourceFile) -> &str {
         let i = self.files[file].0.index();
         match self.files.get(file.plus(1)) {
@@ -72,4 +87,26 @@ impl Source {
     pub fn code(&self, range: Range<SourceLoc>) -> &str {
         &self.code[range.start.index()..range.end.index()]
     }
+
+    /// The file containing the given location.
+    pub fn file(&self, loc: SourceLoc) -> SourceFile {
+        let i = self.files.raw.partition_point(|&(_, start)| start <= loc);
+        SourceFile::new(i - 1)
+    }
+
+    /// One-based line and column numbers of the given location within its file, counting columns
+    /// in characters.
+    pub fn line_col(&self, loc: SourceLoc) -> (usize, usize) {
+        let start = self.range(self.file(loc)).start;
+        let first = self.lines.partition_point(|&line| line < start);
+        let i = self.lines.partition_point(|&line| line <= loc) - 1;
+        let col = self.code(self.lines[i]..loc).chars().count() + 1;
+        (i - first + 1, col)
+    }
+
+    /// Format a location as `file:line:col`.
+    pub fn describe(&self, loc: SourceLoc) -> String {
+        let (line, col) = self.line_col(loc);
+        format!("{}:{line}:{col}", self.name(self.file(loc)))
+    }
 }
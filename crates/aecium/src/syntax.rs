@@ -1,21 +1,51 @@
-use std::{collections::HashMap, fs, io, iter::Peekable, mem, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    iter::Peekable,
+    mem,
+    path::{self, PathBuf},
+};
 
 use derive_rustc_index::Idx;
 use ra_ap_parser::{Edition, LexedStr, Step, SyntaxKind, TopEntryPoint};
 use ra_ap_rustc_index::Idx;
 
 use crate::{
+    attr::{split_args, Attr, Token},
+    builtin::{BuiltinCx, BuiltinExpansion, BuiltinMacro},
+    cfg::{CfgExpr, CfgFlag, CfgSet},
+    diagnostic::Diagnostic,
     encoding::{Decodable, Decoder, Encodable},
-    name::{Name, Names},
+    hygiene::{Mark, SyntaxContext, SyntaxContexts, Transparency},
+    import::{self, Import, ImportKind, Imports, Res, Resolver, Segment},
+    item::{Field, Item, ItemKind, Items, Namespace, Parent},
+    krate::{Crate, Crates, Extern},
+    local::{LocalId, LocalKind, LocalUse, Locals, UseKind},
+    macros::{MacroCall, MacroKind, MacroRulesId, Macros},
+    mbe::MacroRules,
+    name::{sym, Name, Names},
     path::{Path, Paths},
-    scope::{Scope, Scopes},
+    scope::{Scope, ScopeKind, Scopes},
     source::{Source, SourceFile, SourceLoc},
+    sysroot::{self, Sysroot},
+    vis::Visibility,
 };
 
 fn is_token(kind: SyntaxKind) -> bool {
     let ws = u16::from(SyntaxKind::WHITESPACE);
     assert_eq!(ws + 1, u16::from(SyntaxKind::ABI));
-    u16::from(kind) <= ws
+    // Error nodes are numbered among the tokens.
+    u16::from(kind) <= ws && kind != SyntaxKind::ERROR
+}
+
+/// How many macro expansions deep a macro call can be before it isn't expanded, which is the
+/// default `recursion_limit` in rustc.
+const RECURSION_LIMIT: usize = 128;
+
+/// Whether a node kind is followed by a pointer to another node, which is filled in once it is
+/// known.
+fn has_pointer(kind: SyntaxKind) -> bool {
+    matches!(kind, SyntaxKind::MODULE | SyntaxKind::MACRO_CALL)
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Idx, PartialEq)]
@@ -37,7 +67,7 @@ impl Decodable for Node {
     }
 }
 
-struct Nodes {
+pub struct Nodes {
     data: Vec<u8>,
 }
 
@@ -59,95 +89,465 @@ impl Nodes {
     fn write(&mut self, node: Node, x: impl Encodable) {
         x.write(&mut self.data[node.index()..]);
     }
+
+    /// Discard the given node and everything after it.
+    fn truncate(&mut self, node: Node) {
+        self.data.truncate(node.index());
+    }
+
+    /// The location of the first token at or after the start of the given node.
+    pub fn loc(&self, node: Node) -> SourceLoc {
+        let mut decoder = Decoder::new(&self.data[node.index()..]);
+        loop {
+            let tag = u16::decode(&mut decoder);
+            if tag == u16::MAX {
+                continue;
+            }
+            let kind = SyntaxKind::from(tag);
+            if is_token(kind) {
+                return SourceLoc::decode(&mut decoder);
+            }
+            if has_pointer(kind) {
+                Node::decode(&mut decoder);
+            }
+        }
+    }
+
+    /// The node that a [`SyntaxKind::MODULE`] or [`SyntaxKind::MACRO_CALL`] node points to,
+    /// which is its body or expansion, or `None` if it hasn't been loaded or expanded.
+    #[cfg(test)]
+    fn pointer(&self, node: Node) -> Option<Node> {
+        let target = Node::decode(&mut Decoder::new(&self.data[node.plus(2).index()..]));
+        (target != node).then_some(target)
+    }
+}
+
+/// Where to look for the files of a module's out-of-line children, mirroring rustc.
+#[derive(Clone)]
+struct ModDir {
+    dir: PathBuf,
+    /// For a non-`mod.rs` file like `foo.rs`, children live under `foo/` relative to `dir`.
+    relative: Option<Name>,
+}
+
+/// A `#[path = "..."]` attribute on a module declaration.
+#[derive(Clone)]
+struct PathAttr {
+    value: String,
+    /// The location of the string literal.
+    loc: SourceLoc,
+    /// The location of the `cfg_attr` this attribute was expanded from, if any.
+    cfg_attr: Option<SourceLoc>,
+}
+
+/// What a `break` or `continue` can't leave without referring to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BreakableKind {
+    Loop,
+    Block,
+    /// A closure, which `break` and `continue` can't leave at all.
+    Closure,
+    /// An `async` or `gen` block, which `break` and `continue` can't leave at all.
+    AsyncBlock,
+}
+
+/// A node enclosing a `break` or `continue`, along with its label if it has one.
+#[derive(Clone)]
+struct Breakable {
+    node: Node,
+    kind: BreakableKind,
+    label: Option<(Name, SyntaxContext, SourceLoc)>,
+    /// The latest local variable in scope at the start of the node, after which any
+    /// `macro_rules!` definitions can see the label.
+    locals: Option<LocalId>,
 }
 
 struct PendingMod {
     path: Path,
     node: Node,
+    path_attr: Option<PathAttr>,
+    /// The latest `macro_rules!` definition in textual scope at the declaration.
+    macros: Option<MacroRulesId>,
+}
+
+/// What a macro call needs to be expanded, along with what the parser had in scope there to
+/// carry on with in its expansion.
+struct CallSite {
+    /// The kind of node to parse the expansion as, which is one of [`SyntaxKind::MACRO_ITEMS`],
+    /// [`SyntaxKind::MACRO_STMTS`], [`SyntaxKind::MACRO_EXPR`], [`SyntaxKind::MACRO_TYPE`] and
+    /// [`SyntaxKind::MACRO_PAT`].
+    root: SyntaxKind,
+    /// Whether the call is in item position, where it could define any name until it is expanded.
+    item: bool,
+    /// Whether the call is among the associated items of an impl or trait, which its expansion is
+    /// parsed as [`SyntaxKind::MACRO_ITEMS`] in place of.
+    assoc: bool,
+    /// The tokens between the delimiters of the call's token tree, or the tokens of the item that
+    /// a derive is applied to.
+    input: Vec<Token>,
+    /// The location of the closing delimiter, or of the path of a derive.
+    close: SourceLoc,
+    locals: Option<LocalId>,
+    generics: Option<LocalId>,
+    breakables: Vec<Breakable>,
+    /// How many expansions the call is nested in.
+    depth: usize,
+    /// The location of the outermost macro call this one came out of, or of this one if it is
+    /// written in a source file.
+    call_site: SourceLoc,
+}
+
+/// The expansion of a macro call, to be parsed.
+struct Expansion {
+    call: MacroCall,
+    site: CallSite,
+    output: Output,
+}
+
+/// What a macro call expands to.
+enum Output {
+    Tokens(Vec<Token>),
+    /// A file loaded by `include!`, which is parsed as if it were written in place of the call.
+    File(SourceFile),
 }
 
 enum Task {
     PendingMods(Vec<PendingMod>),
+    Expansions(Vec<Expansion>),
 }
 
 /// Data other than the actual source, to allow a mutable reference while parsing.
+/// Build a [`Resolver`] from the parts of a [`TreeData`] it needs, borrowing only those so that
+/// the rest can still be used alongside it.
+macro_rules! resolver {
+    ($tree:expr, $finalizing:expr) => {
+        Resolver {
+            names: &$tree.names,
+            nodes: &$tree.nodes,
+            paths: &$tree.paths,
+            scopes: &$tree.scopes,
+            items: &$tree.items,
+            modules: &$tree.modules,
+            crates: &$tree.crates,
+            extern_crates: &$tree.extern_crates,
+            macros: &$tree.macros,
+            imports: &mut $tree.imports,
+            unexpanded: &$tree.unexpanded,
+            expanding: &$tree.expanding,
+            diagnostics: &mut $tree.diagnostics,
+            finalizing: $finalizing,
+        }
+    };
+}
+
 struct TreeData {
+    crates: Crates,
     names: Names,
     paths: Paths,
     nodes: Nodes,
     scopes: Scopes,
+    items: Items,
+    imports: Imports,
+    locals: Locals,
+    macros: Macros,
+    contexts: SyntaxContexts,
+    /// Scopes containing macro calls in item position that won't be expanded, which could define
+    /// any name.
+    unexpanded: HashSet<Scope>,
+    /// The number of macro calls in item position in each scope that haven't been resolved yet,
+    /// until which names missing there can't be resolved.
+    expanding: HashMap<Scope, usize>,
     modules: HashMap<Path, Scope>,
+    mod_dirs: HashMap<Path, ModDir>,
+    /// The module loaded from each canonicalized file in each crate root, along with the node that
+    /// declared it.
+    mod_files: HashMap<(Path, PathBuf), (Path, Node)>,
+    /// The root module of the crate named by each `extern crate` item.
+    extern_crates: HashMap<Node, Path>,
+    /// The loop or labeled block that each `break` or `continue` refers to.
+    targets: HashMap<Node, Node>,
+    /// Files of the standard library, whose diagnostics aren't reported.
+    sysroot_files: HashSet<SourceFile>,
+    diagnostics: Vec<Diagnostic>,
     pending_mods: Vec<PendingMod>,
-    pending_macro_calls: Vec<Node>,
+    pending_macro_calls: Vec<(MacroCall, CallSite)>,
+    expansions: Vec<Expansion>,
+    /// What each macro call resolves to, once imports have been resolved.
+    macro_res: HashMap<Node, Res>,
+    records: Vec<RecordUse>,
+    path_uses: Vec<PathUse>,
+}
+
+/// The fields named by a struct expression or pattern, whose privacy is checked once imports have
+/// been resolved.
+///
+/// Fields accessed as `expr.field` aren't checked, since that needs the type of `expr`.
+struct RecordUse {
+    scope: Scope,
+    path: Vec<(Segment, SourceLoc)>,
+    fields: Vec<(Name, SourceLoc)>,
+}
+
+/// A path of more than one segment in an expression or type, whose privacy is checked once
+/// imports have been resolved.
+struct PathUse {
+    node: Node,
+    scope: Scope,
+    path: Vec<(Segment, SourceLoc)>,
+    ns: Namespace,
 }
 
-pub struct Tree<'a> {
-    edition: Edition,
-    root: &'a str,
+pub struct Tree {
     src: Source,
     tree: TreeData,
 }
 
-impl<'a> Tree<'a> {
-    pub fn new(edition: Edition, root: &'a str) -> io::Result<Self> {
-        let mut tree = Self {
-            edition,
-            root,
+impl Tree {
+    pub fn new() -> Self {
+        Self {
             src: Source::new(),
             tree: TreeData {
+                crates: Crates::new(),
                 names: Names::new(),
                 paths: Paths::new(),
                 nodes: Nodes::new(),
                 scopes: Scopes::new(),
+                items: Items::new(),
+                imports: Imports::new(),
+                locals: Locals::new(),
+                macros: Macros::new(),
+                contexts: SyntaxContexts::new(),
+                unexpanded: HashSet::new(),
+                expanding: HashMap::new(),
                 modules: HashMap::new(),
+                mod_dirs: HashMap::new(),
+                mod_files: HashMap::new(),
+                extern_crates: HashMap::new(),
+                targets: HashMap::new(),
+                sysroot_files: HashSet::new(),
+                diagnostics: Vec::new(),
                 pending_mods: Vec::new(),
                 pending_macro_calls: Vec::new(),
+                expansions: Vec::new(),
+                macro_res: HashMap::new(),
+                records: Vec::new(),
+                path_uses: Vec::new(),
             },
+        }
+    }
+
+    /// Add a crate whose dependencies have already been added, and parse its root file.
+    pub fn add_crate(
+        &mut self,
+        name: &str,
+        edition: Edition,
+        cfg: &[CfgFlag],
+        env: &[(String, String)],
+        root: &str,
+        deps: &[(String, Extern)],
+    ) -> io::Result<Crate> {
+        let cfg = CfgSet::new(&mut self.tree.names, cfg);
+        let env = env.iter().cloned().collect();
+        let deps = deps
+            .iter()
+            .map(|(name, dep)| (self.tree.names.make(name), *dep))
+            .collect();
+        let name = self.tree.names.make(name);
+        let path = self.tree.paths.root();
+        let krate = self.tree.crates.push(name, path, edition, cfg, env, deps);
+        self.parse_root(path, root)?;
+        Ok(krate)
+    }
+
+    /// Add the standard library crates, which every crate added afterward can use.
+    pub fn add_sysroot(&mut self, sysroot: &Sysroot, cfg: &[CfgFlag]) -> io::Result<()> {
+        for name in sysroot::CRATES {
+            let (root, edition) = sysroot.krate(name);
+            let name = self.tree.names.make(name);
+            let path = self.tree.paths.root();
+            let krate = self.tree.crates.push(
+                name,
+                path,
+                edition,
+                CfgSet::new(&mut self.tree.names, cfg),
+                HashMap::new(),
+                Vec::new(),
+            );
+            self.tree.crates.add_sysroot(name, krate);
+            let root = root.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{root:?} is not valid UTF-8"),
+                )
+            })?;
+            self.parse_root(path, root)?;
+        }
+        Ok(())
+    }
+
+    fn parse_root(&mut self, path: Path, root: &str) -> io::Result<()> {
+        let dir = path::Path::new(root)
+            .parent()
+            .unwrap_or(path::Path::new(""));
+        let mod_dir = ModDir {
+            dir: dir.to_owned(),
+            relative: None,
         };
-        tree.file_mod(root, tree.tree.paths.root())?;
-        Ok(tree)
+        let node = self.file_mod(root, path, mod_dir, None)?;
+        self.tree
+            .mod_files
+            .insert((path, fs::canonicalize(root)?), (path, node));
+        Ok(())
     }
 
-    fn file_mod(&mut self, name: &str, path: Path) -> io::Result<Node> {
+    /// Parse the file of a module, in which the `macros` definitions are in textual scope.
+    fn file_mod(
+        &mut self,
+        name: &str,
+        path: Path,
+        mod_dir: ModDir,
+        macros: Option<MacroRulesId>,
+    ) -> io::Result<Node> {
         let source_file = self.src.read(name, fs::File::open(name)?)?;
+        let krate = self.tree.crates.of_root(self.tree.paths.root_of(path));
+        if self.tree.crates.is_sysroot(krate) {
+            self.tree.sysroot_files.insert(source_file);
+        }
         let node = self.tree.nodes.next_index();
-        let scope = self.tree.scopes.push(path, None, node);
-        self.parse_source(scope, source_file);
+        let start = self.src.range(source_file).start;
+        let scope = self.tree.scopes.push(ScopeKind::Module, path, None, start);
+        self.tree.mod_dirs.insert(path, mod_dir);
         self.tree.modules.insert(path, scope);
+        let end = self.parse_source(scope, source_file, macros);
+        self.tree.macros.set_end(path, end);
         Ok(node)
     }
 
-    fn parse_source(&mut self, scope: Scope, source_file: SourceFile) {
+    /// Parse a source file, returning the latest `macro_rules!` definition in textual scope at
+    /// its end.
+    fn parse_source(
+        &mut self,
+        scope: Scope,
+        source_file: SourceFile,
+        macros: Option<MacroRulesId>,
+    ) -> Option<MacroRulesId> {
+        let root = self.tree.paths.root_of(self.tree.scopes.module(scope));
+        let krate = self.tree.crates.of_root(root);
+        let edition = self.tree.crates.edition(krate);
         let range = self.src.range(source_file);
         let text = self.src.code(range.clone());
-        let lexed = LexedStr::new(self.edition, text);
-        let input = lexed.to_input(self.edition);
-        let output = TopEntryPoint::SourceFile.parse(&input, self.edition);
-        Parser {
+        let lexed = LexedStr::new(edition, text);
+        let input = lexed.to_input(edition);
+        let output = TopEntryPoint::SourceFile.parse(&input, edition);
+        let mut parser = Parser {
             tree: &mut self.tree,
+            krate,
             lexed,
-            start: range.start,
+            locs: vec![(0, range.start, SyntaxContext::ROOT)],
             offset: 0,
             stack: Vec::new(),
             scope,
-            nesting: Vec::new(),
+            enums: Vec::new(),
+            owners: Vec::new(),
+            locals: None,
+            generics: None,
+            bindings: None,
+            fields: None,
+            breakables: Vec::new(),
+            assoc: false,
+            macros,
+            depth: 0,
+            call_site: None,
             iterator: output.iter().peekable(),
-        }
-        .entrypoint();
+            attrs: Vec::new(),
+            recording: None,
+            docs: false,
+        };
+        parser.entrypoint();
+        let end = parser.macros;
+        self.tree.scopes.set_end(scope, range.end);
+        end
     }
 
+    /// Load modules and expand macro calls until no more progress is made, then resolve imports
+    /// and the remaining names.
     pub fn expand(&mut self) -> io::Result<()> {
-        while let Some(task) = self.next_task() {
-            match task {
-                Task::PendingMods(pending_mods) => {
-                    for pending_mod in pending_mods {
-                        let name = self.path_buf(pending_mod.path);
-                        let node = self.file_mod(name.to_str().unwrap(), pending_mod.path)?;
-                        self.tree.nodes.write(pending_mod.node.plus(2), node);
+        // Whether to resolve macro calls as if every import that could be had been, because
+        // nothing else is left to do.
+        let mut force = false;
+        loop {
+            while let Some(task) = self.next_task() {
+                match task {
+                    Task::PendingMods(pending_mods) => {
+                        for pending_mod in pending_mods {
+                            self.load_mod(pending_mod)?;
+                        }
+                    }
+                    Task::Expansions(expansions) => {
+                        for expansion in expansions {
+                            self.parse_expansion(expansion);
+                        }
                     }
                 }
             }
+            if self.resolve_macro_calls(force) {
+                force = false;
+            } else if !force && !self.tree.pending_macro_calls.is_empty() {
+                force = true;
+            } else {
+                break;
+            }
+        }
+        let tree = &mut self.tree;
+        let mut resolver = resolver!(tree, false);
+        resolver.resolve();
+        let mut privacy = Vec::new();
+        for record in &tree.records {
+            let res = match resolver.resolve_path(record.scope, &record.path, Namespace::Type) {
+                Ok(res) => res,
+                Err(diagnostic) => {
+                    privacy.push(diagnostic);
+                    continue;
+                }
+            };
+            let Some(Res::Item(item)) = res else { continue };
+            let module = tree.scopes.module(record.scope);
+            for &(name, loc) in &record.fields {
+                let fields = tree.items.fields(item.node);
+                let Some(field) = fields.iter().find(|field| field.name == Some(name)) else {
+                    continue;
+                };
+                if !field.vis.is_accessible_from(module, &tree.paths) {
+                    let (name, kind) = (tree.names.get(name), item.kind.descr());
+                    let owner = match record.path.last() {
+                        Some(&(Segment::Name(owner), _)) => tree.names.get(owner),
+                        _ => "",
+                    };
+                    privacy.push(
+                        Diagnostic::error(
+                            loc,
+                            format!("field `{name}` of {kind} `{owner}` is private"),
+                        )
+                        .code("E0451")
+                        .label(loc, "private field"),
+                    );
+                }
+            }
+        }
+        for path_use in &tree.path_uses {
+            if let Err(diagnostic) =
+                resolver.resolve_path(path_use.scope, &path_use.path, path_use.ns)
+            {
+                privacy.push(diagnostic);
+            }
         }
+        let diagnostics = tree
+            .locals
+            .resolve(&tree.names, &tree.contexts, |scope, ns, name| {
+                resolver.lookup(scope, ns, name)
+            });
+        tree.diagnostics.extend(privacy);
+        tree.diagnostics.extend(diagnostics);
         Ok(())
     }
 
@@ -156,22 +556,404 @@ impl<'a> Tree<'a> {
         if !pending_mods.is_empty() {
             return Some(Task::PendingMods(pending_mods));
         }
+        let expansions = mem::take(&mut self.tree.expansions);
+        if !expansions.is_empty() {
+            return Some(Task::Expansions(expansions));
+        }
         None
     }
 
-    fn path_buf(&self, mut path: Path) -> PathBuf {
-        let mut components = Vec::new();
-        while let Some(stem) = self.tree.paths.stem(path) {
-            components.push(self.tree.names.get(stem));
-            path = self.tree.paths.parent(path).unwrap();
+    fn load_mod(&mut self, pending_mod: PendingMod) -> io::Result<()> {
+        let Some((file, mod_dir)) = self.mod_file(&pending_mod) else {
+            return Ok(());
+        };
+        let root = self.tree.paths.root_of(pending_mod.path);
+        let canonical = (root, fs::canonicalize(&file)?);
+        if let Some(&(other, decl)) = self.tree.mod_files.get(&canonical) {
+            self.include_error(&pending_mod, &file, other, decl);
+            return Ok(());
+        }
+        self.tree
+            .mod_files
+            .insert(canonical, (pending_mod.path, pending_mod.node));
+        let node = self.file_mod(
+            file.to_str().unwrap(),
+            pending_mod.path,
+            mod_dir,
+            pending_mod.macros,
+        )?;
+        self.tree.nodes.write(pending_mod.node.plus(2), node);
+        Ok(())
+    }
+
+    /// Resolve what imports and macro calls can be, expanding calls to `macro_rules!` and built-in
+    /// macros later, and returning whether any calls were resolved.
+    ///
+    /// With `force`, imports and calls are resolved even if pending imports or other macro calls
+    /// could still change what they refer to.
+    fn resolve_macro_calls(&mut self, force: bool) -> bool {
+        let (src, tree) = (&mut self.src, &mut self.tree);
+        let mut resolver = resolver!(tree, force);
+        resolver.resolve_some();
+        let mut diagnostics = Vec::new();
+        // The scope of each call that was resolved, whether it is in item position, and whether it
+        // will be expanded.
+        let mut resolved = Vec::new();
+        let mut builtins = Vec::new();
+        for (call, site) in mem::take(&mut tree.pending_macro_calls) {
+            let res = match resolver.resolve_macro(&call) {
+                Ok(Some(res)) => res,
+                Ok(None) => {
+                    tree.pending_macro_calls.push((call, site));
+                    continue;
+                }
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    resolved.push((call.scope, site.item, false));
+                    continue;
+                }
+            };
+            tree.macro_res.insert(call.node, res);
+            let builtin = match res {
+                Res::Item(item) => tree.macros.builtin(item.node),
+                // Without the standard library's sources its macros resolve opaquely, so calls
+                // named like a built-in macro are taken to be calls to it.
+                Res::Opaque => match call.path.last() {
+                    Some(&(Segment::Name(name), _)) => {
+                        BuiltinMacro::from_name(tree.names.get(name))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            // Derives can only be used in `#[derive]`, where nothing else can be.
+            let derive = call.kind == MacroKind::Derive;
+            let builtin =
+                builtin.filter(|&builtin| matches!(builtin, BuiltinMacro::Derive(_)) == derive);
+            if let Some(builtin) = builtin {
+                // A macro of the standard library without its sources is marked as if it was
+                // defined at the call.
+                let def = match res {
+                    Res::Item(item) => item.node,
+                    _ => call.node,
+                };
+                builtins.push((builtin, call, site, def));
+                continue;
+            }
+            let rules = match res {
+                Res::Item(item) if !derive => {
+                    tree.macros.rules(item.node).map(|rules| (rules, item.node))
+                }
+                _ => None,
+            };
+            let Some((rules, def)) = rules else {
+                resolved.push((call.scope, site.item, false));
+                continue;
+            };
+            if site.depth >= RECURSION_LIMIT {
+                let loc = tree.nodes.loc(call.node);
+                let name = tree.names.get(rules.name());
+                diagnostics.push(
+                    Diagnostic::error(
+                        loc,
+                        format!("recursion limit reached while expanding `{name}!`"),
+                    )
+                    .help(format!(
+                        "consider increasing the recursion limit by adding a \
+                             `#![recursion_limit = \"{}\"]` attribute to your crate",
+                        RECURSION_LIMIT * 2,
+                    )),
+                );
+                resolved.push((call.scope, site.item, false));
+                continue;
+            }
+            let mark = Mark {
+                call: call.node,
+                def,
+                transparency: Transparency::SemiTransparent,
+            };
+            let marker = (&mut tree.contexts, mark);
+            match rules.expand(&tree.names, marker, &site.input, site.close) {
+                Ok(tokens) => {
+                    resolved.push((call.scope, site.item, true));
+                    let output = Output::Tokens(tokens);
+                    tree.expansions.push(Expansion { call, site, output });
+                }
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    resolved.push((call.scope, site.item, false));
+                }
+            }
+        }
+        for (builtin, call, site, def) in builtins {
+            // Like rustc, derives keep the names they bind to themselves, while other built-in
+            // macros are `macro_rules!` macros.
+            let transparency = match builtin {
+                BuiltinMacro::Derive(_) => Transparency::Opaque,
+                _ => Transparency::SemiTransparent,
+            };
+            let mark = Mark {
+                call: call.node,
+                def,
+                transparency,
+            };
+            let ctx = tree.contexts.apply(SyntaxContext::ROOT, mark);
+            let module = tree.scopes.module(call.scope);
+            let krate = tree.crates.of_root(tree.paths.root_of(module));
+            let mut cx = BuiltinCx {
+                src: &mut *src,
+                names: &mut tree.names,
+                paths: &tree.paths,
+                cfg: tree.crates.cfg(krate),
+                env: tree.crates.env(krate),
+                krate: tree.crates.name(krate),
+                module,
+                call_site: site.call_site,
+                ctx,
+            };
+            let output = match cx.expand(builtin, &site.input, tree.nodes.loc(call.node)) {
+                Ok(BuiltinExpansion::Tokens(tokens)) => Ok(Output::Tokens(tokens)),
+                Ok(BuiltinExpansion::File(file)) => {
+                    if tree.crates.is_sysroot(krate) {
+                        tree.sysroot_files.insert(file);
+                    }
+                    Ok(Output::File(file))
+                }
+                Err(diagnostic) => Err(diagnostic),
+            };
+            match output {
+                Ok(output) => {
+                    resolved.push((call.scope, site.item, true));
+                    tree.expansions.push(Expansion { call, site, output });
+                }
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    resolved.push((call.scope, site.item, false));
+                }
+            }
+        }
+        tree.diagnostics.extend(diagnostics);
+        let progress = !resolved.is_empty();
+        for (scope, item, expanded) in resolved {
+            if !item {
+                continue;
+            }
+            if let Some(count) = tree.expanding.get_mut(&scope) {
+                *count -= 1;
+                if *count == 0 {
+                    tree.expanding.remove(&scope);
+                }
+            }
+            if !expanded {
+                tree.unexpanded.insert(scope);
+            }
+        }
+        progress
+    }
+
+    /// Parse the expansion of a macro call, carrying on from where the parser was at the call, and
+    /// point the call's node to it.
+    fn parse_expansion(&mut self, expansion: Expansion) {
+        let Expansion { call, site, output } = expansion;
+        let root = self.tree.paths.root_of(self.tree.scopes.module(call.scope));
+        let krate = self.tree.crates.of_root(root);
+        let edition = self.tree.crates.edition(krate);
+        let rendered;
+        // The text of an included file comes from where it is written rather than from the call.
+        let (text, locs, call_site) = match output {
+            Output::Tokens(tokens) => {
+                let locs;
+                (rendered, locs) = render(&self.tree.names, &tokens, site.close);
+                (&rendered[..], locs, Some(site.call_site))
+            }
+            Output::File(file) => {
+                let range = self.src.range(file);
+                let locs = vec![(0, range.start, SyntaxContext::ROOT)];
+                (self.src.code(range), locs, None)
+            }
+        };
+        let lexed = LexedStr::new(edition, text);
+        let input = lexed.to_input(edition);
+        let entry = match site.root {
+            SyntaxKind::MACRO_ITEMS => TopEntryPoint::MacroItems,
+            SyntaxKind::MACRO_STMTS => TopEntryPoint::MacroStmts,
+            SyntaxKind::MACRO_TYPE => TopEntryPoint::Type,
+            SyntaxKind::MACRO_PAT => TopEntryPoint::Pattern,
+            _ => TopEntryPoint::Expr,
+        };
+        let output = entry.parse(&input, edition);
+        let node = self.tree.nodes.next_index();
+        let mut parser = Parser {
+            tree: &mut self.tree,
+            krate,
+            lexed,
+            locs,
+            offset: 0,
+            stack: Vec::new(),
+            scope: call.scope,
+            enums: Vec::new(),
+            owners: Vec::new(),
+            locals: site.locals,
+            generics: site.generics,
+            bindings: None,
+            fields: None,
+            breakables: site.breakables,
+            assoc: site.assoc,
+            macros: call.textual,
+            depth: site.depth + 1,
+            call_site,
+            iterator: output.iter().peekable(),
+            attrs: Vec::new(),
+            recording: None,
+            docs: false,
+        };
+        parser.expansion(site.root);
+        let (end, locals) = (parser.macros, parser.locals);
+        self.tree.nodes.write(call.node.plus(2), node);
+        self.tree.macros.set_expansion_end(call.node, end);
+        if site.root == SyntaxKind::MACRO_STMTS {
+            self.tree.locals.set_expansion_end(call.node, locals);
+        }
+    }
+
+    /// Find the file for an out-of-line module, reporting a diagnostic if there isn't exactly one.
+    fn mod_file(&mut self, pending_mod: &PendingMod) -> Option<(PathBuf, ModDir)> {
+        let parent = self.tree.paths.parent(pending_mod.path).unwrap();
+        let stem = self.tree.paths.stem(pending_mod.path).unwrap();
+        let ModDir { dir, relative } = &self.tree.mod_dirs[&parent];
+        let loc = self.tree.nodes.loc(pending_mod.node);
+        if let Some(path_attr) = pending_mod.path_attr.clone() {
+            // Files loaded via `#[path]` are always treated like `mod.rs` files.
+            let file = dir.join(path_attr.value);
+            if !file.exists() {
+                let name = self.tree.names.get(stem);
+                let mut diagnostic =
+                    Diagnostic::error(loc, format!("file not found for module `{name}`"))
+                        .code("E0583")
+                        .label(path_attr.loc, format!("`#[path]` points to {file:?}"));
+                if let Some(cfg_attr) = path_attr.cfg_attr {
+                    diagnostic =
+                        diagnostic.label(cfg_attr, "`#[path]` expanded from this `cfg_attr`");
+                }
+                self.tree.diagnostics.push(diagnostic);
+                return None;
+            }
+            let mod_dir = ModDir {
+                dir: file.parent().unwrap().to_owned(),
+                relative: None,
+            };
+            return Some((file, mod_dir));
+        }
+        let mut dir = dir.clone();
+        if let Some(relative) = *relative {
+            dir.push(file_stem(self.tree.names.get(relative)));
+        }
+        let name = file_stem(self.tree.names.get(stem));
+        let file_rs = dir.join(format!("{name}.rs"));
+        let file_mod_rs = dir.join(name).join("mod.rs");
+        match (file_rs.exists(), file_mod_rs.exists()) {
+            (true, false) => {
+                let mod_dir = ModDir {
+                    dir: file_rs.parent().unwrap().to_owned(),
+                    relative: Some(stem),
+                };
+                Some((file_rs, mod_dir))
+            }
+            (false, true) => {
+                let mod_dir = ModDir {
+                    dir: file_mod_rs.parent().unwrap().to_owned(),
+                    relative: None,
+                };
+                Some((file_mod_rs, mod_dir))
+            }
+            (false, false) => {
+                self.tree.diagnostics.push(
+                    Diagnostic::error(loc, format!("file not found for module `{name}`"))
+                        .code("E0583")
+                        .help(format!(
+                        "to create the module `{name}`, create file {file_rs:?} or {file_mod_rs:?}",
+                    )),
+                );
+                None
+            }
+            (true, true) => {
+                self.tree.diagnostics.push(
+                    Diagnostic::error(
+                        loc,
+                        format!(
+                            "file for module `{name}` found at both {file_rs:?} and {file_mod_rs:?}",
+                        ),
+                    )
+                    .code("E0761")
+                    .help("delete or rename one of them to remove the ambiguity"),
+                );
+                None
+            }
+        }
+    }
+
+    /// Report a module file that was already loaded for the module `other`, declared at `decl`.
+    fn include_error(
+        &mut self,
+        pending_mod: &PendingMod,
+        file: &path::Path,
+        other: Path,
+        decl: Node,
+    ) {
+        let names = &self.tree.names;
+        let paths = &self.tree.paths;
+        let loc = self.tree.nodes.loc(pending_mod.node);
+        let other_loc = self.tree.nodes.loc(decl);
+        let module = paths.display(names, pending_mod.path);
+        let other_module = paths.display(names, other);
+        let mut ancestor = paths.parent(pending_mod.path);
+        while let Some(path) = ancestor {
+            if path == other {
+                self.tree.diagnostics.push(
+                    Diagnostic::error(
+                        loc,
+                        format!(
+                            "circular modules: {file:?} is loaded as `{module}` inside its own module `{other_module}`",
+                        ),
+                    )
+                    .label(other_loc, format!("`{other_module}` is loaded here")),
+                );
+                return;
+            }
+            ancestor = paths.parent(path);
         }
-        let mut buf = PathBuf::from(self.root);
-        buf.pop();
-        for component in components.into_iter().rev() {
-            buf.push(component);
+        self.tree.diagnostics.push(
+            Diagnostic::error(
+                loc,
+                format!("{file:?} is loaded as both `{other_module}` and `{module}`"),
+            )
+            .label(other_loc, format!("`{other_module}` is loaded here")),
+        );
+    }
+
+    /// Diagnostics outside of the standard library.
+    fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        let sysroot_files = &self.tree.sysroot_files;
+        self.tree
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| !sysroot_files.contains(&self.src.file(diagnostic.loc())))
+    }
+
+    /// Print all diagnostics to stderr, returning whether any of them are errors.
+    pub fn report(&self) -> bool {
+        let mut any = false;
+        for diagnostic in self.diagnostics() {
+            eprintln!("{}", diagnostic.render(&self.src));
+            any |= diagnostic.is_error();
         }
-        buf.set_extension("rs");
-        buf
+        any
+    }
+
+    /// The node of the expansion of a [`SyntaxKind::MACRO_CALL`], if it was expanded.
+    #[cfg(test)]
+    pub fn expansion(&self, node: Node) -> Option<Node> {
+        self.tree.nodes.pointer(node)
     }
 
     pub fn print(&self) {
@@ -192,10 +974,11 @@ impl<'a> Tree<'a> {
                     SourceLoc::decode(&mut decoder);
                     if let SyntaxKind::IDENT = kind {
                         let name = Name::decode(&mut decoder);
+                        SyntaxContext::decode(&mut decoder);
                         print!(" {:?}", self.tree.names.get(name));
                     }
                 } else {
-                    if let SyntaxKind::MODULE = kind {
+                    if has_pointer(kind) {
                         Node::decode(&mut decoder);
                     }
                     print!(" {{");
@@ -210,16 +993,149 @@ impl<'a> Tree<'a> {
     }
 }
 
+/// Point a diagnostic about an attribute back at the `cfg_attr` it came from, if any.
+fn expanded_from(diagnostic: Diagnostic, attr: &Attr) -> Diagnostic {
+    match attr.cfg_attr {
+        Some(loc) => diagnostic.label(loc, "expanded from this `cfg_attr`"),
+        None => diagnostic,
+    }
+}
+
+/// Whether a node kind is a pattern.
+fn is_pattern(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::BOX_PAT
+            | SyntaxKind::CONST_BLOCK_PAT
+            | SyntaxKind::IDENT_PAT
+            | SyntaxKind::LITERAL_PAT
+            | SyntaxKind::MACRO_PAT
+            | SyntaxKind::OR_PAT
+            | SyntaxKind::PAREN_PAT
+            | SyntaxKind::PATH_PAT
+            | SyntaxKind::RANGE_PAT
+            | SyntaxKind::RECORD_PAT
+            | SyntaxKind::REF_PAT
+            | SyntaxKind::REST_PAT
+            | SyntaxKind::SLICE_PAT
+            | SyntaxKind::TUPLE_PAT
+            | SyntaxKind::TUPLE_STRUCT_PAT
+            | SyntaxKind::WILDCARD_PAT,
+    )
+}
+
+/// The file or directory name for a module, without any raw identifier prefix.
+fn file_stem(name: &str) -> &str {
+    name.strip_prefix("r#").unwrap_or(name)
+}
+
+/// Render the tokens of a macro expansion as text for the lexer, along with the offset in the
+/// text of each token, where it came from and its syntax context, ending with the `end` of the
+/// macro call.
+///
+/// Tokens are separated by spaces unless they are punctuation that was adjacent in the source, so
+/// that the lexer splits and joins them the same way as before.
+fn render(
+    names: &Names,
+    tokens: &[Token],
+    end: SourceLoc,
+) -> (String, Vec<(usize, SourceLoc, SyntaxContext)>) {
+    let mut text = String::new();
+    let mut locs = Vec::new();
+    let mut prev: Option<(Token, usize)> = None;
+    for &token in tokens {
+        let token_text = names.get(token.text);
+        let joint = prev.is_some_and(|(prev, len)| {
+            prev.kind.is_punct() && token.kind.is_punct() && prev.loc.plus(len) == token.loc
+        });
+        if prev.is_some() && !joint {
+            text.push(' ');
+        }
+        locs.push((text.len(), token.loc, token.ctx));
+        text.push_str(token_text);
+        prev = Some((token, token_text.len()));
+    }
+    locs.push((text.len(), end, SyntaxContext::ROOT));
+    (text, locs)
+}
+
+/// Add a token to the tree, returning its name if it is an identifier.
+fn push_token(
+    tree: &mut TreeData,
+    recording: &mut Option<Vec<Token>>,
+    kind: SyntaxKind,
+    text: &str,
+    (loc, ctx): (SourceLoc, SyntaxContext),
+) -> Option<Name> {
+    tree.nodes.push(kind);
+    tree.nodes.push(loc);
+    if let Some(tokens) = recording {
+        let text = tree.names.make(text);
+        tokens.push(Token {
+            kind,
+            text,
+            loc,
+            ctx,
+        });
+    }
+    match kind {
+        SyntaxKind::IDENT => {
+            let name = tree.names.make(text);
+            tree.nodes.push(name);
+            tree.nodes.push(ctx);
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+/// The path of a derive in a `#[derive]` attribute, along with its tokens.
+type DerivePath = (Vec<(Segment, SourceLoc)>, Vec<Token>);
+
 /// Helper struct to process parser output into data structures used for macroexpansion.
 struct Parser<'a, 'b, I: Iterator<Item = Step<'a>>> {
     tree: &'b mut TreeData,
+    krate: Crate,
     lexed: LexedStr<'a>,
-    start: SourceLoc,
+    /// Where the text comes from: the offset in the text at which each part of it starts, along
+    /// with the location and syntax context of that part. A source file is a single part, while
+    /// the expansion of a macro call has a part for each token.
+    locs: Vec<(usize, SourceLoc, SyntaxContext)>,
     offset: usize,
-    stack: Vec<SyntaxKind>,
+    /// The kind of each node being processed, and the number of [`Self::attrs`] outside it.
+    stack: Vec<(SyntaxKind, usize)>,
     scope: Scope,
-    nesting: Vec<Scope>,
+    /// The enums whose variant lists are being processed, and their visibility.
+    enums: Vec<(Node, Visibility)>,
+    /// The structs, unions and variants whose fields are being processed, and the default
+    /// visibility of those fields.
+    owners: Vec<(Node, Visibility)>,
+    /// The latest local variable in scope.
+    locals: Option<LocalId>,
+    /// The latest const generic parameter in scope.
+    generics: Option<LocalId>,
+    /// Names bound by the pattern being processed, along with their syntax contexts, if any.
+    bindings: Option<Vec<((Name, SyntaxContext), Node)>>,
+    /// Fields named by the struct expression or pattern being processed, if any.
+    fields: Option<Vec<(Name, SourceLoc)>>,
+    /// The loops, blocks and closures containing the node being processed in the innermost item,
+    /// innermost last.
+    breakables: Vec<Breakable>,
+    /// Whether the text is the expansion of a macro call among associated items.
+    assoc: bool,
+    /// The latest `macro_rules!` definition in textual scope.
+    macros: Option<MacroRulesId>,
+    /// How many macro expansions the text is nested in.
+    depth: usize,
+    /// The location of the outermost macro call the text was expanded from, if any.
+    call_site: Option<SourceLoc>,
     iterator: Peekable<I>,
+    /// Attributes of the nodes on the stack.
+    attrs: Vec<Attr>,
+    /// Tokens of the attribute being processed, if any.
+    recording: Option<Vec<Token>>,
+    /// Whether doc comments are recorded, as they are in the token tree of a macro call.
+    docs: bool,
 }
 
 impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
@@ -229,42 +1145,159 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
         assert!(self.stack.is_empty());
     }
 
-    /// Handle a [`Step::Token`].
-    fn token(&mut self, kind: SyntaxKind, n_input_tokens: u8) -> Option<Name> {
-        assert!(is_token(kind));
-        while self.lexed.kind(self.offset).is_trivia() {
+    /// Process the parsed expansion of a macro call, which is wrapped in a node of the given
+    /// `root` kind unless it is parsed as items or statements, which it already is.
+    fn expansion(&mut self, root: SyntaxKind) {
+        if let SyntaxKind::MACRO_ITEMS | SyntaxKind::MACRO_STMTS = root {
+            self.entrypoint();
+            return;
+        }
+        self.enter(root);
+        while let Some(()) = self.node() {}
+        assert!(self.iterator.next().is_none());
+        self.exit();
+    }
+
+    /// The location and syntax context of the given offset in the text.
+    fn span_at(&self, offset: usize) -> (SourceLoc, SyntaxContext) {
+        let i = self.locs.partition_point(|&(start, ..)| start <= offset) - 1;
+        let (start, loc, ctx) = self.locs[i];
+        (loc.plus(offset - start), ctx)
+    }
+
+    /// The location of the given offset in the text.
+    fn loc_at(&self, offset: usize) -> SourceLoc {
+        self.span_at(offset).0
+    }
+
+    fn skip_trivia(&mut self) {
+        while self.offset < self.lexed.len() && self.lexed.kind(self.offset).is_trivia() {
+            if self.docs {
+                self.doc_comment();
+            }
             self.offset += 1;
         }
+    }
+
+    /// Record the comment at the current offset, if it is a doc comment, as the tokens of the
+    /// `#[doc = "..."]` or `#![doc = "..."]` attribute it stands for.
+    fn doc_comment(&mut self) {
+        if self.lexed.kind(self.offset) != SyntaxKind::COMMENT {
+            return;
+        }
+        let text = self.lexed.text(self.offset);
+        let block = |prefix| {
+            let rest: &str = text.strip_prefix(prefix)?;
+            // `/***` starts an ordinary comment, and `/**/` is an empty one.
+            (!rest.starts_with('*')).then_some(())?;
+            rest.strip_suffix("*/")
+        };
+        let (inner, doc) = if let Some(doc) = text.strip_prefix("//!") {
+            (true, doc)
+        } else if let Some(doc) = text.strip_prefix("///").filter(|doc| !doc.starts_with('/')) {
+            (false, doc)
+        } else if let Some(doc) = block("/*!") {
+            (true, doc)
+        } else if let Some(doc) = block("/**") {
+            (false, doc)
+        } else {
+            return;
+        };
+        let value = format!("{doc:?}");
+        let (loc, ctx) = self.span_at(self.lexed.text_start(self.offset));
+        let bang = inner.then_some((SyntaxKind::BANG, "!"));
+        let tokens = [(SyntaxKind::POUND, "#")].into_iter().chain(bang).chain([
+            (SyntaxKind::L_BRACK, "["),
+            (SyntaxKind::IDENT, "doc"),
+            (SyntaxKind::EQ, "="),
+            (SyntaxKind::STRING, &value),
+            (SyntaxKind::R_BRACK, "]"),
+        ]);
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        for (kind, text) in tokens {
+            let text = self.tree.names.make(text);
+            recording.push(Token {
+                kind,
+                text,
+                loc,
+                ctx,
+            });
+        }
+    }
+
+    /// Handle a [`Step::Token`], returning its name and syntax context if it is an identifier.
+    fn token(&mut self, kind: SyntaxKind, n_input_tokens: u8) -> Option<(Name, SyntaxContext)> {
+        assert!(is_token(kind));
+        self.skip_trivia();
         let n = usize::from(n_input_tokens);
-        self.tree.nodes.push(kind);
-        self.tree.nodes.push(self.start.plus(self.offset));
-        match kind {
-            SyntaxKind::IDENT => {
-                let name = self
-                    .tree
-                    .names
-                    .make(self.lexed.range_text(self.offset..self.offset + n));
-                self.tree.nodes.push(name);
-                self.offset += n;
-                Some(name)
-            }
-            _ => {
-                self.offset += n;
-                None
-            }
+        let span = self.span_at(self.lexed.text_start(self.offset));
+        let text = self.lexed.range_text(self.offset..self.offset + n);
+        let name = push_token(self.tree, &mut self.recording, kind, text, span);
+        self.offset += n;
+        name.map(|name| (name, span.1))
+    }
+
+    /// Handle a [`Step::FloatSplit`], which splits a float literal in a nested field access like
+    /// `x.0.1` into the two field names it actually is.
+    ///
+    /// The parser omits the exit of the inner field access, and also of the outer one unless the
+    /// literal ends in a dot like the `0.` in `x.0.foo()`.
+    fn float_split(&mut self, ends_in_dot: bool) {
+        self.skip_trivia();
+        let start = self.lexed.text_start(self.offset);
+        let text = self.lexed.text(self.offset).to_owned();
+        self.offset += 1;
+        let (left, right) = text.split_once('.').unwrap();
+        let span = |i| self.span_at(start + i);
+        let (left_span, dot_span, right_span) = (span(0), span(left.len()), span(left.len() + 1));
+        let int = SyntaxKind::INT_NUMBER;
+        self.enter(SyntaxKind::NAME_REF);
+        push_token(self.tree, &mut self.recording, int, left, left_span);
+        self.exit();
+        self.exit();
+        push_token(
+            self.tree,
+            &mut self.recording,
+            SyntaxKind::DOT,
+            ".",
+            dot_span,
+        );
+        if !ends_in_dot {
+            self.enter(SyntaxKind::NAME_REF);
+            push_token(self.tree, &mut self.recording, int, right, right_span);
+            self.exit();
+            self.exit();
         }
     }
 
+    /// Handle a [`Step::Error`].
+    fn error(&mut self, msg: &str) {
+        self.skip_trivia();
+        let loc = self.loc_at(self.lexed.text_start(self.offset));
+        self.tree
+            .diagnostics
+            .push(Diagnostic::error(loc, format!("syntax error: {msg}")));
+    }
+
     /// Handle a [`Step::Enter`].
     fn enter(&mut self, kind: SyntaxKind) -> Node {
         assert!(!is_token(kind));
-        self.stack.push(kind);
-        self.tree.nodes.push(kind)
+        self.stack.push((kind, self.attrs.len()));
+        let node = self.tree.nodes.push(kind);
+        if has_pointer(kind) {
+            // Placeholder for a pointer to the module body or macro expansion, pointing back to
+            // the node itself.
+            self.tree.nodes.push(node);
+        }
+        node
     }
 
     /// Handle a [`Step::Exit`].
     fn exit(&mut self) {
-        self.stack.pop();
+        let (_, attrs) = self.stack.pop().unwrap();
+        self.attrs.truncate(attrs);
         self.tree.nodes.push(u16::MAX);
     }
 
@@ -285,20 +1318,67 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
                 } => {
                     self.token(kind, n_input_tokens);
                 }
-                Step::FloatSplit { .. } => todo!(),
+                Step::FloatSplit { ends_in_dot } => self.float_split(ends_in_dot),
                 Step::Enter { kind } => {
                     let node = self.enter(kind);
-                    match kind {
-                        SyntaxKind::BLOCK_EXPR => self.block_expr(node),
-                        SyntaxKind::MACRO_CALL => self.macro_call(node),
-                        SyntaxKind::MODULE => self.module_decl(node),
-                        _ => {}
+                    if !self.outer_attrs() {
+                        self.strip(node);
+                    } else {
+                        match kind {
+                            SyntaxKind::ATTR => self.attr(),
+                            SyntaxKind::BLOCK_EXPR => self.block_expr(node),
+                            SyntaxKind::BREAK_EXPR | SyntaxKind::CONTINUE_EXPR => {
+                                self.jump(kind, node)
+                            }
+                            SyntaxKind::CLOSURE_EXPR => self.closure_expr(node),
+                            SyntaxKind::CONST_PARAM => self.const_param(node),
+                            SyntaxKind::EXTERN_CRATE => self.extern_crate(node),
+                            SyntaxKind::FOR_EXPR => self.for_expr(node),
+                            SyntaxKind::FOR_TYPE | SyntaxKind::WHERE_PRED => self.for_binder(),
+                            SyntaxKind::GENERIC_PARAM_LIST => self.generic_param_list(),
+                            SyntaxKind::IDENT_PAT => self.ident_pat(node),
+                            SyntaxKind::IF_EXPR => self.if_expr(),
+                            SyntaxKind::IMPL => self.impl_block(),
+                            SyntaxKind::LABEL => self.label(),
+                            SyntaxKind::LET_EXPR | SyntaxKind::LET_STMT => self.let_binding(),
+                            SyntaxKind::LIFETIME => self.lifetime(node),
+                            SyntaxKind::LOOP_EXPR => self.loop_expr(node),
+                            SyntaxKind::MACRO_CALL => self.macro_call(node),
+                            SyntaxKind::MATCH_ARM => self.match_arm(),
+                            SyntaxKind::MODULE => self.module_decl(node),
+                            SyntaxKind::PARAM => self.param(),
+                            SyntaxKind::PATH_EXPR => self.path_expr(node),
+                            SyntaxKind::PATH_TYPE => self.path_type(node),
+                            SyntaxKind::RECORD_EXPR | SyntaxKind::RECORD_PAT => self.record_expr(),
+                            SyntaxKind::RECORD_EXPR_FIELD | SyntaxKind::RECORD_PAT_FIELD => {
+                                self.record_expr_field()
+                            }
+                            SyntaxKind::CONST
+                            | SyntaxKind::ENUM
+                            | SyntaxKind::FN
+                            | SyntaxKind::MACRO_DEF
+                            | SyntaxKind::MACRO_RULES
+                            | SyntaxKind::STATIC
+                            | SyntaxKind::STRUCT
+                            | SyntaxKind::TRAIT
+                            | SyntaxKind::TRAIT_ALIAS
+                            | SyntaxKind::TYPE_ALIAS
+                            | SyntaxKind::UNION => self.item(kind, node),
+                            SyntaxKind::RECORD_FIELD | SyntaxKind::TUPLE_FIELD => {
+                                self.field(kind, node)
+                            }
+                            SyntaxKind::TYPE_PARAM => self.type_param(node),
+                            SyntaxKind::USE => self.use_item(),
+                            SyntaxKind::VARIANT => self.variant(node),
+                            SyntaxKind::WHILE_EXPR => self.while_expr(node),
+                            _ => {}
+                        }
                     }
                 }
                 Step::Exit => self.exit(),
-                Step::Error { .. } => todo!(),
+                Step::Error { msg } => self.error(msg),
             }
-            if self.stack.len() == height {
+            if self.stack.len() <= height {
                 return Some(());
             }
         }
@@ -324,16 +1404,40 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
         }
     }
 
-    fn scope_node(&mut self, scope: Scope) {
-        self.nesting.push(mem::replace(&mut self.scope, scope));
-        self.node().unwrap();
-        self.scope = self.nesting.pop().unwrap();
+    /// The location just after the last token processed.
+    fn loc(&self) -> SourceLoc {
+        self.loc_at(self.lexed.text_start(self.offset))
     }
 
-    fn scope_ascend(&mut self, scope: Scope) {
-        self.nesting.push(mem::replace(&mut self.scope, scope));
-        self.ascend();
-        self.scope = self.nesting.pop().unwrap();
+    /// Where a scope starting or ending here is located. In a macro expansion, whose tokens are
+    /// located in the macro definition, that's the call site, so every scope in the expansion is
+    /// empty there.
+    fn scope_loc(&self) -> SourceLoc {
+        self.call_site.unwrap_or_else(|| self.loc())
+    }
+
+    /// Enter a new scope nested in the current one, starting here.
+    fn push_scope(&mut self, kind: ScopeKind) {
+        let module = self.tree.scopes.module(self.scope);
+        let start = self.scope_loc();
+        self.scope = self.tree.scopes.push(kind, module, Some(self.scope), start);
+    }
+
+    /// Leave the scopes entered since `outer` was the current one, ending them here.
+    fn pop_scopes(&mut self, outer: Scope) {
+        let end = self.scope_loc();
+        while self.scope != outer {
+            self.tree.scopes.set_end(self.scope, end);
+            self.scope = self.tree.scopes.parent(self.scope).unwrap();
+        }
+    }
+
+    /// Process the next node in the given scope, which is nested in the current one and ends
+    /// after it.
+    fn scope_node(&mut self, scope: Scope) {
+        let outer = mem::replace(&mut self.scope, scope);
+        self.node().unwrap();
+        self.pop_scopes(outer);
     }
 
     /// Process nodes one at a time until one is found with the given `start` kind.
@@ -348,7 +1452,7 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
         }
     }
 
-    fn ident(&mut self) -> Name {
+    fn ident(&mut self) -> (Name, SyntaxContext) {
         match self.iterator.next().unwrap() {
             Step::Token {
                 kind,
@@ -359,6 +1463,12 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
     }
 
     fn name(&mut self) -> Option<Name> {
+        self.hygienic_name().map(|(name, _)| name)
+    }
+
+    /// The next name, along with its syntax context for when it binds a local variable or generic
+    /// parameter.
+    fn hygienic_name(&mut self) -> Option<(Name, SyntaxContext)> {
         self.find(SyntaxKind::NAME)?;
         self.descend(SyntaxKind::NAME);
         let name = self.ident();
@@ -366,35 +1476,2626 @@ impl<'a, I: Iterator<Item = Step<'a>>> Parser<'a, '_, I> {
         Some(name)
     }
 
-    /// Process part of a [`SyntaxKind::BLOCK_EXPR`] node's interior after its [`Step::Enter`].
-    fn block_expr(&mut self, start: Node) {
-        let module = self.tree.scopes.module(self.scope);
-        let scope = self.tree.scopes.push(module, Some(self.scope), start);
-        self.scope_ascend(scope);
+    /// Process the outer attributes at the start of the current node, or the inner attributes at
+    /// the start of a file or module body.
+    ///
+    /// Returns `false` if a `#[cfg]` attribute says the node should be removed. If a `#![cfg]`
+    /// says a module should be, its body is left empty instead.
+    fn outer_attrs(&mut self) -> bool {
+        let &(kind, start) = self.stack.last().unwrap();
+        let body = match kind {
+            SyntaxKind::SOURCE_FILE => true,
+            SyntaxKind::ITEM_LIST => {
+                let &(parent, _) = self.stack.iter().nth_back(1).unwrap();
+                parent == SyntaxKind::MODULE
+            }
+            _ => false,
+        };
+        loop {
+            match self.iterator.peek() {
+                Some(&Step::Enter {
+                    kind: SyntaxKind::ATTR,
+                }) => {}
+                // The inner attributes of an inline module come after its `{`.
+                Some(&Step::Token {
+                    kind: SyntaxKind::L_CURLY,
+                    ..
+                }) if body => {}
+                _ => break,
+            }
+            self.node().unwrap();
+        }
+        if !self.cfg(start, false) {
+            return false;
+        }
+        if body && !self.cfg(start, true) {
+            self.skip();
+            self.exit();
+        }
+        true
     }
 
-    /// Process part of a [`SyntaxKind::MACRO_CALL`] node's interior after its [`Step::Enter`].
-    fn macro_call(&mut self, start: Node) {
-        self.tree.pending_macro_calls.push(start);
+    /// Evaluate the `#[cfg]` attributes from `start` on, outer or `inner` ones, returning `false`
+    /// if any of them is false.
+    fn cfg(&mut self, start: usize, inner: bool) -> bool {
+        for attr in &self.attrs[start..] {
+            if attr.inner != inner || attr.name() != Some(sym::CFG) {
+                continue;
+            }
+            match CfgExpr::parse_attr(&mut self.tree.names, &attr.tokens) {
+                Some(expr) => {
+                    if !self.tree.crates.cfg(self.krate).eval(&expr) {
+                        return false;
+                    }
+                }
+                None => {
+                    let diagnostic = Diagnostic::error(
+                        attr.loc(),
+                        "malformed `cfg` attribute input",
+                    )
+                    .help(
+                        "expected a predicate like `#[cfg(unix)]` or `#[cfg(feature = \"std\")]`",
+                    );
+                    self.tree.diagnostics.push(expanded_from(diagnostic, attr));
+                }
+            }
+        }
+        true
     }
 
-    /// Process part of a [`SyntaxKind::MODULE`] node's interior after its [`Step::Enter`].
-    fn module_decl(&mut self, start: Node) {
-        let body_pointer = self.tree.nodes.push(start);
-        let Some(name) = self.name() else { return };
-        let parent = self.tree.scopes.module(self.scope);
-        let path = self.tree.paths.child(parent, name);
-        match self.find(SyntaxKind::ITEM_LIST) {
-            Some(()) => {
-                let body = self.tree.nodes.next_index();
-                self.tree.nodes.write(body_pointer, body);
-                let scope = self.tree.scopes.push(path, None, body);
-                self.scope_node(scope);
+    /// Discard the rest of the current node, which starts at `start`, along with what was
+    /// already processed of it.
+    fn strip(&mut self, start: Node) {
+        self.skip();
+        let (_, attrs) = self.stack.pop().unwrap();
+        self.attrs.truncate(attrs);
+        self.tree.nodes.truncate(start);
+        // Its attributes were processed before their `#[cfg]` was evaluated, and can contain macro
+        // calls and paths.
+        let calls = &mut self.tree.pending_macro_calls;
+        while calls
+            .last()
+            .is_some_and(|(call, _)| call.node.index() >= start.index())
+        {
+            calls.pop();
+        }
+        self.tree.locals.strip(start);
+        let path_uses = &mut self.tree.path_uses;
+        while path_uses
+            .last()
+            .is_some_and(|path_use| path_use.node.index() >= start.index())
+        {
+            path_uses.pop();
+        }
+    }
+
+    /// Discard the rest of the current node's interior, up to and including its [`Step::Exit`],
+    /// without processing any of it.
+    fn skip(&mut self) {
+        let mut depth: usize = 0;
+        loop {
+            match self.iterator.next().unwrap() {
+                Step::Token { n_input_tokens, .. } => {
+                    self.skip_trivia();
+                    self.offset += usize::from(n_input_tokens);
+                }
+                // A float literal like the `0.1` in `x.0.1` is two field names, and the parser
+                // omits the exit of the inner field access, and also of the outer one unless the
+                // literal ends in a dot.
+                Step::FloatSplit { ends_in_dot } => {
+                    self.skip_trivia();
+                    self.offset += 1;
+                    depth -= if ends_in_dot { 1 } else { 2 };
+                }
+                Step::Enter { .. } => depth += 1,
+                Step::Exit if depth == 0 => break,
+                Step::Exit => depth -= 1,
+                Step::Error { .. } => {}
             }
-            None => self
-                .tree
-                .pending_mods
-                .push(PendingMod { path, node: start }),
         }
     }
+
+    /// The attributes processed so far on the current node.
+    fn attrs(&self) -> &[Attr] {
+        let &(_, start) = self.stack.last().unwrap();
+        &self.attrs[start..]
+    }
+
+    /// Whether the current node has an outer attribute with the given name.
+    fn has_attr(&self, name: Name) -> bool {
+        self.attrs()
+            .iter()
+            .any(|attr| !attr.inner && attr.name() == Some(name))
+    }
+
+    /// Process part of a [`SyntaxKind::ATTR`] node's interior after its [`Step::Enter`].
+    fn attr(&mut self) {
+        let mut tokens = self.record();
+        let inner = tokens.get(1).is_some_and(|t| t.kind == SyntaxKind::BANG);
+        tokens.drain(..tokens.len().min(if inner { 3 } else { 2 }));
+        if tokens.last().is_some_and(|t| t.kind == SyntaxKind::R_BRACK) {
+            tokens.pop();
+        }
+        if tokens.is_empty() {
+            return;
+        }
+        self.push_attr(Attr {
+            inner,
+            tokens,
+            cfg_attr: None,
+        });
+    }
+
+    /// Add an attribute to the current node, replacing any `cfg_attr` by the attributes it expands
+    /// to, recursively.
+    fn push_attr(&mut self, attr: Attr) {
+        if attr.name() != Some(sym::CFG_ATTR) {
+            if attr.inner {
+                self.crate_attr(&attr);
+            }
+            self.attrs.push(attr);
+            return;
+        }
+        let loc = attr.cfg_attr.unwrap_or(attr.loc());
+        let parsed = split_args(&attr.tokens).and_then(|args| {
+            let (predicate, attrs) = args.split_first()?;
+            let expr = CfgExpr::parse(&mut self.tree.names, predicate)?;
+            Some((
+                expr,
+                attrs
+                    .iter()
+                    .map(|tokens| tokens.to_vec())
+                    .collect::<Vec<_>>(),
+            ))
+        });
+        let Some((expr, attrs)) = parsed else {
+            let diagnostic = Diagnostic::error(attr.loc(), "malformed `cfg_attr` attribute input")
+                .help("expected `#[cfg_attr(predicate, attr1, attr2, ...)]`");
+            self.tree.diagnostics.push(expanded_from(diagnostic, &attr));
+            return;
+        };
+        if self.tree.crates.cfg(self.krate).eval(&expr) {
+            for tokens in attrs {
+                self.push_attr(Attr {
+                    inner: attr.inner,
+                    tokens,
+                    cfg_attr: Some(loc),
+                });
+            }
+        }
+    }
+
+    /// Apply an inner attribute if it is at the crate root and affects the whole crate.
+    fn crate_attr(&mut self, attr: &Attr) {
+        let module = self.tree.scopes.module(self.scope);
+        if self.stack.len() != 1 || self.tree.paths.parent(module).is_some() {
+            return;
+        }
+        match attr.name() {
+            Some(sym::NO_STD) => self.tree.crates.set_no_std(self.krate),
+            Some(sym::NO_CORE) => self.tree.crates.set_no_core(self.krate),
+            _ => {}
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::BLOCK_EXPR`] node's interior after its [`Step::Enter`].
+    fn block_expr(&mut self, start: Node) {
+        let (scope, locals, macros) = (self.scope, self.locals, self.macros);
+        let &(parent, _) = self.stack.iter().nth_back(1).unwrap();
+        let kind = match parent {
+            SyntaxKind::FN => ScopeKind::FnBody,
+            _ => ScopeKind::Block,
+        };
+        let breakable = match self.iterator.peek() {
+            Some(Step::Token {
+                kind: SyntaxKind::ASYNC_KW | SyntaxKind::GEN_KW,
+                ..
+            }) => BreakableKind::AsyncBlock,
+            _ => BreakableKind::Block,
+        };
+        self.push_scope(kind);
+        self.push_breakable(start, breakable);
+        self.ascend();
+        self.breakables.pop();
+        self.pop_scopes(scope);
+        (self.locals, self.macros) = (locals, macros);
+    }
+
+    /// Process part of a [`SyntaxKind::CLOSURE_EXPR`] node's interior after its [`Step::Enter`].
+    fn closure_expr(&mut self, start: Node) {
+        let (scope, locals, generics) = (self.scope, self.locals, self.generics);
+        self.push_scope(ScopeKind::Closure);
+        self.push_breakable(start, BreakableKind::Closure);
+        self.ascend();
+        self.breakables.pop();
+        self.pop_scopes(scope);
+        (self.locals, self.generics) = (locals, generics);
+    }
+
+    /// Process part of a [`SyntaxKind::CONST_PARAM`] node's interior after its [`Step::Enter`].
+    fn const_param(&mut self, start: Node) {
+        let Some(name) = self.hygienic_name() else {
+            return;
+        };
+        self.bind_generic(name, start, LocalKind::ConstParam);
+    }
+
+    /// Process part of a [`SyntaxKind::FOR_EXPR`] node's interior after its [`Step::Enter`].
+    fn for_expr(&mut self, start: Node) {
+        let (scope, locals) = (self.scope, self.locals);
+        self.push_breakable(start, BreakableKind::Loop);
+        let bindings = self.pattern();
+        // The iterator expression can't see the names bound by the pattern.
+        self.next_node();
+        self.bind(bindings);
+        self.ascend();
+        self.breakables.pop();
+        self.pop_scopes(scope);
+        self.locals = locals;
+    }
+
+    /// Process part of a [`SyntaxKind::FOR_TYPE`] or [`SyntaxKind::WHERE_PRED`] node's interior
+    /// after its [`Step::Enter`], either of which can start with a `for<...>` binder.
+    fn for_binder(&mut self) {
+        let (scope, generics) = (self.scope, self.generics);
+        self.ascend();
+        self.pop_scopes(scope);
+        self.generics = generics;
+    }
+
+    /// Process part of a [`SyntaxKind::GENERIC_PARAM_LIST`] node's interior after its
+    /// [`Step::Enter`]. Its parameters stay in scope for the rest of the node containing it,
+    /// which restores the scope and generic parameters from before afterward.
+    fn generic_param_list(&mut self) {
+        self.push_scope(ScopeKind::Generics);
+        let (outer, first) = (self.generics, self.tree.locals.next_use());
+        self.ascend();
+        // Bounds can refer to parameters declared after them, as in `<T: Into<U>, U>`.
+        self.tree.locals.forward(first, outer, self.generics);
+    }
+
+    /// Process part of a [`SyntaxKind::IDENT_PAT`] node's interior after its [`Step::Enter`].
+    fn ident_pat(&mut self, start: Node) {
+        if self.bindings.is_none() {
+            return;
+        }
+        let Some(name) = self.hygienic_name() else {
+            return;
+        };
+        self.bindings.as_mut().unwrap().push((name, start));
+    }
+
+    /// Process part of a [`SyntaxKind::IF_EXPR`] node's interior after its [`Step::Enter`].
+    fn if_expr(&mut self) {
+        let locals = self.locals;
+        // Names bound by `if let` are only in scope in the first branch.
+        if let Some(()) = self.find(SyntaxKind::BLOCK_EXPR) {
+            self.node().unwrap();
+        }
+        self.locals = locals;
+        self.ascend();
+    }
+
+    /// Process part of a [`SyntaxKind::IMPL`] node's interior after its [`Step::Enter`].
+    fn impl_block(&mut self) {
+        let (scope, generics) = (self.scope, self.generics);
+        self.push_scope(ScopeKind::Impl);
+        // The first type in the header is a trait if `for` follows it.
+        let mut first = None;
+        loop {
+            match self.iterator.peek() {
+                Some(Step::Enter {
+                    kind: SyntaxKind::PATH_TYPE,
+                }) if first.is_none() => {
+                    first = Some(self.tree.nodes.next_index());
+                    self.node().unwrap();
+                }
+                Some(Step::Token {
+                    kind: SyntaxKind::FOR_KW,
+                    ..
+                }) => {
+                    if let Some(first) = first {
+                        self.tree.locals.set_trait(first);
+                    }
+                    self.node().unwrap();
+                }
+                Some(Step::Enter {
+                    kind: SyntaxKind::ASSOC_ITEM_LIST,
+                })
+                | Some(Step::Exit)
+                | None => break,
+                _ => self.node().unwrap(),
+            }
+        }
+        self.ascend();
+        self.pop_scopes(scope);
+        self.generics = generics;
+    }
+
+    /// Process part of a [`SyntaxKind::BREAK_EXPR`] or [`SyntaxKind::CONTINUE_EXPR`] node's
+    /// interior after its [`Step::Enter`], recording the loop or labeled block it refers to.
+    fn jump(&mut self, kind: SyntaxKind, start: Node) {
+        let tokens = self.record();
+        let Some(keyword) = tokens.first() else {
+            return;
+        };
+        let label = tokens
+            .get(1)
+            .filter(|token| token.kind == SyntaxKind::LIFETIME_IDENT);
+        let is_break = kind == SyntaxKind::BREAK_EXPR;
+        let word = if is_break { "break" } else { "continue" };
+        let mut target = None;
+        let mut barrier = None;
+        // The labeled block an unlabeled `break` or `continue` would have to leave, if any.
+        let mut labeled_block = None;
+        // The label's syntax context outside of the `macro_rules!` definitions passed so far.
+        let mut ctx = label.map(|label| label.ctx);
+        let mut next = self.locals;
+        let mut rest = self.breakables.iter().rev();
+        for breakable in rest.by_ref() {
+            if let Some(ctx) = &mut ctx {
+                let locals = &self.tree.locals;
+                *ctx = locals.leave(&self.tree.contexts, next, breakable.locals, *ctx);
+            }
+            next = breakable.locals;
+            match (breakable.kind, label) {
+                (BreakableKind::Closure | BreakableKind::AsyncBlock, _) => {
+                    barrier = Some(breakable);
+                    break;
+                }
+                (_, Some(label))
+                    if breakable
+                        .label
+                        .is_some_and(|(n, c, _)| (n, Some(c)) == (label.text, ctx)) =>
+                {
+                    target = Some(breakable);
+                    break;
+                }
+                (BreakableKind::Loop, None) => {
+                    target = Some(breakable);
+                    break;
+                }
+                (BreakableKind::Block, None) if breakable.label.is_some() => {
+                    labeled_block = labeled_block.or(Some(breakable));
+                }
+                _ => {}
+            }
+        }
+        let diagnostic = if let Some(label) = label {
+            let name = self.tree.names.get(label.text);
+            match target {
+                Some(target) if !is_break && target.kind == BreakableKind::Block => {
+                    let (_, _, decl) = target.label.unwrap();
+                    Diagnostic::error(label.loc, "`continue` pointing to a labeled block")
+                        .code("E0696")
+                        .label(label.loc, "labeled blocks cannot be `continue`'d")
+                        .label(decl, "labeled block the `continue` points to")
+                }
+                Some(target) => {
+                    self.tree.targets.insert(start, target.node);
+                    return;
+                }
+                None => {
+                    let outer = barrier.and_then(|_| {
+                        rest.find_map(|b| b.label.filter(|&(n, ..)| n == label.text))
+                    });
+                    match outer {
+                        Some((_, _, decl)) => Diagnostic::error(
+                            label.loc,
+                            format!("use of unreachable label `{name}`"),
+                        )
+                        .code("E0767")
+                        .label(label.loc, format!("unreachable label `{name}`"))
+                        .label(decl, "unreachable label defined here")
+                        .note(
+                            "labels are unreachable through functions, closures, async blocks \
+                             and modules",
+                        ),
+                        None => Diagnostic::error(
+                            label.loc,
+                            format!("use of undeclared label `{name}`"),
+                        )
+                        .code("E0426")
+                        .label(label.loc, format!("undeclared label `{name}`")),
+                    }
+                }
+            }
+        } else if labeled_block.is_some() {
+            Diagnostic::error(
+                keyword.loc,
+                format!("unlabeled `{word}` inside of a labeled block"),
+            )
+            .code("E0695")
+            .label(
+                keyword.loc,
+                format!(
+                    "`{word}` statements that would diverge to or through a labeled block need \
+                     to bear a label",
+                ),
+            )
+        } else if let Some(target) = target {
+            self.tree.targets.insert(start, target.node);
+            return;
+        } else if let Some(barrier) = barrier {
+            let (inside, enclosing) = match barrier.kind {
+                BreakableKind::Closure => ("inside of a closure", "enclosing closure"),
+                _ => ("inside `async` block", "enclosing `async` block"),
+            };
+            let loc = self.tree.nodes.loc(barrier.node);
+            Diagnostic::error(keyword.loc, format!("`{word}` {inside}"))
+                .code("E0267")
+                .label(keyword.loc, format!("cannot `{word}` {inside}"))
+                .label(loc, enclosing)
+        } else {
+            let outside = match is_break {
+                true => "outside of a loop or labeled block",
+                false => "outside of a loop",
+            };
+            Diagnostic::error(keyword.loc, format!("`{word}` {outside}"))
+                .code("E0268")
+                .label(keyword.loc, format!("cannot `{word}` {outside}"))
+        };
+        self.tree.diagnostics.push(diagnostic);
+    }
+
+    /// Process part of a [`SyntaxKind::LABEL`] node's interior after its [`Step::Enter`]. The
+    /// label is in scope for the rest of the loop or block containing it, which leaves that scope
+    /// afterward.
+    fn label(&mut self) {
+        let tokens = self.record();
+        let Some(&token) = tokens.first() else { return };
+        self.push_scope(ScopeKind::Label(token.text));
+        let [outer @ .., breakable] = &mut self.breakables[..] else {
+            return;
+        };
+        breakable.label = Some((token.text, token.ctx, token.loc));
+        let shadowed = outer.iter().rev().find_map(|b| {
+            b.label
+                .filter(|&(name, ctx, _)| (name, ctx) == (token.text, token.ctx))
+        });
+        if let Some((_, _, decl)) = shadowed {
+            let name = self.tree.names.get(token.text);
+            self.tree.diagnostics.push(
+                Diagnostic::warning(
+                    token.loc,
+                    format!("label name `{name}` shadows a label name that is already in scope"),
+                )
+                .label(decl, "first declared here")
+                .label(token.loc, format!("label `{name}` already in scope")),
+            );
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::LET_STMT`] or [`SyntaxKind::LET_EXPR`] node's interior
+    /// after its [`Step::Enter`]. The names it binds stay in scope afterward.
+    fn let_binding(&mut self) {
+        let bindings = self.pattern();
+        // Neither the initializer nor the `else` block of `let`-`else` can see the bindings.
+        self.ascend();
+        self.bind(bindings);
+    }
+
+    /// Process part of a [`SyntaxKind::LIFETIME`] node's interior after its [`Step::Enter`].
+    fn lifetime(&mut self, start: Node) {
+        let tokens = self.record();
+        let &(parent, _) = self.stack.last().unwrap();
+        let [token] = tokens[..] else { return };
+        match parent {
+            SyntaxKind::LIFETIME_PARAM => {
+                self.bind_generic((token.text, token.ctx), start, LocalKind::LifetimeParam)
+            }
+            // Labels are resolved separately.
+            SyntaxKind::LABEL | SyntaxKind::BREAK_EXPR | SyntaxKind::CONTINUE_EXPR => {}
+            _ if matches!(token.text, sym::STATIC_LIFETIME | sym::UNDERSCORE_LIFETIME) => {}
+            _ => self.push_use(start, UseKind::Lifetime, (token.text, token.ctx), token.loc),
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::LOOP_EXPR`] node's interior after its [`Step::Enter`].
+    fn loop_expr(&mut self, start: Node) {
+        let scope = self.scope;
+        self.push_breakable(start, BreakableKind::Loop);
+        self.ascend();
+        self.breakables.pop();
+        self.pop_scopes(scope);
+    }
+
+    /// Process part of a [`SyntaxKind::MATCH_ARM`] node's interior after its [`Step::Enter`].
+    fn match_arm(&mut self) {
+        let locals = self.locals;
+        let bindings = self.pattern();
+        self.bind(bindings);
+        self.ascend();
+        self.locals = locals;
+    }
+
+    /// Process part of a [`SyntaxKind::PARAM`] node's interior after its [`Step::Enter`].
+    fn param(&mut self) {
+        // Parameters of function pointer types don't bind anything.
+        let &(owner, _) = self.stack.iter().nth_back(2).unwrap();
+        if !matches!(owner, SyntaxKind::FN | SyntaxKind::CLOSURE_EXPR) {
+            return;
+        }
+        let bindings = self.pattern();
+        self.bind(bindings);
+    }
+
+    /// Process part of a [`SyntaxKind::PATH_EXPR`] node's interior after its [`Step::Enter`].
+    fn path_expr(&mut self, start: Node) {
+        let tokens = self.record();
+        self.push_path(start, &tokens, Namespace::Value);
+        let [token] = tokens[..] else { return };
+        if token.kind != SyntaxKind::IDENT {
+            return;
+        }
+        self.push_use(start, UseKind::Value, (token.text, token.ctx), token.loc);
+    }
+
+    /// Process a [`SyntaxKind::PATH_TYPE`] node's interior after its [`Step::Enter`].
+    fn path_type(&mut self, start: Node) {
+        let &(parent, _) = self.stack.iter().nth_back(1).unwrap();
+        let kind = match parent {
+            SyntaxKind::TYPE_BOUND => UseKind::Trait,
+            SyntaxKind::TYPE_ARG => UseKind::GenericArg,
+            _ => UseKind::Type,
+        };
+        let outer = self.recording.replace(Vec::new());
+        let height = self.stack.len();
+        self.first_segment(start, kind);
+        while self.stack.len() >= height {
+            self.ascend();
+        }
+        let tokens = self.finish_recording(outer);
+        self.push_path(start, &tokens, Namespace::Type);
+    }
+
+    /// Process part of a [`SyntaxKind::PATH_TYPE`] node's interior, up to the name of its first
+    /// segment.
+    fn first_segment(&mut self, start: Node, mut kind: UseKind) {
+        let Some(()) = self.find(SyntaxKind::PATH) else {
+            return;
+        };
+        // The first segment is in the innermost path.
+        self.descend(SyntaxKind::PATH);
+        while let Some(Step::Enter {
+            kind: SyntaxKind::PATH,
+        }) = self.iterator.peek()
+        {
+            self.descend(SyntaxKind::PATH);
+            kind = UseKind::Prefix;
+        }
+        let Some(Step::Enter {
+            kind: SyntaxKind::PATH_SEGMENT,
+        }) = self.iterator.peek()
+        else {
+            return;
+        };
+        self.descend(SyntaxKind::PATH_SEGMENT);
+        let Some(Step::Enter {
+            kind: SyntaxKind::NAME_REF,
+        }) = self.iterator.peek()
+        else {
+            return;
+        };
+        self.descend(SyntaxKind::NAME_REF);
+        let Some(&Step::Token {
+            kind: SyntaxKind::IDENT,
+            ..
+        }) = self.iterator.peek()
+        else {
+            return;
+        };
+        let name = self.ident();
+        let loc = self.tree.nodes.loc(start);
+        self.push_use(start, kind, name, loc);
+    }
+
+    /// Record a path of more than one segment for its privacy to be checked. Paths with generic
+    /// arguments aren't resolved.
+    fn push_path(&mut self, node: Node, tokens: &[Token], ns: Namespace) {
+        let leaf =
+            import::parse_use_tree(&self.tree.contexts, tokens).and_then(|mut leaves| leaves.pop());
+        if let Some((path, ImportKind::Single(_))) = leaf {
+            if path.len() > 1 {
+                self.tree.path_uses.push(PathUse {
+                    node,
+                    scope: self.scope,
+                    path,
+                    ns,
+                });
+            }
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::RECORD_EXPR`] or [`SyntaxKind::RECORD_PAT`] node's interior
+    /// after its [`Step::Enter`].
+    fn record_expr(&mut self) {
+        let path = match self.iterator.peek() {
+            Some(Step::Enter {
+                kind: SyntaxKind::PATH,
+            }) => {
+                let outer = self.recording.replace(Vec::new());
+                self.node().unwrap();
+                let tokens = self.finish_recording(outer);
+                // Paths with generic arguments aren't resolved.
+                match import::parse_use_tree(&self.tree.contexts, &tokens)
+                    .and_then(|mut leaves| leaves.pop())
+                {
+                    Some((path, ImportKind::Single(_))) => Some(path),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let outer = self.fields.replace(Vec::new());
+        self.ascend();
+        let fields = mem::replace(&mut self.fields, outer).unwrap();
+        if let Some(path) = path {
+            if !fields.is_empty() {
+                self.tree.records.push(RecordUse {
+                    scope: self.scope,
+                    path,
+                    fields,
+                });
+            }
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::RECORD_EXPR_FIELD`] or [`SyntaxKind::RECORD_PAT_FIELD`]
+    /// node's interior after its [`Step::Enter`].
+    fn record_expr_field(&mut self) {
+        let tokens = self.record();
+        // The field is named before a `:`, or by a shorthand like `ref mut x`.
+        let token = tokens.iter().find(|token| {
+            !matches!(
+                token.kind,
+                SyntaxKind::BOX_KW | SyntaxKind::REF_KW | SyntaxKind::MUT_KW
+            )
+        });
+        // Tuple fields like `0: x` have no name.
+        if let (Some(token), Some(fields)) = (token, &mut self.fields) {
+            if token.kind == SyntaxKind::IDENT {
+                fields.push((token.text, token.loc));
+            }
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::TYPE_PARAM`] node's interior after its [`Step::Enter`].
+    fn type_param(&mut self, start: Node) {
+        let Some(name) = self.hygienic_name() else {
+            return;
+        };
+        self.bind_generic(name, start, LocalKind::TypeParam);
+    }
+
+    /// Process part of a [`SyntaxKind::WHILE_EXPR`] node's interior after its [`Step::Enter`].
+    fn while_expr(&mut self, start: Node) {
+        let (scope, locals) = (self.scope, self.locals);
+        self.push_breakable(start, BreakableKind::Loop);
+        self.ascend();
+        self.breakables.pop();
+        self.pop_scopes(scope);
+        self.locals = locals;
+    }
+
+    /// Process the rest of the current node, returning its tokens.
+    fn record(&mut self) -> Vec<Token> {
+        let outer = self.recording.replace(Vec::new());
+        self.ascend();
+        self.finish_recording(outer)
+    }
+
+    /// Like [`Self::record`], but with doc comments turned into the attributes they stand for, as
+    /// they are in the token trees that macros are given.
+    fn record_with_docs(&mut self) -> Vec<Token> {
+        self.docs = true;
+        let tokens = self.record();
+        self.docs = false;
+        tokens
+    }
+
+    /// Return the tokens recorded since `outer` was replaced, which are also added to it, and carry
+    /// on recording for it if it was recording.
+    fn finish_recording(&mut self, outer: Option<Vec<Token>>) -> Vec<Token> {
+        let tokens = mem::replace(&mut self.recording, outer).unwrap();
+        if let Some(outer) = &mut self.recording {
+            outer.extend(&tokens);
+        }
+        tokens
+    }
+
+    /// Enter a loop, block or closure, whose label if any is set by [`Self::label`].
+    fn push_breakable(&mut self, node: Node, kind: BreakableKind) {
+        self.breakables.push(Breakable {
+            node,
+            kind,
+            label: None,
+            locals: self.locals,
+        });
+    }
+
+    /// Record a use of a name to be resolved later. If it is in a field, it counts as using the
+    /// generic parameter it refers to, if any.
+    fn push_use(
+        &mut self,
+        node: Node,
+        kind: UseKind,
+        (name, ctx): (Name, SyntaxContext),
+        loc: SourceLoc,
+    ) {
+        let in_field = self
+            .stack
+            .iter()
+            .any(|&(kind, _)| matches!(kind, SyntaxKind::RECORD_FIELD | SyntaxKind::TUPLE_FIELD));
+        if in_field {
+            let locals = &mut self.tree.locals;
+            locals.mark_used(&self.tree.contexts, self.generics, kind, (name, ctx));
+        }
+        self.tree.locals.push_use(LocalUse {
+            node,
+            kind,
+            name,
+            ctx,
+            loc,
+            scope: self.scope,
+            locals: self.locals,
+            generics: self.generics,
+        });
+    }
+
+    /// Bring a generic parameter into scope.
+    fn bind_generic(&mut self, name: (Name, SyntaxContext), node: Node, kind: LocalKind) {
+        let scope = self.scope;
+        let generics = self
+            .tree
+            .locals
+            .bind(self.generics, name, node, scope, kind);
+        self.generics = Some(generics);
+    }
+
+    /// Process nodes up to and including the next pattern, returning the names it binds.
+    fn pattern(&mut self) -> Vec<((Name, SyntaxContext), Node)> {
+        let outer = self.bindings.replace(Vec::new());
+        loop {
+            match self.iterator.peek() {
+                Some(&Step::Enter { kind }) if is_pattern(kind) => {
+                    self.node().unwrap();
+                    break;
+                }
+                Some(Step::Exit) | None => break,
+                _ => self.node().unwrap(),
+            }
+        }
+        mem::replace(&mut self.bindings, outer).unwrap()
+    }
+
+    /// Bring the names bound by a pattern into scope.
+    fn bind(&mut self, bindings: Vec<((Name, SyntaxContext), Node)>) {
+        for (name, node) in bindings {
+            let local =
+                self.tree
+                    .locals
+                    .bind(self.locals, name, node, self.scope, LocalKind::Variable);
+            self.locals = Some(local);
+        }
+    }
+
+    /// Process tokens up to and including the next node, if there is one.
+    fn next_node(&mut self) {
+        loop {
+            match self.iterator.peek() {
+                Some(Step::Enter { .. }) => {
+                    self.node().unwrap();
+                    return;
+                }
+                Some(Step::Exit) | None => return,
+                _ => self.node().unwrap(),
+            }
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::EXTERN_CRATE`] node's interior after its [`Step::Enter`].
+    fn extern_crate(&mut self, start: Node) {
+        let vis = self.visibility().unwrap_or(self.private());
+        let Some(()) = self.find(SyntaxKind::NAME_REF) else {
+            return;
+        };
+        let name_ref = self.tree.nodes.next_index();
+        self.descend(SyntaxKind::NAME_REF);
+        let name = match self.iterator.next().unwrap() {
+            Step::Token {
+                kind,
+                n_input_tokens,
+            } => self.token(kind, n_input_tokens).map(|(name, _)| name),
+            _ => panic!(),
+        };
+        self.ascend();
+        let rename = match self.find(SyntaxKind::RENAME) {
+            Some(()) => {
+                self.descend(SyntaxKind::RENAME);
+                // `None` for `extern crate foo as _;`, which defines no name.
+                let rename = self.name();
+                self.ascend();
+                Some(rename)
+            }
+            None => None,
+        };
+        if let Some(name) = rename.unwrap_or(name) {
+            let kind = ItemKind::ExternCrate;
+            let parent = Parent::Scope(self.scope);
+            let item = Item {
+                kind,
+                node: start,
+                vis,
+            };
+            self.define(parent, Namespace::Type, name, item);
+        }
+        let root = match name {
+            // `extern crate self as foo;`
+            None => self.tree.paths.root_of(self.tree.scopes.module(self.scope)),
+            Some(name) => match self.tree.crates.extern_crate(self.krate, name) {
+                Some(dep) => {
+                    self.macro_use_crate(dep);
+                    match dep {
+                        Extern::Crate(dep) => self.tree.crates.root(dep),
+                        Extern::Opaque => return,
+                    }
+                }
+                None => {
+                    let loc = self.tree.nodes.loc(name_ref);
+                    let name = self.tree.names.get(name);
+                    self.tree.diagnostics.push(
+                        Diagnostic::error(loc, format!("can't find crate for `{name}`"))
+                            .code("E0463"),
+                    );
+                    return;
+                }
+            },
+        };
+        self.tree.extern_crates.insert(start, root);
+    }
+
+    /// Import the macros of a crate loaded by an `extern crate` item with `#[macro_use]`, which
+    /// can list the names of the macros to import.
+    fn macro_use_crate(&mut self, dep: Extern) {
+        let Some(attr) = self
+            .attrs()
+            .iter()
+            .find(|attr| !attr.inner && attr.name() == Some(sym::MACRO_USE))
+        else {
+            return;
+        };
+        let loc = attr.loc();
+        let names = split_args(&attr.tokens).map(|args| {
+            args.iter()
+                .filter_map(|arg| match arg {
+                    [ident] if ident.kind == SyntaxKind::IDENT => Some(ident.text),
+                    _ => None,
+                })
+                .collect()
+        });
+        let module = self.tree.scopes.module(self.scope);
+        if self.tree.paths.parent(module).is_some() || self.scope != self.tree.modules[&module] {
+            self.tree.diagnostics.push(
+                Diagnostic::error(
+                    loc,
+                    "an `extern crate` loading macros must be at the crate root",
+                )
+                .code("E0468"),
+            );
+            return;
+        }
+        self.tree.macros.macro_use_crate(self.krate, dep, names);
+    }
+
+    /// The visibility of private items in the current scope.
+    fn private(&self) -> Visibility {
+        Visibility::Restricted(self.tree.scopes.module(self.scope))
+    }
+
+    /// Process the [`SyntaxKind::VISIBILITY`] node at the start of the current node, if any.
+    fn visibility(&mut self) -> Option<Visibility> {
+        let Some(&Step::Enter {
+            kind: SyntaxKind::VISIBILITY,
+        }) = self.iterator.peek()
+        else {
+            return None;
+        };
+        let outer = self.recording.replace(Vec::new());
+        self.node().unwrap();
+        let tokens = self.finish_recording(outer);
+        Some(self.lower_visibility(&tokens))
+    }
+
+    /// Lower a visibility like `pub(in crate::foo)` to the module it is restricted to, reporting
+    /// paths that don't name an ancestor of the current module. Those are treated as `pub`, to
+    /// avoid spurious privacy errors.
+    fn lower_visibility(&mut self, tokens: &[Token]) -> Visibility {
+        let module = self.tree.scopes.module(self.scope);
+        let root = self.tree.paths.root_of(module);
+        let path = match tokens {
+            [token] if token.kind == SyntaxKind::CRATE_KW => return Visibility::Restricted(root),
+            [_, paren, inner @ .., _] if paren.kind == SyntaxKind::L_PAREN => match inner {
+                [in_kw, path @ ..] if in_kw.kind == SyntaxKind::IN_KW => path,
+                _ => inner,
+            },
+            _ => return Visibility::Public,
+        };
+        let edition = self.tree.crates.edition(self.krate);
+        let (mut current, path) = match path {
+            // A leading `::` starts at the crate root in the 2015 edition, and at an extern crate
+            // after it, which is never an ancestor.
+            [colon2, rest @ ..] if colon2.kind == SyntaxKind::COLON2 => {
+                if edition != Edition::Edition2015 {
+                    self.not_ancestor(colon2.loc);
+                    return Visibility::Public;
+                }
+                (root, rest)
+            }
+            _ => (module, path),
+        };
+        for (i, token) in path.iter().enumerate() {
+            current = match token.kind {
+                SyntaxKind::COLON2 => continue,
+                SyntaxKind::CRATE_KW if i == 0 => root,
+                SyntaxKind::SELF_KW if i == 0 => module,
+                SyntaxKind::SUPER_KW
+                    if i == 0
+                        || matches!(
+                            path[i - 2].kind,
+                            SyntaxKind::SELF_KW | SyntaxKind::SUPER_KW
+                        ) =>
+                {
+                    match self.tree.paths.parent(current) {
+                        Some(parent) => parent,
+                        None => {
+                            self.visibility_error(
+                                token.loc,
+                                "there are too many leading `super` keywords",
+                            );
+                            return Visibility::Public;
+                        }
+                    }
+                }
+                SyntaxKind::IDENT if i == 0 && edition != Edition::Edition2015 => {
+                    let name = self.tree.names.get(token.text);
+                    self.tree.diagnostics.push(
+                        Diagnostic::error(
+                            token.loc,
+                            "relative paths are not supported in visibilities in 2018 edition or later",
+                        )
+                        .help(format!("try `crate::{name}`")),
+                    );
+                    return Visibility::Public;
+                }
+                SyntaxKind::IDENT => {
+                    let parent = if i == 0 { root } else { current };
+                    match self.tree.paths.get(parent, token.text) {
+                        Some(path) => path,
+                        None => {
+                            let name = self.tree.names.get(token.text);
+                            self.visibility_error(token.loc, format!("could not find `{name}`"));
+                            return Visibility::Public;
+                        }
+                    }
+                }
+                SyntaxKind::CRATE_KW | SyntaxKind::SELF_KW | SyntaxKind::SUPER_KW => {
+                    let keyword = self.tree.names.get(token.text);
+                    self.visibility_error(
+                        token.loc,
+                        format!("`{keyword}` in paths can only be used in start position"),
+                    );
+                    return Visibility::Public;
+                }
+                SyntaxKind::SELF_TYPE_KW => {
+                    self.visibility_error(token.loc, "`Self` is not a module");
+                    return Visibility::Public;
+                }
+                // Syntax errors have already been reported by the parser.
+                _ => return Visibility::Public,
+            };
+        }
+        if !self.tree.paths.is_ancestor(current, module) {
+            self.not_ancestor(path[0].loc);
+            return Visibility::Public;
+        }
+        Visibility::Restricted(current)
+    }
+
+    fn visibility_error(&mut self, loc: SourceLoc, message: impl Into<String>) {
+        self.tree.diagnostics.push(
+            Diagnostic::error(loc, format!("failed to resolve: {}", message.into())).code("E0433"),
+        );
+    }
+
+    fn not_ancestor(&mut self, loc: SourceLoc) {
+        self.tree.diagnostics.push(
+            Diagnostic::error(
+                loc,
+                "visibilities can only be restricted to ancestor modules",
+            )
+            .code("E0742"),
+        );
+    }
+
+    /// Whether the current node is directly inside a module, block or `extern` block.
+    fn in_item_list(&self) -> bool {
+        let &(parent, _) = self.stack.iter().nth_back(1).unwrap();
+        match parent {
+            SyntaxKind::SOURCE_FILE
+            | SyntaxKind::ITEM_LIST
+            | SyntaxKind::STMT_LIST
+            | SyntaxKind::EXTERN_ITEM_LIST
+            | SyntaxKind::MACRO_STMTS => true,
+            // Expansions among associated items are parsed as items too.
+            SyntaxKind::MACRO_ITEMS => !self.assoc,
+            _ => false,
+        }
+    }
+
+    /// Process part of a named item's interior after its [`Step::Enter`], other than a module or
+    /// `extern crate` item.
+    fn item(&mut self, kind: SyntaxKind, start: Node) {
+        let (scope, locals, generics) = (self.scope, self.locals, self.generics);
+        // Derives are expanded from the tokens of the item, without its outer attributes.
+        let derives = match kind {
+            SyntaxKind::ENUM | SyntaxKind::STRUCT | SyntaxKind::UNION if self.in_item_list() => {
+                self.derives()
+            }
+            _ => Vec::new(),
+        };
+        let recording = (!derives.is_empty()).then(|| self.recording.replace(Vec::new()));
+        // Items can't use the local variables of enclosing functions, and only associated items
+        // can use the generic parameters of the enclosing impl or trait.
+        self.locals = None;
+        if self.in_item_list() {
+            self.generics = None;
+        }
+        // Nor can `break` or `continue` leave an item.
+        let breakables = mem::take(&mut self.breakables);
+        let outer = self.generics;
+        let height = self.stack.len();
+        let name = self.define_item(kind, start);
+        if self.stack.len() == height {
+            if kind == SyntaxKind::TRAIT {
+                self.push_scope(ScopeKind::Trait);
+            }
+            self.ascend();
+        }
+        if matches!(
+            kind,
+            SyntaxKind::ENUM | SyntaxKind::STRUCT | SyntaxKind::UNION
+        ) {
+            self.unused_params(outer);
+        }
+        self.pop_scopes(scope);
+        (self.locals, self.generics) = (locals, generics);
+        self.breakables = breakables;
+        if let Some(outer) = recording {
+            let tokens = self.finish_recording(outer);
+            for (path, path_tokens) in derives {
+                self.derive(path, &path_tokens, &tokens);
+            }
+        }
+        if let (SyntaxKind::MACRO_RULES, Some(name), false) =
+            (kind, name, self.breakables.is_empty())
+        {
+            // Identifiers from the expansions of a `macro_rules!` definition in a body can see the
+            // local variables and labels before it.
+            let (ident, scope) = ((name, SyntaxContext::ROOT), self.scope);
+            let local = self
+                .tree
+                .locals
+                .bind(self.locals, ident, start, scope, LocalKind::Macro);
+            self.locals = Some(local);
+        }
+    }
+
+    /// The paths of the derives that the `#[derive]` attributes of the current node apply, along
+    /// with their tokens.
+    fn derives(&mut self) -> Vec<DerivePath> {
+        let &(_, start) = self.stack.last().unwrap();
+        let mut derives = Vec::new();
+        for attr in &self.attrs[start..] {
+            if attr.inner || attr.name() != Some(sym::DERIVE) {
+                continue;
+            }
+            let Some(args) = split_args(&attr.tokens) else {
+                let diagnostic =
+                    Diagnostic::error(attr.loc(), "malformed `derive` attribute input")
+                        .help("expected `#[derive(Trait1, Trait2, ...)]`");
+                self.tree.diagnostics.push(expanded_from(diagnostic, attr));
+                continue;
+            };
+            for arg in args {
+                let mut tokens: Vec<Token> = Vec::new();
+                for &token in arg {
+                    match tokens.last_mut() {
+                        // Inside the attribute's token tree, each `::` is two `:` tokens.
+                        Some(prev)
+                            if prev.kind == SyntaxKind::COLON
+                                && token.kind == SyntaxKind::COLON
+                                && prev.loc.plus(1) == token.loc =>
+                        {
+                            prev.kind = SyntaxKind::COLON2;
+                            prev.text = self.tree.names.make("::");
+                        }
+                        _ => tokens.push(token),
+                    }
+                }
+                // A path is a `use` tree without any groups, globs or renames.
+                let leaf = tokens
+                    .iter()
+                    .all(|token| !matches!(token.kind, SyntaxKind::L_CURLY | SyntaxKind::STAR))
+                    .then(|| import::parse_use_tree(&self.tree.contexts, &tokens))
+                    .flatten()
+                    .and_then(|mut leaves| leaves.pop());
+                match leaf {
+                    Some((path, ImportKind::Single(Some(name)))) if matches!(path.last(), Some(&(Segment::Name(last), _)) if last == name) => {
+                        derives.push((path, tokens))
+                    }
+                    _ => self
+                        .tree
+                        .diagnostics
+                        .push(Diagnostic::error(arg[0].loc, "expected path to a trait")),
+                }
+            }
+        }
+        derives
+    }
+
+    /// Add a [`SyntaxKind::MACRO_CALL`] with the tokens of a derive's path after the item that it
+    /// is applied to, so that its expansion follows the item like that of a macro call there.
+    fn derive(&mut self, path: Vec<(Segment, SourceLoc)>, path_tokens: &[Token], item: &[Token]) {
+        let start = self.enter(SyntaxKind::MACRO_CALL);
+        for token in path_tokens {
+            let text = self.tree.names.get(token.text).to_owned();
+            let span = (token.loc, token.ctx);
+            push_token(self.tree, &mut self.recording, token.kind, &text, span);
+        }
+        self.exit();
+        let loc = path_tokens[0].loc;
+        let call = MacroCall {
+            node: start,
+            kind: MacroKind::Derive,
+            path,
+            scope: self.scope,
+            textual: self.macros,
+        };
+        let site = CallSite {
+            root: SyntaxKind::MACRO_ITEMS,
+            // Derives only add `impl` blocks, which don't define any names.
+            item: false,
+            assoc: false,
+            input: item.to_vec(),
+            close: loc,
+            locals: None,
+            generics: None,
+            breakables: Vec::new(),
+            depth: self.depth,
+            call_site: self.call_site.unwrap_or(loc),
+        };
+        self.tree.pending_macro_calls.push((call, site));
+    }
+
+    /// Report the type and lifetime parameters of a struct, enum or union that none of its fields
+    /// refer to, which are those bound after `outer`.
+    fn unused_params(&mut self, outer: Option<LocalId>) {
+        for (kind, name, node) in self.tree.locals.unused(outer, self.generics) {
+            let loc = self.tree.nodes.loc(node);
+            let name = self.tree.names.get(name);
+            let descr = match kind {
+                LocalKind::LifetimeParam => "lifetime",
+                _ => "type",
+            };
+            self.tree.diagnostics.push(
+                Diagnostic::error(loc, format!("{descr} parameter `{name}` is never used"))
+                    .code("E0392")
+                    .label(loc, format!("unused {descr} parameter"))
+                    .help(format!(
+                        "consider removing `{name}`, referring to it in a field, or using a \
+                         marker such as `PhantomData`",
+                    )),
+            );
+        }
+    }
+
+    /// Define the names of an item in the enclosing scope, returning the item's name.
+    fn define_item(&mut self, kind: SyntaxKind, start: Node) -> Option<Name> {
+        // Associated items are not defined in the enclosing scope.
+        if !self.in_item_list() {
+            return None;
+        }
+        let vis = self.visibility().unwrap_or(self.private());
+        let name = self.name()?;
+        let (kind, ns) = match kind {
+            SyntaxKind::CONST => (ItemKind::Const, Namespace::Value),
+            SyntaxKind::ENUM => (ItemKind::Enum, Namespace::Type),
+            SyntaxKind::FN => (ItemKind::Fn, Namespace::Value),
+            SyntaxKind::MACRO_DEF => (ItemKind::Macro, Namespace::Macro),
+            SyntaxKind::MACRO_RULES => (ItemKind::MacroRules, Namespace::Macro),
+            SyntaxKind::STATIC => (ItemKind::Static, Namespace::Value),
+            SyntaxKind::STRUCT => (ItemKind::Struct, Namespace::Type),
+            SyntaxKind::TRAIT | SyntaxKind::TRAIT_ALIAS => (ItemKind::Trait, Namespace::Type),
+            SyntaxKind::TYPE_ALIAS => (ItemKind::TypeAlias, Namespace::Type),
+            SyntaxKind::UNION => (ItemKind::Union, Namespace::Type),
+            _ => unreachable!(),
+        };
+        let item = Item {
+            kind,
+            node: start,
+            vis,
+        };
+        let parent = Parent::Scope(self.scope);
+        if kind == ItemKind::MacroRules {
+            self.macro_rules(name, item);
+            return Some(name);
+        }
+        self.define(parent, ns, name, item);
+        match kind {
+            ItemKind::Macro => {
+                self.builtin_macro(name, start);
+            }
+            ItemKind::Struct | ItemKind::Union => {
+                let constructor = kind == ItemKind::Struct && self.constructor();
+                self.fields(start, self.private());
+                if constructor {
+                    // The constructor of a tuple struct is only as visible as its fields.
+                    let paths = &self.tree.paths;
+                    let fields = self.tree.items.fields(start);
+                    let vis = fields
+                        .iter()
+                        .fold(vis, |vis, field| vis.min(field.vis, paths));
+                    self.define(parent, Namespace::Value, name, Item { vis, ..item });
+                }
+            }
+            ItemKind::Fn => {
+                // Procedural macros are functions, but are used by name in the macro namespace.
+                let proc_macro = self.attrs().iter().find_map(|attr| match attr.name()? {
+                    sym::PROC_MACRO | sym::PROC_MACRO_ATTRIBUTE => Some(name),
+                    sym::PROC_MACRO_DERIVE => match attr.tokens.get(1..3)? {
+                        [paren, ident]
+                            if paren.kind == SyntaxKind::L_PAREN
+                                && ident.kind == SyntaxKind::IDENT =>
+                        {
+                            Some(ident.text)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                });
+                if let Some(proc_macro) = proc_macro {
+                    let kind = ItemKind::Macro;
+                    self.define(parent, Namespace::Macro, proc_macro, Item { kind, ..item });
+                }
+            }
+            ItemKind::Enum => {
+                let Some(()) = self.find(SyntaxKind::VARIANT_LIST) else {
+                    return Some(name);
+                };
+                self.enums.push((start, vis));
+                self.descend(SyntaxKind::VARIANT_LIST);
+                self.ascend();
+                self.enums.pop();
+            }
+            _ => {}
+        }
+        Some(name)
+    }
+
+    /// Define a `macro_rules!` macro, which is in textual scope for the rest of the enclosing
+    /// module or block and the modules declared there. With `#[macro_export]`, it can also be
+    /// named by path from the crate root. Its rules are compiled for expanding calls to it.
+    fn macro_rules(&mut self, name: Name, item: Item) {
+        let root = self.tree.paths.root_of(self.tree.scopes.module(self.scope));
+        let vis = Visibility::Restricted(root);
+        let macros = &mut self.tree.macros;
+        self.macros = Some(macros.define(self.macros, name, Item { vis, ..item }));
+        if self.has_attr(sym::MACRO_EXPORT) {
+            let parent = Parent::Scope(self.tree.modules[&root]);
+            let vis = Visibility::Public;
+            self.define(parent, Namespace::Macro, name, Item { vis, ..item });
+        }
+        if self.builtin_macro(name, item.node) {
+            return;
+        }
+        let mut body = self.record_with_docs();
+        if body
+            .last()
+            .is_some_and(|token| token.kind == SyntaxKind::SEMICOLON)
+        {
+            body.pop();
+        }
+        let loc = self.tree.nodes.loc(item.node);
+        let edition = self.tree.crates.edition(self.krate);
+        match MacroRules::parse(&self.tree.names, name, loc, root, &body, edition) {
+            Ok(rules) => self.tree.macros.set_rules(item.node, rules),
+            Err(diagnostic) => self.tree.diagnostics.push(diagnostic),
+        }
+    }
+
+    /// Check for `#[rustc_builtin_macro]` on a macro definition, whose body is only a placeholder,
+    /// and record which built-in macro it is if that is one that can be expanded.
+    fn builtin_macro(&mut self, name: Name, node: Node) -> bool {
+        if !self.has_attr(sym::RUSTC_BUILTIN_MACRO) {
+            return false;
+        }
+        if let Some(builtin) = BuiltinMacro::from_name(self.tree.names.get(name)) {
+            self.tree.macros.set_builtin(node, builtin);
+        }
+        true
+    }
+
+    /// Process nodes up to the field list of a struct or variant, returning whether it defines a
+    /// constructor in the value namespace, which tuple and unit ones do.
+    fn constructor(&mut self) -> bool {
+        loop {
+            match self.iterator.peek() {
+                Some(Step::Enter {
+                    kind: SyntaxKind::RECORD_FIELD_LIST,
+                }) => return false,
+                Some(Step::Enter {
+                    kind: SyntaxKind::TUPLE_FIELD_LIST,
+                })
+                | Some(Step::Exit)
+                | None => return true,
+                _ => self.node().unwrap(),
+            }
+        }
+    }
+
+    /// Process the rest of a struct, union or variant, collecting its fields, which are visible
+    /// as `vis` unless they say otherwise.
+    fn fields(&mut self, owner: Node, vis: Visibility) {
+        self.owners.push((owner, vis));
+        self.ascend();
+        self.owners.pop();
+    }
+
+    /// Process part of a [`SyntaxKind::RECORD_FIELD`] or [`SyntaxKind::TUPLE_FIELD`] node's
+    /// interior after its [`Step::Enter`].
+    fn field(&mut self, kind: SyntaxKind, start: Node) {
+        let Some(&(owner, default)) = self.owners.last() else {
+            return;
+        };
+        let vis = self.visibility().unwrap_or(default);
+        let name = match kind {
+            SyntaxKind::RECORD_FIELD => self.name(),
+            _ => None,
+        };
+        let fields = self.tree.items.fields(owner);
+        if let Some(prev) = fields
+            .iter()
+            .find(|field| name.is_some() && field.name == name)
+        {
+            let loc = self.tree.nodes.loc(start);
+            let prev_loc = self.tree.nodes.loc(prev.node);
+            let name = self.tree.names.get(name.unwrap());
+            self.tree.diagnostics.push(
+                Diagnostic::error(loc, format!("field `{name}` is already declared"))
+                    .code("E0124")
+                    .label(prev_loc, format!("`{name}` first declared here")),
+            );
+        }
+        let field = Field {
+            name,
+            node: start,
+            vis,
+        };
+        self.tree.items.push_field(owner, field);
+    }
+
+    /// Process part of a [`SyntaxKind::VARIANT`] node's interior after its [`Step::Enter`].
+    fn variant(&mut self, start: Node) {
+        let Some(&(parent, vis)) = self.enums.last() else {
+            return;
+        };
+        let Some(name) = self.name() else { return };
+        let item = Item {
+            kind: ItemKind::Variant,
+            node: start,
+            vis,
+        };
+        self.define(Parent::Enum(parent), Namespace::Type, name, item);
+        let constructor = self.constructor();
+        // Fields of variants are as visible as the enum.
+        self.fields(start, vis);
+        if constructor {
+            self.define(Parent::Enum(parent), Namespace::Value, name, item);
+        }
+    }
+
+    /// Record an item, reporting a conflict with any earlier definition.
+    fn define(&mut self, parent: Parent, ns: Namespace, name: Name, item: Item) {
+        let Some(prev) = self.tree.items.define(parent, ns, name, item) else {
+            return;
+        };
+        let loc = self.tree.nodes.loc(item.node);
+        let prev_loc = self.tree.nodes.loc(prev.node);
+        let prev_kind = match prev.kind {
+            ItemKind::Module => "module",
+            ItemKind::Trait => "trait",
+            _ => ns.descr(),
+        };
+        let container = match parent {
+            Parent::Scope(scope) => match self.tree.scopes.kind(scope) {
+                ScopeKind::Module => "module",
+                _ => "block",
+            },
+            Parent::Enum(_) => "enum",
+        };
+        let name = self.tree.names.get(name);
+        self.tree.diagnostics.push(
+            Diagnostic::error(loc, format!("the name `{name}` is defined multiple times"))
+                .code("E0428")
+                .label(
+                    prev_loc,
+                    format!("previous definition of the {prev_kind} `{name}` here"),
+                )
+                .note(format!(
+                    "`{name}` must be defined only once in the {} namespace of this {container}",
+                    ns.descr(),
+                )),
+        );
+    }
+
+    /// Process part of a [`SyntaxKind::USE`] node's interior after its [`Step::Enter`].
+    fn use_item(&mut self) {
+        let vis = self.visibility().unwrap_or(self.private());
+        let Some(()) = self.find(SyntaxKind::USE_TREE) else {
+            return;
+        };
+        let outer = self.recording.replace(Vec::new());
+        self.node().unwrap();
+        let tokens = self.finish_recording(outer);
+        // Syntax errors have already been reported by the parser.
+        let Some(leaves) = import::parse_use_tree(&self.tree.contexts, &tokens) else {
+            return;
+        };
+        for (path, kind) in leaves {
+            self.tree.imports.push(Import {
+                scope: self.scope,
+                vis,
+                path,
+                kind,
+                macros: self.macros,
+            });
+        }
+    }
+
+    /// Process part of a [`SyntaxKind::MACRO_CALL`] node's interior after its [`Step::Enter`].
+    /// A call in item or statement position can define `macro_rules!` macros, which are in
+    /// textual scope after it once it is expanded.
+    fn macro_call(&mut self, start: Node) {
+        let mut ancestors = self.stack.iter().rev().skip(1).map(|&(kind, _)| kind);
+        let assoc = match ancestors.clone().next() {
+            Some(SyntaxKind::ASSOC_ITEM_LIST) => true,
+            Some(SyntaxKind::MACRO_ITEMS) => self.assoc,
+            _ => false,
+        };
+        let root = match ancestors.next() {
+            Some(SyntaxKind::MACRO_EXPR) => match ancestors.next() {
+                Some(SyntaxKind::EXPR_STMT | SyntaxKind::STMT_LIST | SyntaxKind::MACRO_STMTS) => {
+                    SyntaxKind::MACRO_STMTS
+                }
+                _ => SyntaxKind::MACRO_EXPR,
+            },
+            Some(SyntaxKind::MACRO_TYPE) => SyntaxKind::MACRO_TYPE,
+            Some(SyntaxKind::MACRO_PAT) => SyntaxKind::MACRO_PAT,
+            Some(SyntaxKind::STMT_LIST | SyntaxKind::MACRO_STMTS) => SyntaxKind::MACRO_STMTS,
+            _ => SyntaxKind::MACRO_ITEMS,
+        };
+        let item = self.in_item_list();
+        let Some(Step::Enter {
+            kind: SyntaxKind::PATH,
+        }) = self.iterator.peek()
+        else {
+            if item {
+                self.tree.unexpanded.insert(self.scope);
+            }
+            return;
+        };
+        self.descend(SyntaxKind::PATH);
+        let tokens = self.record();
+        // Macro paths have the same syntax as `use` paths without braces or globs.
+        let leaf = import::parse_use_tree(&self.tree.contexts, &tokens)
+            .and_then(|mut leaves| leaves.pop());
+        let Some((path, ImportKind::Single(_))) = leaf else {
+            if item {
+                self.tree.unexpanded.insert(self.scope);
+            }
+            return;
+        };
+        // The rest is the `!` and the token tree, followed by a `;` in item and statement position.
+        let mut tokens = self.record_with_docs();
+        if tokens.last().map(|token| token.kind) == Some(SyntaxKind::SEMICOLON) {
+            tokens.pop();
+        }
+        let (input, close) = match &tokens[..] {
+            [_, open, input @ .., close]
+                if matches!(
+                    (open.kind, close.kind),
+                    (SyntaxKind::L_PAREN, SyntaxKind::R_PAREN)
+                        | (SyntaxKind::L_BRACK, SyntaxKind::R_BRACK)
+                        | (SyntaxKind::L_CURLY, SyntaxKind::R_CURLY)
+                ) =>
+            {
+                (input.to_vec(), close.loc)
+            }
+            _ => (Vec::new(), self.loc()),
+        };
+        if item {
+            *self.tree.expanding.entry(self.scope).or_default() += 1;
+        }
+        let textual = self.macros;
+        if let SyntaxKind::MACRO_ITEMS | SyntaxKind::MACRO_STMTS = root {
+            let macros = &mut self.tree.macros;
+            self.macros = Some(macros.expansion(self.macros, start));
+        }
+        let call = MacroCall {
+            node: start,
+            kind: MacroKind::Bang,
+            path,
+            scope: self.scope,
+            textual,
+        };
+        let site = CallSite {
+            root,
+            item,
+            assoc,
+            input,
+            close,
+            locals: self.locals,
+            generics: self.generics,
+            breakables: self.breakables.clone(),
+            depth: self.depth,
+            call_site: self.call_site.unwrap_or_else(|| self.tree.nodes.loc(start)),
+        };
+        if let (SyntaxKind::MACRO_STMTS, Some(&(Segment::Name(name), _))) = (root, call.path.last())
+        {
+            // The statements after the call can see the local variables its expansion binds.
+            let (ident, scope) = ((name, SyntaxContext::ROOT), self.scope);
+            let local =
+                self.tree
+                    .locals
+                    .bind(self.locals, ident, start, scope, LocalKind::Expansion);
+            self.locals = Some(local);
+        }
+        self.tree.pending_macro_calls.push((call, site));
+    }
+
+    /// Process part of a [`SyntaxKind::MODULE`] node's interior after its [`Step::Enter`].
+    fn module_decl(&mut self, start: Node) {
+        let body_pointer = start.plus(2);
+        let vis = self.visibility().unwrap_or(self.private());
+        let Some(name) = self.name() else { return };
+        let path_attr = self
+            .attrs()
+            .iter()
+            .filter(|attr| !attr.inner && attr.name() == Some(sym::PATH))
+            .find_map(|attr| {
+                let (value, loc) = attr.value(&self.tree.names)?;
+                let cfg_attr = attr.cfg_attr;
+                Some(PathAttr {
+                    value,
+                    loc,
+                    cfg_attr,
+                })
+            });
+        // The module's `macro_rules!` definitions stay in textual scope after it.
+        let macro_use = self.has_attr(sym::MACRO_USE);
+        let kind = ItemKind::Module;
+        let item = Item {
+            kind,
+            node: start,
+            vis,
+        };
+        self.define(Parent::Scope(self.scope), Namespace::Type, name, item);
+        let parent = self.tree.scopes.module(self.scope);
+        let path = self.tree.paths.child(parent, name);
+        match self.find(SyntaxKind::ITEM_LIST) {
+            Some(()) => {
+                let ModDir { dir, relative } = &self.tree.mod_dirs[&parent];
+                let mut dir = dir.clone();
+                if let Some(path_attr) = path_attr {
+                    // For inline modules, `#[path]` names a directory rather than a file.
+                    dir.push(path_attr.value);
+                } else {
+                    if let Some(relative) = *relative {
+                        dir.push(file_stem(self.tree.names.get(relative)));
+                    }
+                    dir.push(file_stem(self.tree.names.get(name)));
+                }
+                let mod_dir = ModDir {
+                    dir,
+                    relative: None,
+                };
+                self.tree.mod_dirs.insert(path, mod_dir);
+                let body = self.tree.nodes.next_index();
+                self.tree.nodes.write(body_pointer, body);
+                let (outer, start) = (Some(self.scope), self.scope_loc());
+                let scope = self.tree.scopes.push(ScopeKind::Module, path, outer, start);
+                self.tree.modules.insert(path, scope);
+                let (locals, generics) = (self.locals.take(), self.generics.take());
+                let macros = self.macros;
+                self.scope_node(scope);
+                (self.locals, self.generics) = (locals, generics);
+                if !macro_use {
+                    self.macros = macros;
+                }
+            }
+            None => {
+                self.tree.pending_mods.push(PendingMod {
+                    path,
+                    node: start,
+                    path_attr,
+                    macros: self.macros,
+                });
+                if macro_use {
+                    let macros = &mut self.tree.macros;
+                    self.macros = Some(macros.macro_use_module(self.macros, path));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+    };
+
+    use ra_ap_parser::{Edition, SyntaxKind};
+
+    use ra_ap_rustc_index::Idx;
+
+    use crate::{
+        cfg::CfgFlag,
+        encoding::{Decodable, Decoder},
+        hygiene::SyntaxContext,
+        import::Res,
+        item::{ItemKind, Namespace, Parent},
+        krate::{Crate, Extern},
+        local::PathRes,
+        name::{sym, Name},
+        path,
+        scope::{Scope, ScopeKind},
+        source::SourceLoc,
+        syntax::{has_pointer, is_token, Node, Tree},
+        sysroot::Sysroot,
+        vis::Visibility,
+    };
+
+    fn fixture(path: &str) -> String {
+        format!("{}/fixtures/{path}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    fn expand(fixture: &str) -> Tree {
+        expand_cfg(fixture, &[])
+    }
+
+    fn expand_cfg(path: &str, cfg: &[CfgFlag]) -> Tree {
+        let mut tree = Tree::new();
+        tree.add_crate("lib", Edition::Edition2021, cfg, &[], &fixture(path), &[])
+            .unwrap();
+        tree.expand().unwrap();
+        tree
+    }
+
+    fn errors(tree: &Tree) -> Vec<String> {
+        tree.diagnostics().map(|d| d.render(&tree.src)).collect()
+    }
+
+    /// Visit each node of the tree in order, along with the name and syntax context of each
+    /// identifier.
+    fn visit(tree: &Tree, mut f: impl FnMut(Node, SyntaxKind, Option<(Name, SyntaxContext)>)) {
+        let data = &tree.tree;
+        let mut decoder = Decoder::new(&data.nodes.data);
+        while !decoder.data().is_empty() {
+            let node = Node::new(data.nodes.data.len() - decoder.data().len());
+            let tag = u16::decode(&mut decoder);
+            if tag == u16::MAX {
+                continue;
+            }
+            let kind = SyntaxKind::from(tag);
+            let mut ident = None;
+            if is_token(kind) {
+                SourceLoc::decode(&mut decoder);
+                if kind == SyntaxKind::IDENT {
+                    let name = Name::decode(&mut decoder);
+                    ident = Some((name, SyntaxContext::decode(&mut decoder)));
+                }
+            } else if has_pointer(kind) {
+                Node::decode(&mut decoder);
+            }
+            f(node, kind, ident);
+        }
+    }
+
+    /// The resolution of each path and lifetime in a `lib.rs`, by line and column.
+    fn resolved(tree: &Tree) -> HashMap<String, String> {
+        let data = &tree.tree;
+        let describe = |node| {
+            let loc = tree.src.describe(data.nodes.loc(node));
+            loc.rsplit_once("lib.rs:").unwrap().1.to_owned()
+        };
+        let mut resolved = HashMap::new();
+        visit(tree, |node, kind, _| {
+            if is_token(kind) || has_pointer(kind) {
+                return;
+            }
+            if let Some(res) = data.locals.res(node) {
+                let res = match res {
+                    PathRes::Local(local) => describe(local),
+                    PathRes::Item(_) => "item".to_owned(),
+                    PathRes::Primitive => "primitive".to_owned(),
+                };
+                resolved.insert(describe(node), res);
+            }
+        });
+        resolved
+    }
+
+    #[test]
+    fn test_mod_rs() {
+        let tree = expand("modules/lib.rs");
+        assert!(errors(&tree).is_empty());
+        assert_eq!(tree.tree.modules.len(), 7);
+    }
+
+    #[test]
+    fn test_mod_file_errors() {
+        let tree = expand("ambiguous/lib.rs");
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("error[E0761]"));
+        assert!(errors[1].starts_with("error[E0583]"));
+        assert!(errors[1].contains("lib.rs:2:1"));
+    }
+
+    #[test]
+    fn test_path_attr() {
+        let tree = expand("path_attr/lib.rs");
+        assert!(errors(&tree).is_empty());
+        assert_eq!(tree.tree.modules.len(), 9);
+    }
+
+    #[test]
+    fn test_path_attr_reuse() {
+        let tree = expand("path_cycle/lib.rs");
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("is loaded as both `crate::b2` and `crate::b`"));
+        assert!(errors[1].contains("circular modules"));
+    }
+
+    #[test]
+    fn test_cfg() {
+        let cfg = ["unix".parse().unwrap(), "feature=\"a\"".parse().unwrap()];
+        let tree = expand_cfg("cfg/lib.rs", &cfg);
+        assert!(errors(&tree).is_empty());
+        // The modules whose bodies start with a false `#![cfg]` are left empty.
+        assert_eq!(tree.tree.modules.len(), 5);
+        assert!(tree.tree.macro_res.is_empty());
+    }
+
+    #[test]
+    fn test_cfg_attr() {
+        let cfg = ["unix".parse().unwrap()];
+        let tree = expand_cfg("cfg_attr/lib.rs", &cfg);
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("lib.rs:8:3: expanded from this `cfg_attr`"));
+        assert_eq!(tree.tree.modules.len(), 3);
+    }
+
+    #[test]
+    fn test_extern_crate() {
+        let mut tree = Tree::new();
+        let dep = tree
+            .add_crate(
+                "dep",
+                Edition::Edition2015,
+                &[],
+                &[],
+                &fixture("extern/dep.rs"),
+                &[],
+            )
+            .unwrap();
+        let dep = Extern::Crate(dep);
+        let deps = [("dep".to_owned(), dep), ("renamed".to_owned(), dep)];
+        let root = fixture("extern/main.rs");
+        tree.add_crate("lib", Edition::Edition2021, &[], &[], &root, &deps)
+            .unwrap();
+        tree.expand().unwrap();
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("error[E0463]: can't find crate for `missing`"));
+        // The same file can be a module in two different crates.
+        assert_eq!(tree.tree.modules.len(), 4);
+        assert_eq!(tree.tree.extern_crates.len(), 3);
+        let roots: HashSet<_> = tree.tree.extern_crates.values().collect();
+        assert_eq!(roots.len(), 2);
+    }
+
+    #[test]
+    fn test_syntax() {
+        let tree = expand("syntax/lib.rs");
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.contains("lib.rs:9:12")));
+    }
+
+    #[test]
+    fn test_sysroot() {
+        let mut tree = Tree::new();
+        let sysroot = Sysroot::new(std::path::Path::new(&fixture("sysroot"))).unwrap();
+        tree.add_sysroot(&sysroot, &[]).unwrap();
+        let no_std = tree
+            .add_crate(
+                "no_std",
+                Edition::Edition2021,
+                &[],
+                &[],
+                &fixture("no_std/lib.rs"),
+                &[],
+            )
+            .unwrap();
+        let with_std = tree
+            .add_crate(
+                "inner",
+                Edition::Edition2021,
+                &[],
+                &[],
+                &fixture("extern/inner.rs"),
+                &[],
+            )
+            .unwrap();
+        tree.expand().unwrap();
+        // `std` can't find `libc`, but that isn't the user's problem.
+        assert_eq!(tree.tree.diagnostics.len(), 1);
+        assert!(errors(&tree).is_empty());
+        assert_eq!(tree.tree.extern_crates.len(), 3);
+        let crates = &tree.tree.crates;
+        assert!(crates.dep(no_std, sym::CORE).is_some());
+        assert!(crates.dep(no_std, sym::STD).is_none());
+        assert_eq!(crates.dep(no_std, sym::PROC_MACRO), None);
+        assert_eq!(
+            crates.extern_crate(no_std, sym::PROC_MACRO),
+            Some(Extern::Opaque)
+        );
+        assert!(crates.dep(with_std, sym::STD).is_some());
+    }
+
+    #[test]
+    fn test_items() {
+        let mut tree = expand("items/lib.rs");
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("error[E0428]: the name `Unit` is defined multiple times"));
+        assert!(errors[0].contains("lib.rs:1:1: previous definition of the type `Unit` here"));
+        assert!(errors[1].contains("lib.rs:15:1: previous definition of the module `inner` here"));
+        assert!(errors[2].contains("in the value namespace of this block"));
+        let root = tree.tree.modules[&path::Path::new(0)];
+        let items = &tree.tree.items;
+        let mut get = |ns, name| items.get(Parent::Scope(root), ns, tree.tree.names.make(name));
+        let kinds = [
+            (Namespace::Type, "Unit", Some(ItemKind::Struct)),
+            (Namespace::Value, "Unit", Some(ItemKind::Struct)),
+            (Namespace::Value, "Record", None),
+            (Namespace::Value, "Tuple", Some(ItemKind::Struct)),
+            (Namespace::Value, "f", Some(ItemKind::Fn)),
+            (Namespace::Type, "f", Some(ItemKind::Module)),
+            (Namespace::Value, "ext", Some(ItemKind::Static)),
+            (Namespace::Value, "method", None),
+            (Namespace::Macro, "m", Some(ItemKind::MacroRules)),
+            (Namespace::Type, "me", Some(ItemKind::ExternCrate)),
+            (Namespace::Value, "local", None),
+        ];
+        for (ns, name, kind) in kinds {
+            assert_eq!(get(ns, name).map(|item| item.kind), kind, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_imports() {
+        let mut tree = expand("imports/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            "error[E0255]: the name `g` is defined multiple times",
+            "error[E0252]: the name `Twice` is defined multiple times",
+            "lib.rs:38:11: no `Missing` in `a::b`",
+            "lib.rs:39:5: use of undeclared crate or module `missing`",
+            "lib.rs:40:11: `S` is a struct, not a module",
+            "error[E0433]: failed to resolve: there are too many leading `super` keywords",
+            "error[E0659]: `S` is ambiguous",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.contains(expected), "{error}");
+        }
+        let data = &mut tree.tree;
+        let root = data.modules[&path::Path::new(0)];
+        let mut res = |ns, name| {
+            let binding = data.imports.binding(root, ns, data.names.make(name));
+            binding.map(|binding| match binding.res {
+                Res::Item(item) => Some(item.kind),
+                _ => None,
+            })
+        };
+        let kinds = [
+            (Namespace::Type, "b", Some(None)),
+            (Namespace::Value, "f", Some(Some(ItemKind::Fn))),
+            (Namespace::Type, "S", Some(Some(ItemKind::Struct))),
+            (Namespace::Value, "S", Some(Some(ItemKind::Struct))),
+            (Namespace::Value, "V", Some(Some(ItemKind::Variant))),
+            (Namespace::Type, "W", Some(Some(ItemKind::Variant))),
+            (Namespace::Value, "W", None),
+            (Namespace::Type, "H", Some(Some(ItemKind::Struct))),
+            (Namespace::Type, "U", Some(Some(ItemKind::Struct))),
+            (Namespace::Type, "Missing", Some(None)),
+            (Namespace::Type, "_", None),
+        ];
+        for (ns, name, kind) in kinds {
+            assert_eq!(res(ns, name), kind, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_imports_2015() {
+        let mut tree = Tree::new();
+        let root = fixture("imports/2015.rs");
+        tree.add_crate("lib", Edition::Edition2015, &[], &[], &root, &[])
+            .unwrap();
+        tree.expand().unwrap();
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 2, "{errors:#?}");
+        assert!(errors[0].contains("2015.rs:11:8: no `b` in `a`"));
+        // Only `std` is injected at the root, not `core`.
+        assert!(errors[1].contains("2015.rs:17:9: use of undeclared crate or module `core`"));
+    }
+
+    #[test]
+    fn test_privacy() {
+        let mut tree = expand("privacy/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            "error[E0124]: field `x` is already declared",
+            "error[E0742]: visibilities can only be restricted to ancestor modules",
+            "error[E0433]: failed to resolve: `super` in paths can only be used in start position",
+            "error[E0433]: failed to resolve: `Self` is not a module",
+            "lib.rs:31:11: private struct",
+            "lib.rs:32:11: private struct",
+            "error[E0603]: module `private` is private",
+            "lib.rs:37:8: no `SelfVis` in `a`",
+            "lib.rs:41:27: private field",
+            "lib.rs:42:31: private field",
+            "error[E0603]: module `inner` is private",
+            "error[E0603]: function `private` is private",
+            "lib.rs:64:12: private module",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.contains(expected), "{error}");
+        }
+        let data = &mut tree.tree;
+        let root = data.modules[&path::Path::new(0)];
+        let mut bound = |ns, name| {
+            let binding = data.imports.binding(root, ns, data.names.make(name));
+            binding.is_some_and(|binding| binding.res != Res::Opaque)
+        };
+        assert!(bound(Namespace::Type, "Crate"));
+        assert!(bound(Namespace::Type, "Hidden"));
+        assert!(bound(Namespace::Type, "Tuple"));
+        assert!(!bound(Namespace::Value, "Tuple"));
+        assert!(bound(Namespace::Value, "PubTuple"));
+        assert!(bound(Namespace::Type, "Crate2"));
+        let a = data
+            .paths
+            .get(path::Path::new(0), data.names.make("a"))
+            .unwrap();
+        let b = data.modules[&data.paths.get(a, data.names.make("b")).unwrap()];
+        let record = data.names.make("Record");
+        let record = data
+            .items
+            .get(Parent::Scope(b), Namespace::Type, record)
+            .unwrap();
+        let fields = data.items.fields(record.node);
+        let public: Vec<_> = fields.iter().map(|f| f.vis == Visibility::Public).collect();
+        assert_eq!(public, [true, false, false]);
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut tree = Tree::new();
+        let sysroot = Sysroot::new(std::path::Path::new(&fixture("sysroot"))).unwrap();
+        tree.add_sysroot(&sysroot, &[]).unwrap();
+        tree.add_crate(
+            "lib",
+            Edition::Edition2021,
+            &[],
+            &[],
+            &fixture("prelude/lib.rs"),
+            &[],
+        )
+        .unwrap();
+        tree.add_crate(
+            "no_std",
+            Edition::Edition2021,
+            &[],
+            &[],
+            &fixture("prelude/no_std.rs"),
+            &[],
+        )
+        .unwrap();
+        tree.expand().unwrap();
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 2, "{errors:#?}");
+        assert!(errors[0].contains("lib.rs:5:5: no external crate `TryFrom`"));
+        assert!(errors[1].contains("no_std.rs:4:5: no external crate `Vec`"));
+        let data = &mut tree.tree;
+        let lib = data.crates.root(Crate::new(3));
+        let m = data.paths.get(lib, data.names.make("m")).unwrap();
+        let mut res = |module, name| {
+            let scope = data.modules[&module];
+            let name = data.names.make(name);
+            data.imports
+                .binding(scope, Namespace::Type, name)
+                .unwrap()
+                .res
+        };
+        assert!(matches!(res(lib, "V"), Res::Item(item) if item.kind == ItemKind::Struct));
+        assert!(matches!(res(lib, "S"), Res::Item(item) if item.kind == ItemKind::Variant));
+        let alloc = data.crates.root(Crate::new(1));
+        assert_eq!(res(m, "a"), Res::Module(alloc));
+    }
+
+    #[test]
+    fn test_prelude_without_sysroot() {
+        for (edition, n) in [(Edition::Edition2018, 1), (Edition::Edition2021, 0)] {
+            let mut tree = Tree::new();
+            tree.add_crate("lib", edition, &[], &[], &fixture("prelude/lib.rs"), &[])
+                .unwrap();
+            tree.expand().unwrap();
+            let errors = errors(&tree);
+            assert_eq!(errors.len(), n, "{errors:#?}");
+        }
+        let tree = expand("prelude/no_std.rs");
+        assert_eq!(errors(&tree).len(), 1);
+    }
+
+    #[test]
+    fn test_locals() {
+        let tree = expand("locals/lib.rs");
+        let errors = errors(&tree);
+        let expected = ["5:13", "12:40", "14:5", "15:14", "18:9", "23:5", "24:31"];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.starts_with("error[E0425]: cannot find value"));
+            assert!(
+                error.contains(&format!("lib.rs:{expected}: not found")),
+                "{error}"
+            );
+        }
+        let resolved = resolved(&tree);
+        let expected = [
+            ("4:13", "3:6"),
+            ("7:21", "7:14"),
+            ("7:25", "4:9"),
+            ("7:29", "item"),
+            ("9:14", "9:9"),
+            ("10:14", "5:9"),
+            ("12:29", "12:17"),
+            ("13:32", "13:20"),
+            ("25:9", "3:17"),
+            ("25:13", "7:9"),
+            ("25:20", "item"),
+            ("32:9", "30:6"),
+            ("32:13", "31:12"),
+        ];
+        for (use_loc, binding) in expected {
+            assert_eq!(
+                resolved.get(use_loc).map(String::as_str),
+                Some(binding),
+                "{use_loc}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_generics() {
+        let tree = expand("generics/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("E0392", "5:15"),
+            ("E0392", "5:19"),
+            ("E0392", "12:16"),
+            ("E0412", "26:22"),
+            ("E0261", "26:29"),
+            ("E0405", "33:6"),
+            ("E0412", "35:23"),
+            ("E0261", "40:13"),
+            ("E0261", "44:19"),
+            ("E0405", "44:34"),
+            ("E0433", "46:39"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (code, loc)) in errors.iter().zip(expected) {
+            assert!(error.starts_with(&format!("error[{code}]")), "{error}");
+            assert!(error.contains(&format!("lib.rs:{loc}\n")), "{error}");
+        }
+        let resolved = resolved(&tree);
+        let expected = [
+            ("3:27", "3:16"),
+            ("3:30", "3:20"),
+            ("3:45", "3:23"),
+            ("17:38", "17:16"),
+            ("17:54", "16:15"),
+            ("20:18", "20:22"),
+            ("20:25", "item"),
+            ("20:33", "20:22"),
+            ("22:16", "20:6"),
+            ("25:17", "20:6"),
+            ("25:20", "20:10"),
+            ("26:32", "primitive"),
+            ("37:13", "35:11"),
+            ("37:20", "37:9"),
+            ("39:26", "39:17"),
+            ("39:37", "39:17"),
+            ("46:27", "46:11"),
+            ("48:16", "48:20"),
+            ("48:32", "48:36"),
+            ("50:35", "50:9"),
+            ("51:15", "50:9"),
+        ];
+        for (use_loc, binding) in expected {
+            assert_eq!(
+                resolved.get(use_loc).map(String::as_str),
+                Some(binding),
+                "{use_loc}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scopes() {
+        let tree = expand("scopes/lib.rs");
+        assert_eq!(errors(&tree), Vec::<String>::new());
+        let scopes = &tree.tree.scopes;
+        let describe = |scope: Scope| match scopes.kind(scope) {
+            ScopeKind::Label(name) => tree.tree.names.get(name).to_owned(),
+            kind => format!("{kind:?}"),
+        };
+        let text = fs::read_to_string(fixture("scopes/lib.rs")).unwrap();
+        let at = |needle: &str| -> Vec<String> {
+            let loc = SourceLoc::new(text.find(needle).unwrap());
+            let scope = scopes.at(loc).unwrap();
+            scopes.ancestors(scope).map(describe).collect()
+        };
+        assert_eq!(at("mod inner"), ["Module"]);
+        assert_eq!(at("pub fn f"), ["Module", "Module"]);
+        assert_eq!(at("x: T"), ["Generics", "Module", "Module"]);
+        assert_eq!(at("let g"), ["FnBody", "Generics", "Module", "Module"]);
+        assert_eq!(
+            at("y: u8"),
+            ["Closure", "FnBody", "Generics", "Module", "Module"]
+        );
+        assert_eq!(
+            at("let z"),
+            ["Block", "'outer", "Block", "Closure", "FnBody", "Generics", "Module", "Module",]
+        );
+        assert_eq!(at("\n\ntrait"), ["Module"]);
+        assert_eq!(at("fn m(&self) {}"), ["Trait", "Module"]);
+        assert_eq!(at("let w"), ["'block", "Block", "FnBody", "Impl", "Module"]);
+        assert_eq!(at("Fn("), ["Generics", "Generics", "Module"]);
+        // The bodies of the `e`s are pushed after the file, and are empty at the calls.
+        assert_eq!(at("let before"), ["FnBody", "Module"]);
+        assert_eq!(at("let b"), ["FnBody", "Module"]);
+        assert_eq!(at("m!();\n    let b"), ["FnBody", "Module"]);
+        let root = scopes.at(SourceLoc::new(0)).unwrap();
+        let children: Vec<_> = scopes.children(root).map(describe).collect();
+        assert_eq!(
+            children,
+            ["Module", "Trait", "Impl", "Generics", "FnBody", "FnBody"]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let tree = expand("macros/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("error[E0468]", "lib.rs:22:7"),
+            ("error:", "lib.rs:2:13"),
+            ("error[E0433]", "lib.rs:51:24"),
+            ("error:", "lib.rs:63:13"),
+            ("error:", "lib.rs:64:13"),
+            ("error:", "lib.rs:72:13"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (level, loc)) in errors.iter().zip(expected) {
+            assert!(error.starts_with(level), "{error}");
+            assert!(error.contains(&format!("{loc}\n")), "{error}");
+        }
+        let data = &tree.tree;
+        let describe = |node| {
+            let loc = tree.src.describe(data.nodes.loc(node));
+            loc.rsplit_once('/').unwrap().1.to_owned()
+        };
+        let resolved: HashMap<_, _> = data
+            .macro_res
+            .iter()
+            .map(|(&node, res)| {
+                let res = match res {
+                    Res::Item(item) => describe(item.node),
+                    res => format!("{res:?}"),
+                };
+                (describe(node), res)
+            })
+            .collect();
+        let expected = [
+            ("lib.rs:26:17", "lib.rs:5:1"),
+            ("lib.rs:50:17", "lib.rs:41:1"),
+            ("lib.rs:52:17", "lib.rs:5:1"),
+            ("lib.rs:60:13", "lib.rs:5:1"),
+            ("lib.rs:61:13", "lib.rs:13:1"),
+            ("lib.rs:62:13", "with_use.rs:1:1"),
+            ("lib.rs:65:13", "lib.rs:36:5"),
+            ("lib.rs:66:13", "lib.rs:57:5"),
+            ("lib.rs:67:13", "Opaque"),
+            ("lib.rs:68:13", "lib.rs:5:1"),
+            ("with_use.rs:6:13", "lib.rs:5:1"),
+            ("without_use.rs:6:13", "lib.rs:13:1"),
+        ];
+        assert_eq!(resolved.len(), expected.len(), "{resolved:#?}");
+        for (call, def) in expected {
+            assert_eq!(resolved.get(call).map(String::as_str), Some(def), "{call}");
+        }
+    }
+
+    #[test]
+    fn test_expansion() {
+        let tree = expand("expansion/lib.rs");
+        let errors = errors(&tree);
+        assert!(errors.is_empty(), "{errors:#?}");
+        let data = &tree.tree;
+        assert_eq!(data.modules.len(), 4);
+        let expanded = data
+            .macro_res
+            .keys()
+            .filter(|&&node| tree.expansion(node).is_some())
+            .count();
+        assert_eq!(expanded, 18);
+        let resolved = resolved(&tree);
+        let expected = [
+            ("69:27", "68:6"),
+            ("70:21", "68:6"),
+            ("71:5", "item"),
+            ("72:5", "item"),
+            ("74:14", "item"),
+            ("74:21", "item"),
+            ("74:28", "item"),
+            ("74:37", "item"),
+            ("74:44", "70:9"),
+        ];
+        for (use_loc, binding) in expected {
+            assert_eq!(
+                resolved.get(use_loc).map(String::as_str),
+                Some(binding),
+                "{use_loc}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hygiene() {
+        let tree = expand("hygiene/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("error[E0426]", "23:15"),
+            ("error[E0425]", "36:17"),
+            ("error[E0425]", "17:9"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (level, loc)) in errors.iter().zip(expected) {
+            assert!(error.starts_with(level), "{error}");
+            assert!(error.contains(&format!("lib.rs:{loc}\n")), "{error}");
+        }
+        let resolved = resolved(&tree);
+        let expected = [
+            ("4:14", "3:13"),
+            ("29:9", "33:6"),
+            ("34:21", "33:9"),
+            ("36:14", "35:11"),
+            ("42:5", "34:9"),
+            ("49:13", "46:9"),
+            ("53:14", "52:9"),
+        ];
+        for (use_loc, binding) in expected {
+            assert_eq!(
+                resolved.get(use_loc).map(String::as_str),
+                Some(binding),
+                "{use_loc}"
+            );
+        }
+        let data = &tree.tree;
+        let targets: Vec<_> = data
+            .targets
+            .iter()
+            .map(|(&jump, &target)| {
+                let describe = |node| tree.src.describe(data.nodes.loc(node));
+                (describe(jump), describe(target))
+            })
+            .collect();
+        assert_eq!(targets.len(), 1);
+        assert!(targets[0].0.ends_with("lib.rs:57:17"));
+        assert!(targets[0].1.ends_with("lib.rs:54:5"));
+    }
+
+    #[test]
+    fn test_dollar_crate() {
+        let mut tree = Tree::new();
+        let dep = tree
+            .add_crate(
+                "dep",
+                Edition::Edition2021,
+                &[],
+                &[],
+                &fixture("dollar_crate/dep/lib.rs"),
+                &[],
+            )
+            .unwrap();
+        let deps = [("dep".to_owned(), Extern::Crate(dep))];
+        let root = fixture("dollar_crate/lib.rs");
+        tree.add_crate("lib", Edition::Edition2021, &[], &[], &root, &deps)
+            .unwrap();
+        tree.expand().unwrap();
+        // `$crate` names the crate defining the macro even where the call's crate has a module of
+        // the same name, so privacy is checked in the defining crate too.
+        let errors = errors(&tree);
+        assert_eq!(errors.len(), 1, "{errors:#?}");
+        assert!(errors[0].starts_with("error[E0603]: function `private` is private"));
+        assert!(errors[0].contains("dep/lib.rs:37:21\n"));
+        let resolved = resolved(&tree);
+        for use_loc in ["8:17", "16:17", "25:17", "45:17"] {
+            assert_eq!(
+                resolved.get(use_loc).map(String::as_str),
+                Some("item"),
+                "{use_loc}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_builtin_macros() {
+        let tree = expand_cfg("builtin/lib.rs", &["unix".parse().unwrap()]);
+        let reported = errors(&tree);
+        let expected = [
+            ("cannot concatenate a byte string literal", "lib.rs:19:34"),
+            ("`line!` takes no arguments", "lib.rs:20:25"),
+            (
+                "macro requires a cfg-pattern as an argument",
+                "lib.rs:21:21",
+            ),
+            ("expected a literal", "lib.rs:22:28"),
+        ];
+        assert_eq!(reported.len(), expected.len(), "{reported:#?}");
+        for (error, (message, loc)) in reported.iter().zip(expected) {
+            assert!(error.contains(message) && error.contains(loc), "{error}");
+        }
+        let expanded = |tree: &Tree| {
+            let data = &tree.tree;
+            data.macro_res
+                .keys()
+                .filter(|&&node| tree.expansion(node).is_some())
+                .count()
+        };
+        assert_eq!(expanded(&tree), 9);
+
+        // With the standard library loaded, its `#[rustc_builtin_macro]` definitions are expanded.
+        let mut tree = Tree::new();
+        let sysroot = Sysroot::new(std::path::Path::new(&fixture("sysroot"))).unwrap();
+        tree.add_sysroot(&sysroot, &[]).unwrap();
+        let root = fixture("builtin/sysroot.rs");
+        tree.add_crate("sysroot", Edition::Edition2021, &[], &[], &root, &[])
+            .unwrap();
+        tree.expand().unwrap();
+        assert_eq!(errors(&tree), Vec::<String>::new());
+        assert_eq!(expanded(&tree), 3);
+    }
+
+    #[test]
+    fn test_derive() {
+        let tree = expand("derive/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("expected path to a trait", "lib.rs:55:19"),
+            ("malformed `derive` attribute input", "lib.rs:58:3"),
+            (
+                "cannot find derive macro `Missing` in this scope",
+                "lib.rs:55:10",
+            ),
+            ("this trait cannot be derived for unions", "lib.rs:45:10"),
+            ("no default declared", "lib.rs:50:10"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (message, loc)) in errors.iter().zip(expected) {
+            assert!(error.contains(message) && error.contains(loc), "{error}");
+        }
+        // Each derive that succeeds is expanded into an `impl` after its item, located at the
+        // derive's path.
+        let data = &tree.tree;
+        let describe = |node| {
+            let loc = tree.src.describe(data.nodes.loc(node));
+            loc.rsplit_once("lib.rs:").unwrap().1.to_owned()
+        };
+        let (mut adt, mut named, mut items) = (None, None, HashMap::new());
+        let (mut header, mut impls) = (None, Vec::new());
+        visit(&tree, |node, kind, ident| match (kind, ident) {
+            (SyntaxKind::STRUCT | SyntaxKind::ENUM | SyntaxKind::UNION, _) => adt = Some(node),
+            (SyntaxKind::NAME, _) => named = adt.take(),
+            (SyntaxKind::IMPL, _) => header = Some((node, Vec::new())),
+            (SyntaxKind::FOR_KW, _) => {
+                if let Some((_, names)) = &mut header {
+                    names.push("for");
+                }
+            }
+            (_, Some((name, _))) => {
+                let name = data.names.get(name);
+                if let Some(item) = named.take() {
+                    items.insert(name, item);
+                }
+                if let Some((node, names)) = &mut header {
+                    if names.last() == Some(&"for") {
+                        impls.push((names[names.len() - 2], name, *node));
+                        header = None;
+                    } else {
+                        names.push(name);
+                    }
+                }
+            }
+            _ => {}
+        });
+        let text = fs::read_to_string(fixture("derive/lib.rs")).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let mut expected = Vec::new();
+        let derives = [
+            (3, "Unit"),
+            (6, "Pair"),
+            (11, "Point"),
+            (19, "Shape"),
+            (27, "Never"),
+            (30, "Bits"),
+            (36, "Wrapper"),
+            (40, "Local"),
+        ];
+        for (line, item) in derives {
+            let attr = lines[line - 1];
+            let mut col = attr.find("derive(").unwrap() + "derive(".len();
+            let end = col + attr[col..].find(')').unwrap();
+            for path in attr[col..end].split(", ") {
+                let name = path.rsplit("::").next().unwrap();
+                expected.push((name, item, format!("{line}:{}", col + 1)));
+                col += path.len() + ", ".len();
+            }
+        }
+        assert_eq!(impls.len(), expected.len(), "{impls:?}");
+        for (name, item, loc) in expected {
+            let found = impls.iter().find(|&&(n, i, _)| (n, i) == (name, item));
+            let &(_, _, node) = found.unwrap_or_else(|| panic!("no `{name}` for `{item}`"));
+            assert_eq!(describe(node), loc, "`{name}` for `{item}`");
+            assert!(node.index() > items[item].index(), "`{name}` for `{item}`");
+        }
+        // The names that derives bind are kept apart from those of the items they copy.
+        let mut internals = 0;
+        visit(&tree, |_, _, ident| {
+            let Some((name, ctx)) = ident else { return };
+            let opaque = data.contexts.opaque(ctx) != SyntaxContext::ROOT;
+            match data.names.get(name) {
+                "__self_0" | "__arg1_0" | "__H" | "state" | "other" => {
+                    assert!(opaque);
+                    internals += 1;
+                }
+                "T" | "N" | "Point" | "Shape" => assert!(!opaque),
+                _ => {}
+            }
+        });
+        assert!(internals > 0);
+    }
+
+    #[test]
+    fn test_env() {
+        let mut tree = Tree::new();
+        let env = [
+            ("OUT_DIR".to_owned(), fixture("env/out")),
+            ("CARGO_PKG_NAME".to_owned(), "env".to_owned()),
+        ];
+        let root = fixture("env/lib.rs");
+        tree.add_crate("env", Edition::Edition2021, &[], &env, &root, &[])
+            .unwrap();
+        tree.expand().unwrap();
+        let errors = errors(&tree);
+        let expected = [
+            ("set `HOME` first", "lib.rs:8:22"),
+            (
+                "environment variable `MISSING` not defined at compile time",
+                "lib.rs:12:23",
+            ),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (message, loc)) in errors.iter().zip(expected) {
+            assert!(error.contains(message) && error.contains(loc), "{error}");
+        }
+        // The file under `OUT_DIR` is included, so the function called from `f` resolves.
+        let files: Vec<_> = tree.src.files().map(|file| tree.src.name(file)).collect();
+        assert_eq!(files.len(), 2, "{files:#?}");
+        assert!(files[1].ends_with("env/out/gen.rs"));
+    }
+
+    #[test]
+    fn test_include() {
+        let tree = expand("include/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("couldn't read", "lib.rs:13:36"),
+            ("couldn't read", "lib.rs:14:10"),
+            ("argument must be a string literal", "lib.rs:15:39"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (message, loc)) in errors.iter().zip(expected) {
+            assert!(error.contains(message) && error.contains(loc), "{error}");
+        }
+        assert!(errors[0].contains("missing.txt"));
+        // Both included files are loaded, the second relative to the first.
+        let files: Vec<_> = tree.src.files().map(|file| tree.src.name(file)).collect();
+        assert_eq!(files.len(), 4, "{files:#?}");
+        assert!(files[1].ends_with("include/inc/items.rs"));
+        assert!(files[2].ends_with("include/expr.rs"));
+        assert!(files[3].ends_with("include/inc/more.rs"));
+    }
+
+    #[test]
+    fn test_labels() {
+        let tree = expand("labels/lib.rs");
+        let errors = errors(&tree);
+        let expected = [
+            ("error[E0268]", "25:5"),
+            ("error[E0696]", "27:18"),
+            ("error[E0426]", "30:15"),
+            ("error[E0767]", "34:19"),
+            ("error[E0267]", "37:13"),
+            ("error[E0695]", "40:13"),
+            ("warning", "44:9"),
+            ("error[E0268]", "48:5"),
+            ("error[E0268]", "54:13"),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (level, loc)) in errors.iter().zip(expected) {
+            assert!(error.starts_with(level), "{error}");
+            assert!(error.contains(&format!("lib.rs:{loc}\n")), "{error}");
+        }
+        let data = &tree.tree;
+        let line = |loc: SourceLoc| {
+            let loc = tree.src.describe(loc);
+            let (line, _) = loc
+                .rsplit_once("lib.rs:")
+                .unwrap()
+                .1
+                .split_once(':')
+                .unwrap();
+            line.parse::<usize>().unwrap()
+        };
+        let mut targets: Vec<_> = data
+            .targets
+            .iter()
+            .map(|(&jump, &target)| (line(data.nodes.loc(jump)), line(data.nodes.loc(target))))
+            .collect();
+        targets.sort();
+        assert_eq!(
+            targets,
+            [(7, 4), (10, 3), (13, 3), (17, 15), (45, 44), (57, 56)]
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use ra_ap_parser::Edition;
+
+use crate::manifest::Manifest;
+
+/// The standard library crates, each of which can depend on the ones before it.
+pub const CRATES: [&str; 3] = ["core", "alloc", "std"];
+
+/// The sources of the standard library, from a sysroot's `rust-src` component.
+pub struct Sysroot {
+    library: PathBuf,
+}
+
+impl Sysroot {
+    pub fn new(sysroot: &Path) -> Result<Self, String> {
+        let library = sysroot.join("lib/rustlib/src/rust/library");
+        if !library.join("core").exists() {
+            return Err(format!(
+                "can't find the `rust-src` component in sysroot {sysroot:?}",
+            ));
+        }
+        Ok(Self { library })
+    }
+
+    /// Find the sysroot of the `rustc` on the `PATH`, if it has the `rust-src` component.
+    pub fn detect() -> Option<Self> {
+        let output = Command::new("rustc")
+            .args(["--print", "sysroot"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let sysroot = String::from_utf8(output.stdout).ok()?;
+        Self::new(Path::new(sysroot.trim())).ok()
+    }
+
+    /// The root file and edition of one of the [`CRATES`].
+    pub fn krate(&self, name: &str) -> (PathBuf, Edition) {
+        let dir = self.library.join(name);
+        match Manifest::load(&dir.join("Cargo.toml")) {
+            Ok(manifest) if manifest.lib().is_some() => {
+                let lib = manifest.lib().unwrap();
+                (lib.root.clone(), lib.edition)
+            }
+            // The manifest may rely on workspace inheritance, which isn't supported.
+            _ => (dir.join("src/lib.rs"), Edition::Edition2021),
+        }
+    }
+}
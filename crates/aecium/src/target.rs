@@ -0,0 +1,419 @@
+use std::fs;
+
+use crate::cfg::CfgFlag;
+
+/// A built-in target, mirroring `rustc --print cfg --target <triple>` without needing a toolchain.
+struct Preset {
+    triple: &'static str,
+    arch: &'static str,
+    os: &'static str,
+    env: &'static str,
+    vendor: &'static str,
+    abi: &'static str,
+    families: &'static [&'static str],
+    pointer_width: &'static str,
+    panic: &'static str,
+    features: &'static [&'static str],
+}
+
+const X86_64_FEATURES: &[&str] = &["fxsr", "sse", "sse2"];
+const X86_64_WINDOWS_FEATURES: &[&str] = &["cmpxchg16b", "fxsr", "sse", "sse2", "sse3"];
+const WASM_FEATURES: &[&str] = &[
+    "bulk-memory",
+    "multivalue",
+    "mutable-globals",
+    "nontrapping-fptoint",
+    "reference-types",
+    "sign-ext",
+];
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        triple: "x86_64-unknown-linux-gnu",
+        arch: "x86_64",
+        os: "linux",
+        env: "gnu",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: X86_64_FEATURES,
+    },
+    Preset {
+        triple: "x86_64-unknown-linux-musl",
+        arch: "x86_64",
+        os: "linux",
+        env: "musl",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["crt-static", "fxsr", "sse", "sse2"],
+    },
+    Preset {
+        triple: "i686-unknown-linux-gnu",
+        arch: "x86",
+        os: "linux",
+        env: "gnu",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "32",
+        panic: "unwind",
+        features: X86_64_FEATURES,
+    },
+    Preset {
+        triple: "aarch64-unknown-linux-gnu",
+        arch: "aarch64",
+        os: "linux",
+        env: "gnu",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["neon"],
+    },
+    Preset {
+        triple: "armv7-unknown-linux-gnueabihf",
+        arch: "arm",
+        os: "linux",
+        env: "gnu",
+        vendor: "unknown",
+        abi: "eabihf",
+        families: &["unix"],
+        pointer_width: "32",
+        panic: "unwind",
+        features: &[],
+    },
+    Preset {
+        triple: "x86_64-pc-windows-msvc",
+        arch: "x86_64",
+        os: "windows",
+        env: "msvc",
+        vendor: "pc",
+        abi: "",
+        families: &["windows"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: X86_64_WINDOWS_FEATURES,
+    },
+    Preset {
+        triple: "x86_64-pc-windows-gnu",
+        arch: "x86_64",
+        os: "windows",
+        env: "gnu",
+        vendor: "pc",
+        abi: "",
+        families: &["windows"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: X86_64_WINDOWS_FEATURES,
+    },
+    Preset {
+        triple: "i686-pc-windows-msvc",
+        arch: "x86",
+        os: "windows",
+        env: "msvc",
+        vendor: "pc",
+        abi: "",
+        families: &["windows"],
+        pointer_width: "32",
+        panic: "unwind",
+        features: X86_64_FEATURES,
+    },
+    Preset {
+        triple: "aarch64-pc-windows-msvc",
+        arch: "aarch64",
+        os: "windows",
+        env: "msvc",
+        vendor: "pc",
+        abi: "",
+        families: &["windows"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["neon"],
+    },
+    Preset {
+        triple: "x86_64-apple-darwin",
+        arch: "x86_64",
+        os: "macos",
+        env: "",
+        vendor: "apple",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &[
+            "cmpxchg16b",
+            "fxsr",
+            "sse",
+            "sse2",
+            "sse3",
+            "sse4.1",
+            "ssse3",
+        ],
+    },
+    Preset {
+        triple: "aarch64-apple-darwin",
+        arch: "aarch64",
+        os: "macos",
+        env: "",
+        vendor: "apple",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["aes", "crc", "lse", "neon", "sha2", "sha3"],
+    },
+    Preset {
+        triple: "aarch64-apple-ios",
+        arch: "aarch64",
+        os: "ios",
+        env: "",
+        vendor: "apple",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["aes", "neon", "sha2"],
+    },
+    Preset {
+        triple: "aarch64-linux-android",
+        arch: "aarch64",
+        os: "android",
+        env: "",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: &["neon"],
+    },
+    Preset {
+        triple: "x86_64-unknown-freebsd",
+        arch: "x86_64",
+        os: "freebsd",
+        env: "",
+        vendor: "unknown",
+        abi: "",
+        families: &["unix"],
+        pointer_width: "64",
+        panic: "unwind",
+        features: X86_64_FEATURES,
+    },
+    Preset {
+        triple: "wasm32-unknown-unknown",
+        arch: "wasm32",
+        os: "unknown",
+        env: "",
+        vendor: "unknown",
+        abi: "",
+        families: &["wasm"],
+        pointer_width: "32",
+        panic: "abort",
+        features: WASM_FEATURES,
+    },
+    Preset {
+        triple: "wasm32-wasip1",
+        arch: "wasm32",
+        os: "wasi",
+        env: "p1",
+        vendor: "unknown",
+        abi: "",
+        families: &["wasm"],
+        pointer_width: "32",
+        panic: "abort",
+        features: WASM_FEATURES,
+    },
+    Preset {
+        triple: "thumbv7em-none-eabihf",
+        arch: "arm",
+        os: "none",
+        env: "",
+        vendor: "unknown",
+        abi: "eabihf",
+        families: &[],
+        pointer_width: "32",
+        panic: "abort",
+        features: &[],
+    },
+];
+
+/// The properties of a compilation target that are visible through `cfg`.
+#[derive(Debug)]
+pub struct Target {
+    arch: String,
+    os: String,
+    env: String,
+    vendor: String,
+    abi: String,
+    families: Vec<String>,
+    pointer_width: String,
+    endian: String,
+    panic: String,
+    features: Vec<String>,
+}
+
+impl Target {
+    /// Look up a built-in target triple, or read a target spec if given a path to a JSON file.
+    pub fn load(target: &str) -> Result<Self, String> {
+        if target.ends_with(".json") {
+            let text = fs::read_to_string(target)
+                .map_err(|e| format!("failed to read target spec {target:?}: {e}"))?;
+            return Self::from_json(&text)
+                .map_err(|e| format!("invalid target spec {target:?}: {e}"));
+        }
+        Self::builtin(target).ok_or_else(|| {
+            let known: Vec<&str> = PRESETS.iter().map(|preset| preset.triple).collect();
+            format!(
+                "unknown target triple {target:?}; known targets are {}, or pass a path to a \
+                 JSON target spec",
+                known.join(", "),
+            )
+        })
+    }
+
+    fn builtin(triple: &str) -> Option<Self> {
+        let preset = PRESETS.iter().find(|preset| preset.triple == triple)?;
+        let strings = |xs: &[&str]| xs.iter().map(|&x| x.to_owned()).collect();
+        Some(Self {
+            arch: preset.arch.to_owned(),
+            os: preset.os.to_owned(),
+            env: preset.env.to_owned(),
+            vendor: preset.vendor.to_owned(),
+            abi: preset.abi.to_owned(),
+            families: strings(preset.families),
+            pointer_width: preset.pointer_width.to_owned(),
+            endian: "little".to_owned(),
+            panic: preset.panic.to_owned(),
+            features: strings(preset.features),
+        })
+    }
+
+    /// Read the `cfg`-relevant fields of a rustc JSON target spec, using rustc's defaults.
+    fn from_json(text: &str) -> Result<Self, String> {
+        let json: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let field = |key: &str, default: &str| -> Result<String, String> {
+            match json.get(key) {
+                None => Ok(default.to_owned()),
+                Some(serde_json::Value::String(s)) => Ok(s.clone()),
+                Some(serde_json::Value::Number(n)) => Ok(n.to_string()),
+                Some(_) => Err(format!("`{key}` must be a string")),
+            }
+        };
+        let arch = match json.get("arch") {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            _ => return Err("missing `arch`".to_owned()),
+        };
+        let pointer_width = match json.get("target-pointer-width") {
+            Some(_) => field("target-pointer-width", "")?,
+            None => return Err("missing `target-pointer-width`".to_owned()),
+        };
+        let families = match json.get("target-family") {
+            None => Vec::new(),
+            Some(serde_json::Value::String(s)) => vec![s.clone()],
+            Some(serde_json::Value::Array(xs)) => xs
+                .iter()
+                .map(|x| x.as_str().map(str::to_owned))
+                .collect::<Option<_>>()
+                .ok_or("`target-family` must be a list of strings")?,
+            Some(_) => return Err("`target-family` must be a list of strings".to_owned()),
+        };
+        let features = field("features", "")?
+            .split(',')
+            .filter_map(|feature| feature.strip_prefix('+'))
+            .map(str::to_owned)
+            .collect();
+        Ok(Self {
+            arch,
+            os: field("os", "none")?,
+            env: field("env", "")?,
+            vendor: field("vendor", "unknown")?,
+            abi: field("abi", "")?,
+            families,
+            pointer_width,
+            endian: field("target-endian", "little")?,
+            panic: field("panic-strategy", "unwind")?,
+            features,
+        })
+    }
+
+    /// The builtin `cfg` options for this target.
+    pub fn cfg(&self) -> Vec<CfgFlag> {
+        let flag = |key: &str, value: &str| CfgFlag {
+            key: key.to_owned(),
+            value: Some(value.to_owned()),
+        };
+        let mut flags = vec![
+            flag("target_arch", &self.arch),
+            flag("target_os", &self.os),
+            flag("target_env", &self.env),
+            flag("target_vendor", &self.vendor),
+            flag("target_abi", &self.abi),
+            flag("target_pointer_width", &self.pointer_width),
+            flag("target_endian", &self.endian),
+            flag("panic", &self.panic),
+        ];
+        for family in &self.families {
+            flags.push(flag("target_family", family));
+            if family == "unix" || family == "windows" {
+                flags.push(CfgFlag {
+                    key: family.clone(),
+                    value: None,
+                });
+            }
+        }
+        for feature in &self.features {
+            flags.push(flag("target_feature", feature));
+        }
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cfg::CfgFlag, target::Target};
+
+    fn has(flags: &[CfgFlag], flag: &str) -> bool {
+        flags.contains(&flag.parse().unwrap())
+    }
+
+    #[test]
+    fn test_builtin() {
+        let flags = Target::load("x86_64-pc-windows-msvc").unwrap().cfg();
+        assert!(has(&flags, "windows"));
+        assert!(has(&flags, r#"target_family="windows""#));
+        assert!(has(&flags, r#"target_os="windows""#));
+        assert!(has(&flags, r#"target_pointer_width="64""#));
+        assert!(has(&flags, r#"target_feature="sse2""#));
+        assert!(!has(&flags, "unix"));
+        // That's up to the profile, not the target.
+        assert!(!has(&flags, "debug_assertions"));
+        let flags = Target::load("wasm32-unknown-unknown").unwrap().cfg();
+        assert!(has(&flags, r#"panic="abort""#));
+        assert!(!has(&flags, "unix") && !has(&flags, "windows"));
+        assert!(Target::load("x86_64-unknown-nowhere").is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let spec = r#"{
+            "llvm-target": "riscv32imac-unknown-none-elf",
+            "arch": "riscv32",
+            "target-pointer-width": "32",
+            "target-family": ["unix"],
+            "features": "+m,+a,-d",
+            "panic-strategy": "abort"
+        }"#;
+        let flags = Target::from_json(spec).unwrap().cfg();
+        assert!(has(&flags, r#"target_arch="riscv32""#));
+        assert!(has(&flags, r#"target_os="none""#));
+        assert!(has(&flags, r#"target_feature="a""#));
+        assert!(!has(&flags, r#"target_feature="d""#));
+        assert!(has(&flags, "unix"));
+        assert!(Target::from_json(r#"{"arch": "x86"}"#).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use crate::path::{Path, Paths};
+
+/// Where an item can be named from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Visibility {
+    Public,
+    /// Only inside the given module and its descendants, which for a private item is the module
+    /// that defines it.
+    Restricted(Path),
+}
+
+impl Visibility {
+    pub fn is_accessible_from(self, module: Path, paths: &Paths) -> bool {
+        match self {
+            Visibility::Public => true,
+            Visibility::Restricted(ancestor) => paths.is_ancestor(ancestor, module),
+        }
+    }
+
+    /// The more restrictive of two visibilities, assuming one of them is at least as visible as
+    /// the other.
+    pub fn min(self, other: Visibility, paths: &Paths) -> Visibility {
+        match (self, other) {
+            (Visibility::Public, vis) | (vis, Visibility::Public) => vis,
+            (Visibility::Restricted(a), Visibility::Restricted(b)) => {
+                if paths.is_ancestor(a, b) {
+                    other
+                } else {
+                    self
+                }
+            }
+        }
+    }
+}